@@ -3,6 +3,11 @@ use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 
+// shared with the host, which reads the manifests again when it loads the plugins
+#[allow(dead_code)]
+#[path = "src/layer/manifest.rs"]
+mod manifest;
+
 /// Build script to include any wasm component binaries in the build.
 ///
 /// Add builtin_components.rs to your lib.rs or main.rs:
@@ -43,6 +48,7 @@ fn main() {
                         ext == "wasm"
                             && *path.file_stem().unwrap() != *this_root_crate
                             && wasmparser::Parser::is_component(&bytes)
+                            && has_valid_manifest(&path, &bytes)
                     })
                     .map(|_| path.to_path_buf())
             })
@@ -83,3 +89,18 @@ fn main() {
         eprintln!("Failed to write to {}: {}", dest_path.display(), e);
     }
 }
+
+/// Whether the component's manifest is valid, or it has none. Plugins with an invalid one
+/// are left out of the build with a warning.
+fn has_valid_manifest(path: &Path, bytes: &[u8]) -> bool {
+    match manifest::read(bytes) {
+        Ok(_) => true,
+        Err(e) => {
+            println!(
+                "cargo:warning=Leaving out {}, its manifest is invalid: {e}",
+                path.display()
+            );
+            false
+        }
+    }
+}
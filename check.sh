@@ -7,5 +7,6 @@ cargo check --quiet --workspace --all-features --lib --target wasm32-unknown-unk
 cargo fmt --all -- --check
 cargo clippy --quiet --workspace --all-targets --all-features --  -D warnings -W clippy::all
 cargo test --quiet --workspace --all-targets --all-features
+cargo test --quiet --lib --no-default-features --features runtime-wasmi
 cargo test --quiet --workspace --doc
 trunk build
@@ -2,6 +2,12 @@
 #[cfg_attr(rustfmt, rustfmt_skip)]
 mod bindings;
 
+/// Describes the plugin to the host, see `rdx::layer::manifest`
+#[link_section = "rdx-manifest"]
+#[used]
+static MANIFEST: [u8; include_bytes!("../manifest.json").len()] =
+    *include_bytes!("../manifest.json");
+
 mod reactor;
 use reactor::Reactor;
 
@@ -10,7 +16,7 @@ pub use block_on::{block_on, noop_waker};
 
 mod polling;
 
-use bindings::component::plugin::host::{emit, now, subscribe_duration};
+use bindings::component::plugin::host::{emit, now};
 use bindings::component::plugin::types::Event;
 use bindings::exports::component::plugin::run::Guest;
 use bindings::wasi::io::poll::{poll, Pollable};
@@ -26,6 +32,9 @@ impl Guest for Component {
         });
 
         r#"
+        // have the host call `ticker` every second
+            every("ticker", 1000);
+
         // call the system function `render` on the template with the ctx from scope
             render(`
                 <div>
@@ -45,18 +54,11 @@ impl Guest for Component {
         datetime.to_string()
     }
 
-    /// This function calls now() every second by
-    /// Only works in native, breaks in wasm
+    /// Updates the datetime. The host calls this every second, see `every` in `load`.
     fn ticker() {
-        block_on(|reactor| async move {
-            // we use sleep to wait for 1 second in between updates to datetime.
-            let pollable = subscribe_duration(1000);
-            reactor.wait_for(pollable).await;
-
-            emit(&Event {
-                name: "datetime".to_string(),
-                value: Self::datetime(),
-            });
+        emit(&Event {
+            name: "datetime".to_string(),
+            value: Self::datetime(),
         });
     }
 }
@@ -2,6 +2,12 @@
 #[cfg_attr(rustfmt, rustfmt_skip)]
 mod bindings;
 
+/// Describes the plugin to the host, see `rdx::layer::manifest`
+#[link_section = "rdx-manifest"]
+#[used]
+static MANIFEST: [u8; include_bytes!("../manifest.json").len()] =
+    *include_bytes!("../manifest.json");
+
 use bindings::component::plugin::host::{emit, random_byte};
 use bindings::component::plugin::types::Event;
 use bindings::exports::component::plugin::run::Guest;
@@ -67,9 +67,9 @@ fn gen_script() -> String {
     let list_todos = Division::builder()
         .with_rhai(format!(
             r#"
-    if todos().len() > 0 {{
-        // loop over get_record and display the key and optional result if it exists
-        todos().map(|todo| {{
+    // the list `add-todo` emitted last, so the plugin isn't called every frame
+    if is_def_var("todos") && todos.len() > 0 {{
+        todos.map(|todo| {{
             `{todo}`
         }})
         .reduce(|acc, s| acc + s, "")
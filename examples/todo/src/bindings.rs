@@ -1,19 +1,54 @@
-#[allow(dead_code)]
+// Generated by `wit-bindgen` 0.41.0. DO NOT EDIT!
+// Options used:
+//   * runtime_path: "wit_bindgen_rt"
+#[rustfmt::skip]
+#[allow(dead_code, clippy::all)]
 pub mod component {
-    #[allow(dead_code)]
     pub mod plugin {
-        #[allow(dead_code, clippy::all)]
+        #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
         pub mod types {
             #[used]
             #[doc(hidden)]
             static __FORCE_SECTION_REF: fn() = super::super::super::__link_custom_section_describing_imports;
             use super::super::super::_rt;
+            /// The value carried by an event.
+            #[derive(Clone)]
+            pub enum Value {
+                Boolean(bool),
+                Integer(i64),
+                Float(f64),
+                Text(_rt::String),
+                List(_rt::Vec<_rt::String>),
+                /// A JSON document, converted into a Rhai map or array.
+                Json(_rt::String),
+            }
+            impl ::core::fmt::Debug for Value {
+                fn fmt(
+                    &self,
+                    f: &mut ::core::fmt::Formatter<'_>,
+                ) -> ::core::fmt::Result {
+                    match self {
+                        Value::Boolean(e) => {
+                            f.debug_tuple("Value::Boolean").field(e).finish()
+                        }
+                        Value::Integer(e) => {
+                            f.debug_tuple("Value::Integer").field(e).finish()
+                        }
+                        Value::Float(e) => {
+                            f.debug_tuple("Value::Float").field(e).finish()
+                        }
+                        Value::Text(e) => f.debug_tuple("Value::Text").field(e).finish(),
+                        Value::List(e) => f.debug_tuple("Value::List").field(e).finish(),
+                        Value::Json(e) => f.debug_tuple("Value::Json").field(e).finish(),
+                    }
+                }
+            }
             /// The Event type.
             #[derive(Clone)]
             pub struct Event {
                 /// The variable name
                 pub name: _rt::String,
-                pub value: _rt::String,
+                pub value: Value,
             }
             impl ::core::fmt::Debug for Event {
                 fn fmt(
@@ -27,16 +62,18 @@ pub mod component {
                 }
             }
         }
-        #[allow(dead_code, clippy::all)]
+        #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
         pub mod host {
             #[used]
             #[doc(hidden)]
             static __FORCE_SECTION_REF: fn() = super::super::super::__link_custom_section_describing_imports;
+            use super::super::super::_rt;
             pub type Event = super::super::super::component::plugin::types::Event;
             #[allow(unused_unsafe, clippy::all)]
             /// emit an event.
-            pub fn emit(evt: &Event) {
+            pub fn emit(evt: &Event) -> () {
                 unsafe {
+                    let mut cleanup_list = _rt::Vec::new();
                     let super::super::super::component::plugin::types::Event {
                         name: name0,
                         value: value0,
@@ -44,32 +81,323 @@ pub mod component {
                     let vec1 = name0;
                     let ptr1 = vec1.as_ptr().cast::<u8>();
                     let len1 = vec1.len();
-                    let vec2 = value0;
-                    let ptr2 = vec2.as_ptr().cast::<u8>();
-                    let len2 = vec2.len();
+                    use super::super::super::component::plugin::types::Value as V6;
+                    let (result7_0, result7_1, result7_2) = match value0 {
+                        V6::Boolean(e) => {
+                            (
+                                0i32,
+                                ::core::mem::MaybeUninit::new(
+                                    i64::from(
+                                        match e {
+                                            true => 1,
+                                            false => 0,
+                                        },
+                                    ) as u64,
+                                ),
+                                0usize,
+                            )
+                        }
+                        V6::Integer(e) => {
+                            (
+                                1i32,
+                                ::core::mem::MaybeUninit::new(_rt::as_i64(e) as u64),
+                                0usize,
+                            )
+                        }
+                        V6::Float(e) => {
+                            (
+                                2i32,
+                                ::core::mem::MaybeUninit::new(
+                                    (_rt::as_f64(e)).to_bits() as i64 as u64,
+                                ),
+                                0usize,
+                            )
+                        }
+                        V6::Text(e) => {
+                            let vec2 = e;
+                            let ptr2 = vec2.as_ptr().cast::<u8>();
+                            let len2 = vec2.len();
+                            (
+                                3i32,
+                                {
+                                    let mut t = ::core::mem::MaybeUninit::<u64>::uninit();
+                                    t.as_mut_ptr().cast::<*mut u8>().write(ptr2.cast_mut());
+                                    t
+                                },
+                                len2,
+                            )
+                        }
+                        V6::List(e) => {
+                            let vec4 = e;
+                            let len4 = vec4.len();
+                            let layout4 = _rt::alloc::Layout::from_size_align_unchecked(
+                                vec4.len() * (2 * ::core::mem::size_of::<*const u8>()),
+                                ::core::mem::size_of::<*const u8>(),
+                            );
+                            let result4 = if layout4.size() != 0 {
+                                let ptr = _rt::alloc::alloc(layout4).cast::<u8>();
+                                if ptr.is_null() {
+                                    _rt::alloc::handle_alloc_error(layout4);
+                                }
+                                ptr
+                            } else {
+                                ::core::ptr::null_mut()
+                            };
+                            for (i, e) in vec4.into_iter().enumerate() {
+                                let base = result4
+                                    .add(i * (2 * ::core::mem::size_of::<*const u8>()));
+                                {
+                                    let vec3 = e;
+                                    let ptr3 = vec3.as_ptr().cast::<u8>();
+                                    let len3 = vec3.len();
+                                    *base
+                                        .add(::core::mem::size_of::<*const u8>())
+                                        .cast::<usize>() = len3;
+                                    *base.add(0).cast::<*mut u8>() = ptr3.cast_mut();
+                                }
+                            }
+                            cleanup_list.extend_from_slice(&[(result4, layout4)]);
+                            (
+                                4i32,
+                                {
+                                    let mut t = ::core::mem::MaybeUninit::<u64>::uninit();
+                                    t.as_mut_ptr().cast::<*mut u8>().write(result4);
+                                    t
+                                },
+                                len4,
+                            )
+                        }
+                        V6::Json(e) => {
+                            let vec5 = e;
+                            let ptr5 = vec5.as_ptr().cast::<u8>();
+                            let len5 = vec5.len();
+                            (
+                                5i32,
+                                {
+                                    let mut t = ::core::mem::MaybeUninit::<u64>::uninit();
+                                    t.as_mut_ptr().cast::<*mut u8>().write(ptr5.cast_mut());
+                                    t
+                                },
+                                len5,
+                            )
+                        }
+                    };
                     #[cfg(target_arch = "wasm32")]
                     #[link(wasm_import_module = "component:plugin/host")]
-                    extern "C" {
+                    unsafe extern "C" {
                         #[link_name = "emit"]
-                        fn wit_import(_: *mut u8, _: usize, _: *mut u8, _: usize);
+                        fn wit_import8(
+                            _: *mut u8,
+                            _: usize,
+                            _: i32,
+                            _: ::core::mem::MaybeUninit<u64>,
+                            _: usize,
+                        );
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import8(
+                        _: *mut u8,
+                        _: usize,
+                        _: i32,
+                        _: ::core::mem::MaybeUninit<u64>,
+                        _: usize,
+                    ) {
+                        unreachable!()
+                    }
+                    unsafe {
+                        wit_import8(
+                            ptr1.cast_mut(),
+                            len1,
+                            result7_0,
+                            result7_1,
+                            result7_2,
+                        )
+                    };
+                    for (ptr, layout) in cleanup_list {
+                        if layout.size() != 0 {
+                            _rt::alloc::dealloc(ptr.cast(), layout);
+                        }
+                    }
+                }
+            }
+        }
+        /// Persistent key/value storage, private to each plugin.
+        #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
+        pub mod storage {
+            #[used]
+            #[doc(hidden)]
+            static __FORCE_SECTION_REF: fn() = super::super::super::__link_custom_section_describing_imports;
+            use super::super::super::_rt;
+            #[allow(unused_unsafe, clippy::all)]
+            /// Returns the value stored under the key, if any.
+            pub fn get(key: &str) -> Option<_rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 3 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 3
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let vec0 = key;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let ptr1 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "component:plugin/storage")]
+                    unsafe extern "C" {
+                        #[link_name = "get"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import2(_: *mut u8, _: usize, _: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1) };
+                    let l3 = i32::from(*ptr1.add(0).cast::<u8>());
+                    let result7 = match l3 {
+                        0 => None,
+                        1 => {
+                            let e = {
+                                let l4 = *ptr1
+                                    .add(::core::mem::size_of::<*const u8>())
+                                    .cast::<*mut u8>();
+                                let l5 = *ptr1
+                                    .add(2 * ::core::mem::size_of::<*const u8>())
+                                    .cast::<usize>();
+                                let len6 = l5;
+                                let bytes6 = _rt::Vec::from_raw_parts(
+                                    l4.cast(),
+                                    len6,
+                                    len6,
+                                );
+                                _rt::string_lift(bytes6)
+                            };
+                            Some(e)
+                        }
+                        _ => _rt::invalid_enum_discriminant(),
+                    };
+                    result7
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// Stores the value under the key, replacing any previous value.
+            pub fn set(key: &str, value: &str) -> () {
+                unsafe {
+                    let vec0 = key;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    let vec1 = value;
+                    let ptr1 = vec1.as_ptr().cast::<u8>();
+                    let len1 = vec1.len();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "component:plugin/storage")]
+                    unsafe extern "C" {
+                        #[link_name = "set"]
+                        fn wit_import2(_: *mut u8, _: usize, _: *mut u8, _: usize);
                     }
                     #[cfg(not(target_arch = "wasm32"))]
-                    fn wit_import(_: *mut u8, _: usize, _: *mut u8, _: usize) {
+                    unsafe extern "C" fn wit_import2(
+                        _: *mut u8,
+                        _: usize,
+                        _: *mut u8,
+                        _: usize,
+                    ) {
                         unreachable!()
                     }
-                    wit_import(ptr1.cast_mut(), len1, ptr2.cast_mut(), len2);
+                    unsafe { wit_import2(ptr0.cast_mut(), len0, ptr1.cast_mut(), len1) };
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// Removes the key.
+            pub fn delete(key: &str) -> () {
+                unsafe {
+                    let vec0 = key;
+                    let ptr0 = vec0.as_ptr().cast::<u8>();
+                    let len0 = vec0.len();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "component:plugin/storage")]
+                    unsafe extern "C" {
+                        #[link_name = "delete"]
+                        fn wit_import1(_: *mut u8, _: usize);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: *mut u8, _: usize) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(ptr0.cast_mut(), len0) };
+                }
+            }
+            #[allow(unused_unsafe, clippy::all)]
+            /// Lists every key this plugin has stored.
+            pub fn list_keys() -> _rt::Vec<_rt::String> {
+                unsafe {
+                    #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                    #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                    struct RetArea(
+                        [::core::mem::MaybeUninit<
+                            u8,
+                        >; 2 * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let mut ret_area = RetArea(
+                        [::core::mem::MaybeUninit::uninit(); 2
+                            * ::core::mem::size_of::<*const u8>()],
+                    );
+                    let ptr0 = ret_area.0.as_mut_ptr().cast::<u8>();
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "component:plugin/storage")]
+                    unsafe extern "C" {
+                        #[link_name = "list-keys"]
+                        fn wit_import1(_: *mut u8);
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    unsafe extern "C" fn wit_import1(_: *mut u8) {
+                        unreachable!()
+                    }
+                    unsafe { wit_import1(ptr0) };
+                    let l2 = *ptr0.add(0).cast::<*mut u8>();
+                    let l3 = *ptr0
+                        .add(::core::mem::size_of::<*const u8>())
+                        .cast::<usize>();
+                    let base7 = l2;
+                    let len7 = l3;
+                    let mut result7 = _rt::Vec::with_capacity(len7);
+                    for i in 0..len7 {
+                        let base = base7
+                            .add(i * (2 * ::core::mem::size_of::<*const u8>()));
+                        let e7 = {
+                            let l4 = *base.add(0).cast::<*mut u8>();
+                            let l5 = *base
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
+                            let len6 = l5;
+                            let bytes6 = _rt::Vec::from_raw_parts(l4.cast(), len6, len6);
+                            _rt::string_lift(bytes6)
+                        };
+                        result7.push(e7);
+                    }
+                    _rt::cabi_dealloc(
+                        base7,
+                        len7 * (2 * ::core::mem::size_of::<*const u8>()),
+                        ::core::mem::size_of::<*const u8>(),
+                    );
+                    let result8 = result7;
+                    result8
                 }
             }
         }
     }
 }
-#[allow(dead_code)]
+#[rustfmt::skip]
+#[allow(dead_code, clippy::all)]
 pub mod exports {
-    #[allow(dead_code)]
     pub mod component {
-        #[allow(dead_code)]
         pub mod plugin {
-            #[allow(dead_code, clippy::all)]
+            #[allow(dead_code, async_fn_in_trait, unused_imports, clippy::all)]
             pub mod run {
                 #[used]
                 #[doc(hidden)]
@@ -80,12 +408,12 @@ pub mod exports {
                 pub unsafe fn _export_load_cabi<T: Guest>() -> *mut u8 {
                     #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
                     let result0 = T::load();
-                    let ptr1 = _RET_AREA.0.as_mut_ptr().cast::<u8>();
+                    let ptr1 = (&raw mut _RET_AREA.0).cast::<u8>();
                     let vec2 = (result0.into_bytes()).into_boxed_slice();
                     let ptr2 = vec2.as_ptr().cast::<u8>();
                     let len2 = vec2.len();
                     ::core::mem::forget(vec2);
-                    *ptr1.add(4).cast::<usize>() = len2;
+                    *ptr1.add(::core::mem::size_of::<*const u8>()).cast::<usize>() = len2;
                     *ptr1.add(0).cast::<*mut u8>() = ptr2.cast_mut();
                     ptr1
                 }
@@ -93,64 +421,13 @@ pub mod exports {
                 #[allow(non_snake_case)]
                 pub unsafe fn __post_return_load<T: Guest>(arg0: *mut u8) {
                     let l0 = *arg0.add(0).cast::<*mut u8>();
-                    let l1 = *arg0.add(4).cast::<usize>();
+                    let l1 = *arg0
+                        .add(::core::mem::size_of::<*const u8>())
+                        .cast::<usize>();
                     _rt::cabi_dealloc(l0, l1, 1);
                 }
                 #[doc(hidden)]
                 #[allow(non_snake_case)]
-                pub unsafe fn _export_register_cabi<T: Guest>() -> *mut u8 {
-                    #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
-                    let result0 = T::register();
-                    let ptr1 = _RET_AREA.0.as_mut_ptr().cast::<u8>();
-                    let vec3 = result0;
-                    let len3 = vec3.len();
-                    let layout3 = _rt::alloc::Layout::from_size_align_unchecked(
-                        vec3.len() * 8,
-                        4,
-                    );
-                    let result3 = if layout3.size() != 0 {
-                        let ptr = _rt::alloc::alloc(layout3).cast::<u8>();
-                        if ptr.is_null() {
-                            _rt::alloc::handle_alloc_error(layout3);
-                        }
-                        ptr
-                    } else {
-                        ::core::ptr::null_mut()
-                    };
-                    for (i, e) in vec3.into_iter().enumerate() {
-                        let base = result3.add(i * 8);
-                        {
-                            let vec2 = (e.into_bytes()).into_boxed_slice();
-                            let ptr2 = vec2.as_ptr().cast::<u8>();
-                            let len2 = vec2.len();
-                            ::core::mem::forget(vec2);
-                            *base.add(4).cast::<usize>() = len2;
-                            *base.add(0).cast::<*mut u8>() = ptr2.cast_mut();
-                        }
-                    }
-                    *ptr1.add(4).cast::<usize>() = len3;
-                    *ptr1.add(0).cast::<*mut u8>() = result3;
-                    ptr1
-                }
-                #[doc(hidden)]
-                #[allow(non_snake_case)]
-                pub unsafe fn __post_return_register<T: Guest>(arg0: *mut u8) {
-                    let l0 = *arg0.add(0).cast::<*mut u8>();
-                    let l1 = *arg0.add(4).cast::<usize>();
-                    let base4 = l0;
-                    let len4 = l1;
-                    for i in 0..len4 {
-                        let base = base4.add(i * 8);
-                        {
-                            let l2 = *base.add(0).cast::<*mut u8>();
-                            let l3 = *base.add(4).cast::<usize>();
-                            _rt::cabi_dealloc(l2, l3, 1);
-                        }
-                    }
-                    _rt::cabi_dealloc(base4, len4 * 8, 4);
-                }
-                #[doc(hidden)]
-                #[allow(non_snake_case)]
                 pub unsafe fn _export_add_todo_cabi<T: Guest>(
                     arg0: *mut u8,
                     arg1: usize,
@@ -165,12 +442,12 @@ pub mod exports {
                 pub unsafe fn _export_todos_cabi<T: Guest>() -> *mut u8 {
                     #[cfg(target_arch = "wasm32")] _rt::run_ctors_once();
                     let result0 = T::todos();
-                    let ptr1 = _RET_AREA.0.as_mut_ptr().cast::<u8>();
+                    let ptr1 = (&raw mut _RET_AREA.0).cast::<u8>();
                     let vec3 = result0;
                     let len3 = vec3.len();
                     let layout3 = _rt::alloc::Layout::from_size_align_unchecked(
-                        vec3.len() * 8,
-                        4,
+                        vec3.len() * (2 * ::core::mem::size_of::<*const u8>()),
+                        ::core::mem::size_of::<*const u8>(),
                     );
                     let result3 = if layout3.size() != 0 {
                         let ptr = _rt::alloc::alloc(layout3).cast::<u8>();
@@ -182,17 +459,20 @@ pub mod exports {
                         ::core::ptr::null_mut()
                     };
                     for (i, e) in vec3.into_iter().enumerate() {
-                        let base = result3.add(i * 8);
+                        let base = result3
+                            .add(i * (2 * ::core::mem::size_of::<*const u8>()));
                         {
                             let vec2 = (e.into_bytes()).into_boxed_slice();
                             let ptr2 = vec2.as_ptr().cast::<u8>();
                             let len2 = vec2.len();
                             ::core::mem::forget(vec2);
-                            *base.add(4).cast::<usize>() = len2;
+                            *base
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<usize>() = len2;
                             *base.add(0).cast::<*mut u8>() = ptr2.cast_mut();
                         }
                     }
-                    *ptr1.add(4).cast::<usize>() = len3;
+                    *ptr1.add(::core::mem::size_of::<*const u8>()).cast::<usize>() = len3;
                     *ptr1.add(0).cast::<*mut u8>() = result3;
                     ptr1
                 }
@@ -200,81 +480,121 @@ pub mod exports {
                 #[allow(non_snake_case)]
                 pub unsafe fn __post_return_todos<T: Guest>(arg0: *mut u8) {
                     let l0 = *arg0.add(0).cast::<*mut u8>();
-                    let l1 = *arg0.add(4).cast::<usize>();
+                    let l1 = *arg0
+                        .add(::core::mem::size_of::<*const u8>())
+                        .cast::<usize>();
                     let base4 = l0;
                     let len4 = l1;
                     for i in 0..len4 {
-                        let base = base4.add(i * 8);
+                        let base = base4
+                            .add(i * (2 * ::core::mem::size_of::<*const u8>()));
                         {
                             let l2 = *base.add(0).cast::<*mut u8>();
-                            let l3 = *base.add(4).cast::<usize>();
+                            let l3 = *base
+                                .add(::core::mem::size_of::<*const u8>())
+                                .cast::<usize>();
                             _rt::cabi_dealloc(l2, l3, 1);
                         }
                     }
-                    _rt::cabi_dealloc(base4, len4 * 8, 4);
+                    _rt::cabi_dealloc(
+                        base4,
+                        len4 * (2 * ::core::mem::size_of::<*const u8>()),
+                        ::core::mem::size_of::<*const u8>(),
+                    );
                 }
                 pub trait Guest {
-                    /// Returns the RDX script.
+                    /// Returns the RDX script, emitting the saved todos as `todos` first.
                     fn load() -> _rt::String;
-                    /// Register the todos() function with RDX
-                    fn register() -> _rt::Vec<_rt::String>;
-                    /// Increments the counter.
-                    fn add_todo(todo: _rt::String);
+                    /// Adds a todo, saves the list and emits it as `todos`.
+                    fn add_todo(todo: _rt::String) -> ();
                     /// Returns the current todos
                     fn todos() -> _rt::Vec<_rt::String>;
                 }
                 #[doc(hidden)]
                 macro_rules! __export_component_plugin_run_cabi {
                     ($ty:ident with_types_in $($path_to_types:tt)*) => {
-                        const _ : () = { #[export_name = "component:plugin/run#load"]
-                        unsafe extern "C" fn export_load() -> * mut u8 {
-                        $($path_to_types)*:: _export_load_cabi::<$ty > () } #[export_name
-                        = "cabi_post_component:plugin/run#load"] unsafe extern "C" fn
-                        _post_return_load(arg0 : * mut u8,) { $($path_to_types)*::
-                        __post_return_load::<$ty > (arg0) } #[export_name =
-                        "component:plugin/run#register"] unsafe extern "C" fn
-                        export_register() -> * mut u8 { $($path_to_types)*::
-                        _export_register_cabi::<$ty > () } #[export_name =
-                        "cabi_post_component:plugin/run#register"] unsafe extern "C" fn
-                        _post_return_register(arg0 : * mut u8,) { $($path_to_types)*::
-                        __post_return_register::<$ty > (arg0) } #[export_name =
-                        "component:plugin/run#add-todo"] unsafe extern "C" fn
-                        export_add_todo(arg0 : * mut u8, arg1 : usize,) {
-                        $($path_to_types)*:: _export_add_todo_cabi::<$ty > (arg0, arg1) }
-                        #[export_name = "component:plugin/run#todos"] unsafe extern "C"
-                        fn export_todos() -> * mut u8 { $($path_to_types)*::
-                        _export_todos_cabi::<$ty > () } #[export_name =
-                        "cabi_post_component:plugin/run#todos"] unsafe extern "C" fn
-                        _post_return_todos(arg0 : * mut u8,) { $($path_to_types)*::
-                        __post_return_todos::<$ty > (arg0) } };
+                        const _ : () = { #[unsafe (export_name =
+                        "component:plugin/run#load")] unsafe extern "C" fn export_load()
+                        -> * mut u8 { unsafe { $($path_to_types)*::
+                        _export_load_cabi::<$ty > () } } #[unsafe (export_name =
+                        "cabi_post_component:plugin/run#load")] unsafe extern "C" fn
+                        _post_return_load(arg0 : * mut u8,) { unsafe {
+                        $($path_to_types)*:: __post_return_load::<$ty > (arg0) } }
+                        #[unsafe (export_name = "component:plugin/run#add-todo")] unsafe
+                        extern "C" fn export_add_todo(arg0 : * mut u8, arg1 : usize,) {
+                        unsafe { $($path_to_types)*:: _export_add_todo_cabi::<$ty >
+                        (arg0, arg1) } } #[unsafe (export_name =
+                        "component:plugin/run#todos")] unsafe extern "C" fn
+                        export_todos() -> * mut u8 { unsafe { $($path_to_types)*::
+                        _export_todos_cabi::<$ty > () } } #[unsafe (export_name =
+                        "cabi_post_component:plugin/run#todos")] unsafe extern "C" fn
+                        _post_return_todos(arg0 : * mut u8,) { unsafe {
+                        $($path_to_types)*:: __post_return_todos::<$ty > (arg0) } } };
                     };
                 }
                 #[doc(hidden)]
                 pub(crate) use __export_component_plugin_run_cabi;
-                #[repr(align(4))]
-                struct _RetArea([::core::mem::MaybeUninit<u8>; 8]);
+                #[cfg_attr(target_pointer_width = "64", repr(align(8)))]
+                #[cfg_attr(target_pointer_width = "32", repr(align(4)))]
+                struct _RetArea(
+                    [::core::mem::MaybeUninit<
+                        u8,
+                    >; 2 * ::core::mem::size_of::<*const u8>()],
+                );
                 static mut _RET_AREA: _RetArea = _RetArea(
-                    [::core::mem::MaybeUninit::uninit(); 8],
+                    [::core::mem::MaybeUninit::uninit(); 2
+                        * ::core::mem::size_of::<*const u8>()],
                 );
             }
         }
     }
 }
+#[rustfmt::skip]
 mod _rt {
+    #![allow(dead_code, clippy::all)]
     pub use alloc_crate::string::String;
-    #[cfg(target_arch = "wasm32")]
-    pub fn run_ctors_once() {
-        wit_bindgen_rt::run_ctors_once();
+    pub use alloc_crate::vec::Vec;
+    pub fn as_i64<T: AsI64>(t: T) -> i64 {
+        t.as_i64()
     }
-    pub unsafe fn cabi_dealloc(ptr: *mut u8, size: usize, align: usize) {
-        if size == 0 {
-            return;
+    pub trait AsI64 {
+        fn as_i64(self) -> i64;
+    }
+    impl<'a, T: Copy + AsI64> AsI64 for &'a T {
+        fn as_i64(self) -> i64 {
+            (*self).as_i64()
+        }
+    }
+    impl AsI64 for i64 {
+        #[inline]
+        fn as_i64(self) -> i64 {
+            self as i64
+        }
+    }
+    impl AsI64 for u64 {
+        #[inline]
+        fn as_i64(self) -> i64 {
+            self as i64
+        }
+    }
+    pub fn as_f64<T: AsF64>(t: T) -> f64 {
+        t.as_f64()
+    }
+    pub trait AsF64 {
+        fn as_f64(self) -> f64;
+    }
+    impl<'a, T: Copy + AsF64> AsF64 for &'a T {
+        fn as_f64(self) -> f64 {
+            (*self).as_f64()
+        }
+    }
+    impl AsF64 for f64 {
+        #[inline]
+        fn as_f64(self) -> f64 {
+            self as f64
         }
-        let layout = alloc::Layout::from_size_align_unchecked(size, align);
-        alloc::dealloc(ptr, layout);
     }
     pub use alloc_crate::alloc;
-    pub use alloc_crate::vec::Vec;
     pub unsafe fn string_lift(bytes: Vec<u8>) -> String {
         if cfg!(debug_assertions) {
             String::from_utf8(bytes).unwrap()
@@ -282,10 +602,28 @@ mod _rt {
             String::from_utf8_unchecked(bytes)
         }
     }
+    pub unsafe fn invalid_enum_discriminant<T>() -> T {
+        if cfg!(debug_assertions) {
+            panic!("invalid enum discriminant")
+        } else {
+            unsafe { core::hint::unreachable_unchecked() }
+        }
+    }
+    pub unsafe fn cabi_dealloc(ptr: *mut u8, size: usize, align: usize) {
+        if size == 0 {
+            return;
+        }
+        let layout = alloc::Layout::from_size_align_unchecked(size, align);
+        alloc::dealloc(ptr, layout);
+    }
+    #[cfg(target_arch = "wasm32")]
+    pub fn run_ctors_once() {
+        wit_bindgen_rt::run_ctors_once();
+    }
     extern crate alloc as alloc_crate;
 }
-/// Generates `#[no_mangle]` functions to export the specified type as the
-/// root implementation of all generated traits.
+/// Generates `#[unsafe(no_mangle)]` functions to export the specified type as
+/// the root implementation of all generated traits.
 ///
 /// For more information see the documentation of `wit_bindgen::generate!`.
 ///
@@ -315,19 +653,26 @@ macro_rules! __export_plugin_world_impl {
 #[doc(inline)]
 pub(crate) use __export_plugin_world_impl as export;
 #[cfg(target_arch = "wasm32")]
-#[link_section = "component-type:wit-bindgen:0.35.0:component:plugin:plugin-world:encoded world"]
+#[unsafe(
+    link_section = "component-type:wit-bindgen:0.41.0:component:plugin:plugin-world:encoded world"
+)]
 #[doc(hidden)]
-pub static __WIT_BINDGEN_COMPONENT_TYPE: [u8; 410] = *b"\
-\0asm\x0d\0\x01\0\0\x19\x16wit-component-encoding\x04\0\x07\x97\x02\x01A\x02\x01\
-A\x08\x01B\x02\x01r\x02\x04names\x05values\x04\0\x05event\x03\0\0\x03\0\x16compo\
-nent:plugin/types\x05\0\x02\x03\0\0\x05event\x03\0\x05event\x03\0\x01\x01B\x04\x02\
-\x03\x02\x01\x01\x04\0\x05event\x03\0\0\x01@\x01\x03evt\x01\x01\0\x04\0\x04emit\x01\
-\x02\x03\0\x15component:plugin/host\x05\x03\x01B\x08\x01@\0\0s\x04\0\x04load\x01\
-\0\x01ps\x01@\0\0\x01\x04\0\x08register\x01\x02\x01@\x01\x04todos\x01\0\x04\0\x08\
-add-todo\x01\x03\x04\0\x05todos\x01\x02\x04\0\x14component:plugin/run\x05\x04\x04\
-\0\x1dcomponent:plugin/plugin-world\x04\0\x0b\x12\x01\0\x0cplugin-world\x03\0\0\0\
-G\x09producers\x01\x0cprocessed-by\x02\x0dwit-component\x070.220.0\x10wit-bindge\
-n-rust\x060.35.0";
+#[allow(clippy::octal_escapes)]
+pub static __WIT_BINDGEN_COMPONENT_TYPE: [u8; 590] = *b"\
+\0asm\x0d\0\x01\0\0\x19\x16wit-component-encoding\x04\0\x07\xcb\x03\x01A\x02\x01\
+A\x0a\x01B\x05\x01ps\x01q\x06\x07boolean\x01\x7f\0\x07integer\x01x\0\x05float\x01\
+u\0\x04text\x01s\0\x04list\x01\0\0\x04json\x01s\0\x04\0\x05value\x03\0\x01\x01r\x02\
+\x04names\x05value\x02\x04\0\x05event\x03\0\x03\x03\0\x16component:plugin/types\x05\
+\0\x02\x03\0\0\x05event\x03\0\x05event\x03\0\x01\x01B\x04\x02\x03\x02\x01\x01\x04\
+\0\x05event\x03\0\0\x01@\x01\x03evt\x01\x01\0\x04\0\x04emit\x01\x02\x03\0\x15com\
+ponent:plugin/host\x05\x03\x01B\x0a\x01ks\x01@\x01\x03keys\0\0\x04\0\x03get\x01\x01\
+\x01@\x02\x03keys\x05values\x01\0\x04\0\x03set\x01\x02\x01@\x01\x03keys\x01\0\x04\
+\0\x06delete\x01\x03\x01ps\x01@\0\0\x04\x04\0\x09list-keys\x01\x05\x03\0\x18comp\
+onent:plugin/storage\x05\x04\x01B\x07\x01@\0\0s\x04\0\x04load\x01\0\x01@\x01\x04\
+todos\x01\0\x04\0\x08add-todo\x01\x01\x01ps\x01@\0\0\x02\x04\0\x05todos\x01\x03\x04\
+\0\x14component:plugin/run\x05\x05\x04\0\x1dcomponent:plugin/plugin-world\x04\0\x0b\
+\x12\x01\0\x0cplugin-world\x03\0\0\0G\x09producers\x01\x0cprocessed-by\x02\x0dwi\
+t-component\x070.227.1\x10wit-bindgen-rust\x060.41.0";
 #[inline(never)]
 #[doc(hidden)]
 pub fn __link_custom_section_describing_imports() {
@@ -2,37 +2,57 @@
 #[cfg_attr(rustfmt, rustfmt_skip)]
 mod bindings;
 
+/// Describes the plugin to the host, see `rdx::layer::manifest`
+#[link_section = "rdx-manifest"]
+#[used]
+static MANIFEST: [u8; include_bytes!("../manifest.json").len()] =
+    *include_bytes!("../manifest.json");
+
+use bindings::component::plugin::host::emit;
+use bindings::component::plugin::storage;
+use bindings::component::plugin::types::{Event, Value};
 use bindings::exports::component::plugin::run::Guest;
 
-use std::sync::{LazyLock, Mutex};
+/// The storage key the todos are saved under, one per line, as they come from a single line
+/// input
+const KEY: &str = "todos";
 
-static TODOS: LazyLock<Mutex<Vec<String>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+/// The todos saved in the host's storage, so they outlive the plugin
+fn saved() -> Vec<String> {
+    storage::get(KEY)
+        .map(|todos| todos.lines().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+/// Emits the list as `todos`, so the script renders it from the scope rather than calling
+/// `todos()` each frame
+fn emit_todos(todos: Vec<String>) {
+    emit(&Event {
+        name: "todos".to_string(),
+        value: Value::List(todos),
+    });
+}
 
 struct Component;
 
 impl Guest for Component {
     fn load() -> String {
+        let todos = saved();
+        if !todos.is_empty() {
+            emit_todos(todos);
+        }
         include_str!(concat!(env!("OUT_DIR"), "/todo.rhai")).to_string()
     }
 
     fn add_todo(todo: String) {
-        let mut todos = TODOS.lock().unwrap();
+        let mut todos = saved();
         todos.push(todo);
+        storage::set(KEY, &todos.join("\n"));
+        emit_todos(todos);
     }
 
     fn todos() -> Vec<String> {
-        let todos = TODOS.lock().unwrap();
-        let t: Vec<String> = todos.iter().cloned().collect();
-        t
-    }
-
-    fn register() -> Vec<String> {
-        // This function will be available to us in Rhai.
-        // It can only return Option, Bool, Array, String, or numbers.
-        // No records (structs), enums (variants) as they are named,
-        // and the host has no idea what your names are.
-        // TODO: Grab names from the *.wasm wit to overcome this?
-        vec!["todos".to_string()]
+        saved()
     }
 }
 
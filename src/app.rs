@@ -1,10 +1,35 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use egui::ScrollArea;
 
+use crate::layer::capability::{Capabilities, Capability};
+use crate::layer::manifest::Manifest;
+use crate::layer::signature::{SignaturePolicy, TrustedKeys};
+use crate::layer::storage::{EframeStorage, StorageBackend};
+use crate::rdx::{PluginPolicy, PluginStatus, RdxConfig};
 use crate::RdxApp;
 
 /// Our app key
 const APP_KEY: &str = "rdx_app";
 
+/// The app name given to [eframe::run_native], which also names the native storage directory
+#[cfg(not(target_arch = "wasm32"))]
+const APP_NAME: &str = "RDX Playground";
+
+/// The capabilities granted to each builtin plugin, by file name. Every other plugin is granted
+/// none, so it can only use the imports that need none.
+const BUILTIN_GRANTS: [(&str, &[Capability]); 5] = [
+    ("counter.wasm", &[Capability::Emit]),
+    ("datetime.wasm", &[Capability::Emit, Capability::Clock]),
+    ("login.wasm", &[Capability::Emit]),
+    ("random.wasm", &[Capability::Emit, Capability::Random]),
+    ("todo.wasm", &[Capability::Emit, Capability::Storage]),
+];
+
+/// How long each frame may spend loading plugins, see [RdxApp::load_pending]
+const PLUGIN_LOAD_BUDGET: crate::layer::Duration = crate::layer::Duration::from_millis(50);
+
 /// Left Panel State
 #[derive(serde::Deserialize, serde::Serialize)]
 struct LeftPanelState {
@@ -31,6 +56,10 @@ pub struct TemplateApp {
     #[serde(skip)]
     rdx: RdxApp,
 
+    /// Plugin storage kept in the [eframe::Storage], when there is no storage directory
+    #[serde(skip)]
+    plugin_storage: Option<Arc<EframeStorage>>,
+
     split_state: LeftPanelState,
 }
 
@@ -41,6 +70,7 @@ impl Default for TemplateApp {
             label: "Hello World!".to_owned(),
             value: 2.7,
             rdx: RdxApp::default(),
+            plugin_storage: None,
             split_state: LeftPanelState::default(),
         }
     }
@@ -52,23 +82,154 @@ impl TemplateApp {
         // This is also where you can customize the look and feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
 
+        let (storage, plugin_storage) = plugin_storage(cc);
+        let (trusted_keys, signatures) = trust();
+        let config = RdxConfig {
+            storage,
+            policies: builtin_policies(),
+            trusted_keys,
+            signatures,
+            #[cfg(not(target_arch = "wasm32"))]
+            plugin_dir: plugin_dir(),
+            #[cfg(not(target_arch = "wasm32"))]
+            cache_dir: eframe::storage_dir(APP_NAME).map(|dir| dir.join("cache")),
+            ..Default::default()
+        };
+        let ctx = Some(cc.egui_ctx.clone());
+        let rdx = RdxApp::with_config(ctx.clone(), config.clone()).unwrap_or_else(|e| {
+            tracing::error!("Failed to load plugins: {}", e);
+            // fall back to the builtin plugins
+            let config = RdxConfig {
+                #[cfg(not(target_arch = "wasm32"))]
+                plugin_dir: None,
+                ..config
+            };
+            RdxApp::with_config(ctx, config).unwrap_or_default()
+        });
+
         // Load previous app state (if any).
         // Note that you must enable the `persistence` feature for this to work.
         if let Some(storage) = cc.storage {
             tracing::debug!("Loading previous app state");
-            return eframe::get_value(storage, APP_KEY).unwrap_or_default();
+            let app: Self = eframe::get_value(storage, APP_KEY).unwrap_or_default();
+            return Self {
+                rdx,
+                plugin_storage,
+                ..app
+            };
         }
 
         Self {
-            rdx: RdxApp::new(Some(cc.egui_ctx.clone())),
+            rdx,
+            plugin_storage,
             ..Default::default()
         }
     }
 }
 
+/// The [PluginPolicy] of each builtin plugin, granting it what [BUILTIN_GRANTS] lists.
+fn builtin_policies() -> HashMap<String, PluginPolicy> {
+    BUILTIN_GRANTS
+        .iter()
+        .map(|(name, grants)| {
+            let policy = PluginPolicy {
+                capabilities: grants.iter().copied().collect::<Capabilities>(),
+                ..Default::default()
+            };
+            (name.to_string(), policy)
+        })
+        .collect()
+}
+
+/// Picks where plugins persist their key/value pairs.
+///
+/// Native builds use a directory of files next to the app's own storage. Otherwise the entries
+/// are kept in the [eframe::Storage], which is also returned so it can be saved on shutdown.
+fn plugin_storage(
+    cc: &eframe::CreationContext<'_>,
+) -> (Arc<dyn StorageBackend>, Option<Arc<EframeStorage>>) {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(dir) = eframe::storage_dir(APP_NAME) {
+        let storage = crate::layer::storage::FileStorage::new(dir.join("plugins"));
+        return (Arc::new(storage), None);
+    }
+
+    let storage = Arc::new(EframeStorage::load(cc.storage));
+    (storage.clone(), Some(storage))
+}
+
+/// Whose plugins to trust: the keys listed in `$RDX_TRUSTED_KEYS`, else in `trusted_keys` in
+/// the app's storage directory, in the format [TrustedKeys::parse] reads. `$RDX_SIGNATURES`
+/// picks the [SignaturePolicy], `require`, `warn` or `allow`, and defaults to `warn`.
+fn trust() -> (TrustedKeys, SignaturePolicy) {
+    let signatures = std::env::var("RDX_SIGNATURES")
+        .ok()
+        .and_then(|policy| {
+            policy
+                .parse()
+                .inspect_err(|e| tracing::error!("Ignoring RDX_SIGNATURES: {}", e))
+                .ok()
+        })
+        .unwrap_or_default();
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let keys = std::env::var_os("RDX_TRUSTED_KEYS")
+        .map(std::path::PathBuf::from)
+        .or_else(|| eframe::storage_dir(APP_NAME).map(|dir| dir.join("trusted_keys")))
+        .and_then(|path| {
+            let text = std::fs::read_to_string(&path).ok()?;
+            TrustedKeys::parse(&text)
+                .inspect_err(|e| tracing::error!("Failed to read {}: {}", path.display(), e))
+                .ok()
+        })
+        .unwrap_or_default();
+    #[cfg(target_arch = "wasm32")]
+    let keys = TrustedKeys::none();
+
+    (keys, signatures)
+}
+
+/// The plugin as the side panel lists it: its icon and name, and the rest of its [Manifest] to
+/// show on hover. A plugin without a manifest is listed by its file name.
+fn catalogue_entry(file_name: &str, manifest: Option<&Manifest>) -> (String, String) {
+    let Some(manifest) = manifest else {
+        return (file_name.to_string(), file_name.to_string());
+    };
+
+    let title = match &manifest.icon {
+        Some(icon) => format!("{icon} {}", manifest.name),
+        None => manifest.name.clone(),
+    };
+    let mut details = format!("{} {}", manifest.name, manifest.version);
+    if !manifest.author.is_empty() {
+        details.push_str(&format!(" by {}", manifest.author));
+    }
+    if !manifest.description.is_empty() {
+        details.push_str(&format!("\n{}", manifest.description));
+    }
+    if !manifest.capabilities.is_empty() {
+        details.push_str(&format!("\nNeeds {}", manifest.capabilities.join(", ")));
+    }
+    details.push_str(&format!("\n{file_name}"));
+    (title, details)
+}
+
+/// Where plugins are hot reloaded from: `$RDX_PLUGIN_DIR`, else `components` in the app's
+/// storage directory.
+#[cfg(not(target_arch = "wasm32"))]
+fn plugin_dir() -> Option<std::path::PathBuf> {
+    std::env::var_os("RDX_PLUGIN_DIR")
+        .map(Into::into)
+        .or_else(|| eframe::storage_dir(APP_NAME).map(|dir| dir.join("components")))
+}
+
 impl eframe::App for TemplateApp {
     /// Called by the frame work to save state before shutdown.
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.rdx.save();
+        if let Some(plugin_storage) = &self.plugin_storage {
+            plugin_storage.save(storage);
+        }
         eframe::set_value(storage, APP_KEY, self);
     }
 
@@ -79,6 +240,18 @@ impl eframe::App for TemplateApp {
 
         // set egui_ctx for the rdx app
 
+        // instantiate the plugins queued at startup, a few each frame
+        self.rdx.load_pending(PLUGIN_LOAD_BUDGET);
+
+        // pick up plugins rebuilt into the plugin directory
+        self.rdx.reload_changed();
+
+        // call the plugin exports whose timers are due
+        self.rdx.run_timers();
+
+        // hand the messages plugins published to the exports subscribed to them
+        self.rdx.deliver_messages();
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             // The top panel is often a good place for a menu bar:
 
@@ -102,11 +275,60 @@ impl eframe::App for TemplateApp {
             ScrollArea::vertical().show(ui, |ui| {
                 ui.with_layout(egui::Layout::top_down_justified(egui::Align::LEFT), |ui| {
                     ui.label("Demos");
-                    // list all plugins by name here
+                    // list all plugins, by the name in their manifest if they have one
                     let Self { rdx, .. } = self;
-                    let RdxApp { plugins, .. } = rdx;
-                    for name in plugins.keys() {
-                        ui.toggle_value(&mut true, name);
+                    for (name, status) in rdx.statuses() {
+                        let (title, details) = catalogue_entry(&name, rdx.manifest(&name));
+                        ui.horizontal(|ui| {
+                            if let PluginStatus::Failed(error) = &status {
+                                ui.colored_label(
+                                    ui.visuals().error_fg_color,
+                                    format!("{title} (failed)"),
+                                )
+                                .on_hover_text(error);
+                            } else {
+                                // a pending plugin is on its way to being enabled
+                                let mut enabled = status != PluginStatus::Disabled;
+                                let toggle =
+                                    ui.toggle_value(&mut enabled, &title).on_hover_text(details);
+                                if toggle.changed() {
+                                    let result = if enabled {
+                                        rdx.enable(&name)
+                                    } else {
+                                        rdx.disable(&name)
+                                    };
+                                    if let Err(e) = result {
+                                        tracing::error!("Failed to switch {}: {}", name, e);
+                                    }
+                                }
+                                if status == PluginStatus::Pending {
+                                    ui.spinner().on_hover_text("Loading");
+                                }
+                            }
+
+                            match rdx.publisher(&name) {
+                                Some(publisher) => {
+                                    ui.label("✔")
+                                        .on_hover_text(format!("Signed by {publisher}"));
+                                }
+                                None if rdx.is_builtin(&name) => {
+                                    ui.weak("builtin");
+                                }
+                                None if status == PluginStatus::Enabled => {
+                                    ui.weak("unsigned");
+                                }
+                                None => {}
+                            }
+
+                            if ui.small_button("⟳").on_hover_text("Restart").clicked() {
+                                if let Err(e) = rdx.restart(&name) {
+                                    tracing::error!("Failed to restart {}: {}", name, e);
+                                }
+                            }
+                            if ui.small_button("✖").on_hover_text("Unload").clicked() {
+                                rdx.unload(&name);
+                            }
+                        });
                     }
                 });
             });
@@ -3,6 +3,8 @@ use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 
+use crate::layer::manifest;
+
 /// Build script to include any wasm component binaries in the build.
 ///
 /// # Example
@@ -49,6 +51,7 @@ pub fn build_script() {
                         ext == "wasm"
                             && *path.file_stem().unwrap() != *this_root_crate
                             && wasmparser::Parser::is_component(&bytes)
+                            && has_valid_manifest(&path, &bytes)
                     })
                     .map(|_| path.to_path_buf())
             })
@@ -89,3 +92,18 @@ pub fn build_script() {
         eprintln!("Failed to write to {}: {}", dest_path.display(), e);
     }
 }
+
+/// Whether the component's manifest is valid, or it has none. Plugins with an invalid one
+/// are left out of the build with a warning.
+fn has_valid_manifest(path: &Path, bytes: &[u8]) -> bool {
+    match manifest::read(bytes) {
+        Ok(_) => true,
+        Err(e) => {
+            println!(
+                "cargo:warning=Leaving out {}, its manifest is invalid: {e}",
+                path.display()
+            );
+            false
+        }
+    }
+}
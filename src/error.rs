@@ -26,4 +26,71 @@ pub enum Error {
     /// Html parse error
     #[error("Html Parse Error: {0}")]
     HtmlParseError(String),
+
+    /// The plugin imports something it wasn't granted the capability for
+    #[error("Capability `{capability}` not granted, but required by import {import}")]
+    CapabilityNotGranted {
+        capability: crate::layer::capability::Capability,
+        import: String,
+    },
+
+    /// A call into the plugin ran out of its execution budget
+    #[error("Execution budget exceeded: {0}")]
+    BudgetExceeded(crate::layer::limits::Budget),
+
+    /// The bytes are not a valid wasm component
+    #[error("Failed to decode component: {0}")]
+    ComponentDecode(anyhow::Error),
+
+    /// A host import couldn't be defined, or the component's imports couldn't be resolved
+    #[error("Failed to link {item}: {source}")]
+    Link { item: String, source: anyhow::Error },
+
+    /// No plugin is known by that name
+    #[error("No plugin named {0}")]
+    PluginNotFound(String),
+
+    /// The plugin doesn't export something the host needs from it
+    #[error("Plugin is missing the export {0}")]
+    MissingExport(String),
+
+    /// The plugin ran into one of its store quotas
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(crate::layer::limits::Quota),
+
+    /// The plugin's topic permissions don't cover the topic
+    #[error("Not permitted to {action} topic {topic}")]
+    TopicNotPermitted { action: &'static str, topic: String },
+
+    /// A handler was given more or fewer arguments than its export takes
+    #[error("{handler} takes {expected} arguments, but was given {found}")]
+    WrongArity {
+        handler: String,
+        expected: usize,
+        found: usize,
+    },
+
+    /// No trusted publisher signed the plugin, and the host requires it
+    #[error("Plugin is not signed by a trusted publisher: {0}")]
+    Untrusted(String),
+
+    /// The plugin's signature is malformed, or doesn't match the component
+    #[error("Bad plugin signature: {0}")]
+    BadSignature(String),
+
+    /// The plugin's manifest is malformed, or asks for a newer host
+    #[error("Invalid plugin manifest: {0}")]
+    InvalidManifest(#[from] crate::layer::manifest::ManifestError),
+
+    /// The capabilities the plugin's manifest declares aren't the ones it imports
+    #[error("Manifest capabilities don't match: {0}")]
+    CapabilityMismatch(String),
+
+    /// An argument couldn't be converted into the type of its parameter
+    #[error("Bad argument {param} to {handler}: {reason}")]
+    BadArgument {
+        handler: String,
+        param: String,
+        reason: String,
+    },
 }
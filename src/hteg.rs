@@ -10,13 +10,10 @@ use types::{FuncAndArgs, HtmlElement};
 
 use std::cell::RefCell;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
 
-use wasm_component_layer::Value;
-
-use crate::layer::{Inner, Instantiator};
+use crate::layer::Inner;
 use crate::template::TemplatePart;
-use crate::Error;
+use crate::{Error, Worker};
 
 /// Parses the html and renders to egui for us.
 #[derive(Clone)]
@@ -40,44 +37,42 @@ impl HtmlToEgui {
     }
 
     /// Parses the html text into a Vec of [scraper::html::Select] elements.
-    /// Then renders the elements into egui UI components.
-    pub fn parse_and_render<T: Inner + Clone + Send + Sync>(
+    /// Then renders the elements into egui UI components, with the plugin's handlers called
+    /// through the [Worker].
+    pub fn parse_and_render<T: Inner + Clone + Send + Sync + 'static>(
         &mut self,
         ctx: egui::Context,
         ui: &mut egui::Ui,
         html: &str,
-        plugin: Arc<Mutex<dyn Instantiator<T>>>,
+        worker: &Worker<T>,
     ) -> Result<(), Error> {
         let html_ast = self.parser.parse(html)?;
-        self.render_element(ctx, ui, &html_ast, plugin.clone())?;
+        self.render_element(ctx, ui, &html_ast, worker)?;
         Ok(())
     }
 
     /// Recurive function that walks the [scraper::ElementRef] and turns the
     /// HTML into egui UI components.
-    fn render_element<T: Inner + Clone + Send + Sync>(
+    fn render_element<T: Inner + Clone + Send + Sync + 'static>(
         &mut self,
         ctx: egui::Context,
         ui: &mut egui::Ui,
         element: &HtmlElement,
-        plugin: Arc<Mutex<dyn Instantiator<T>>>,
+        worker: &Worker<T>,
     ) -> Result<(), Error> {
         // get the content scope values
         // converts the rhai::Dynamic value to a string
-        let entries = {
-            let lock = plugin.lock().unwrap();
-            let state = lock.store().data();
-            let scope = state.clone().into_scope();
-            scope
-                .iter()
-                .map(|(k, _c, v)| (k.to_string(), v.to_string()))
-                .collect::<Vec<_>>()
-        };
+        let entries = worker
+            .state()
+            .into_scope()
+            .iter()
+            .map(|(k, _c, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>();
 
         match element {
             HtmlElement::Html { children, .. } => {
                 for child in children {
-                    self.render_element(ctx.clone(), ui, child, plugin.clone())?;
+                    self.render_element(ctx.clone(), ui, child, worker)?;
                 }
             }
             HtmlElement::Div {
@@ -88,9 +83,7 @@ impl HtmlToEgui {
                 let add_contents = |ui: &mut egui::Ui| {
                     if element.child_elements().is_some() {
                         for child in element.child_elements().unwrap() {
-                            if let Err(e) =
-                                self.render_element(ctx.clone(), ui, child, plugin.clone())
-                            {
+                            if let Err(e) = self.render_element(ctx.clone(), ui, child, worker) {
                                 tracing::error!("Error rendering child element: {:?}", e);
                             }
                         }
@@ -119,43 +112,44 @@ impl HtmlToEgui {
 
                 let content = template.render(entries);
 
-                if ui.add(egui::Button::new(content).fill(color)).clicked() {
-                    // get button.evt_handlers Vec entry which matches EvtHandler.ty == OnClick
+                // get button.evt_handlers Vec entry which matches EvtHandler.ty == OnClick
+                let handler = button.func_and_args(Action::OnClick);
+
+                // a button whose handler is still running in the background can't be clicked again
+                let pending = handler
+                    .as_ref()
+                    .is_some_and(|handler| worker.is_pending(&handler.function));
+
+                let clicked = ui
+                    .add_enabled(!pending, egui::Button::new(content).fill(color))
+                    .clicked();
+                if pending {
+                    ui.spinner();
+                }
+
+                if clicked {
                     if let Some(FuncAndArgs {
                         function: on_click,
                         args,
-                    }) = button.func_and_args(Action::OnClick)
+                    }) = handler
                     {
                         let arguments = {
-                            let mut lock = plugin.lock().unwrap();
-                            let scope = lock.store_mut().data_mut().scope_mut();
-                            args.iter()
-                                // ONLY use non-empty args, filter everything else out
-                                // there can be zero arg ie) increment() where the return vec is zero
-                                // length. That's ok.
-                                .filter_map(|v| {
-                                    scope
-                                        .get_value::<String>(v)
-                                        .map(|val| Value::String(val.into()))
-                                })
-                                .collect::<Vec<_>>()
+                            let mut state = worker.state();
+                            let scope = state.scope_mut();
+                            worker.arguments(&on_click, scope_arguments(&scope, &args))
                         };
 
-                        tracing::info!(
-                            "Calling on_click function: {} with args: {:?} [length: {}]",
-                            on_click,
-                            arguments,
-                            arguments.len()
-                        );
-
-                        let mut lock = plugin.lock().unwrap();
-                        match lock.call(&on_click, arguments.as_slice()) {
-                            Ok(res) => {
-                                tracing::info!("on_click response {:?}", res);
-                            }
-                            Err(e) => {
-                                tracing::error!("on_click Error {:?}", e);
+                        match arguments {
+                            Ok(arguments) => {
+                                tracing::info!(
+                                    "Calling on_click function: {} with args: {:?} [length: {}]",
+                                    on_click,
+                                    arguments,
+                                    arguments.len()
+                                );
+                                worker.call(&on_click, arguments);
                             }
+                            Err(e) => tracing::error!("Not calling on_click function: {}", e),
                         }
 
                         // also call the same rhai function
@@ -167,7 +161,8 @@ impl HtmlToEgui {
                         // to compile the ast again, we've already got it.
                         // I don't think we can really do anything with the result here, we'll
                         // leave that for now.
-                        let mut scope = lock.store_mut().data_mut().scope_mut();
+                        let mut state = worker.state();
+                        let mut scope = state.scope_mut();
 
                         let options = CallFnOptions::new()
                             .eval_ast(false) // do not re-evaluate the AST
@@ -221,8 +216,10 @@ impl HtmlToEgui {
                 // Get the value of the variable from the rhai::Scope
                 // Put the value into rhai::Scope as the value of the variable
                 // Can I just linkt he rhai scope variable to the TextEdit widget?
-                let mut lock = plugin.lock().unwrap();
-                let mut scope = lock.store_mut().data_mut().scope_mut();
+                let mut state = worker.state();
+                let mut scope = state.scope_mut();
+
+                let handler = input.func_and_args(Action::OnChange);
 
                 if let Some(mut val) = scope.get_value::<String>(var_name.as_str()) {
                     let mut single_line = egui::TextEdit::singleline(&mut val)
@@ -234,6 +231,15 @@ impl HtmlToEgui {
                     }
 
                     let response = ui.add(single_line);
+
+                    // show that the on_change handler is still running in the background
+                    if handler
+                        .as_ref()
+                        .is_some_and(|handler| worker.is_pending(&handler.function))
+                    {
+                        ui.spinner();
+                    }
+
                     if response.changed() {
                         // update the scope variable
                         scope.set_value(var_name.as_str(), val.clone());
@@ -242,37 +248,19 @@ impl HtmlToEgui {
                         if let Some(FuncAndArgs {
                             function: on_change,
                             args: func_args,
-                        }) = input.func_and_args(Action::OnChange)
+                        }) = handler
                         {
                             // if on_change is not empty, call the function
                             if !on_change.is_empty() {
-                                let args = func_args
-                                    .iter()
-                                    .map(|v| {
-                                        Value::String(
-                                            scope.get_value::<String>(v).unwrap_or_default().into(),
-                                        )
-                                    })
-                                    .collect::<Vec<_>>();
+                                let args = scope_arguments(&scope, &func_args);
 
                                 drop(scope);
-                                drop(lock);
-
-                                let mut lock = plugin.lock().unwrap();
 
-                                if let Ok(value) = lock.call(&on_change, args.as_slice()) {
-                                    match value {
-                                        Some(Value::String(_s)) => {
-                                            // TODO: act on return value(s)?
-                                        }
-                                        Some(Value::Bool(_)) => {}
-                                        _ => {}
+                                match worker.arguments(&on_change, args) {
+                                    Ok(args) => worker.call(&on_change, args),
+                                    Err(e) => {
+                                        tracing::error!("Not calling on_change function: {}", e)
                                     }
-                                } else {
-                                    tracing::error!(
-                                        "Failed to call on_change function: {}",
-                                        on_change
-                                    );
                                 }
                             }
                         }
@@ -297,8 +285,8 @@ impl HtmlToEgui {
                 // Get the value of the variable from the rhai::Scope
                 // Put the value into rhai::Scope as the value of the variable
                 // Can I just linkt he rhai scope variable to the TextEdit widget?
-                let mut lock = plugin.lock().unwrap();
-                let mut scope = lock.store_mut().data_mut().scope_mut();
+                let mut state = worker.state();
+                let mut scope = state.scope_mut();
 
                 // 1. get scope.get_value::<String>(var_name.as_str())
                 // 2. if it doesn't exist, set it to var_name.to_string() and set_value
@@ -343,6 +331,18 @@ impl HtmlToEgui {
     }
 }
 
+/// The values of the scope variables a handler names as its arguments, by name. A variable
+/// that isn't in the scope is `()`, which only an `option` parameter accepts.
+fn scope_arguments(scope: &rhai::Scope, names: &[String]) -> Vec<(String, rhai::Dynamic)> {
+    names
+        .iter()
+        .map(|name| {
+            let value = scope.get_value::<rhai::Dynamic>(name).unwrap_or_default();
+            (name.clone(), value)
+        })
+        .collect()
+}
+
 /// Converts kebab-case and pascalCase to snake_case
 fn to_snake_case(s: &str) -> String {
     let mut snake_case = String::new();
@@ -367,4 +367,27 @@ fn to_snake_case(s: &str) -> String {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_arguments() {
+        let mut scope = rhai::Scope::new();
+        scope.push("name", "ada".to_string());
+        scope.push("count", 3 as rhai::INT);
+
+        let args = scope_arguments(
+            &scope,
+            &[
+                "name".to_string(),
+                "count".to_string(),
+                "missing".to_string(),
+            ],
+        );
+        assert_eq!(args.len(), 3);
+        assert_eq!(args[0].0, "name");
+        assert_eq!(args[0].1.clone().into_string().unwrap(), "ada");
+        assert_eq!(args[1].1.as_int(), Ok(3));
+        assert!(args[2].1.is_unit());
+    }
+}
@@ -1,17 +1,47 @@
+pub mod bus;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cache;
+pub mod capability;
+use capability::{Capabilities, Capability};
+
+pub mod clocks;
+
+pub mod event;
+pub mod fuel;
+use fuel::Tanks;
+pub mod guest_resource;
+use guest_resource::GuestResources;
+pub mod http;
+use http::AllowedOrigins;
+mod instrument;
+pub mod limits;
+use limits::{ExecutionLimits, Meter, Quota, Quotas};
+pub mod logging;
+pub mod manifest;
+use manifest::Manifest;
+pub mod memory;
+use memory::Gauges;
+
 pub mod poll;
-use poll::{subscribe, MakeFuture, PollableFuture, Subscribe};
+use poll::{block_on, drop_pollable, poll_once, subscribe, MakeFuture, PollableFuture, Subscribe};
+pub mod random;
 
 mod resource;
-pub use resource::Resource;
-pub use resource_table::ResourceTable;
+pub use resource::{HostResourceIndex, Resource};
+pub use resource_table::{ResourceTable, ResourceTableError};
 
 mod noop_waker;
 pub use noop_waker::noop_waker;
+#[cfg(target_arch = "wasm32")]
 use send_wrapper::SendWrapper;
 
 pub mod resource_table;
+pub mod signature;
+use signature::{Publisher, SignaturePolicy, TrustedKeys};
+pub mod storage;
 
 use std::any::Any;
+use std::borrow::Cow;
 use std::cell::RefMut;
 use std::collections::HashMap;
 use std::ops::Deref;
@@ -29,30 +59,59 @@ pub use web_time::{Duration, Instant, SystemTime};
 
 pub use anyhow::bail;
 use core::future::Future;
-use core::pin::pin;
 use core::task::{Context, Poll};
 
 pub use rhai;
 
 pub use poll::Pollable;
 pub use wasm_component_layer::{
-    AsContext as _, AsContextMut as _, Component, Engine, Func, FuncType, Instance, Linker, List,
-    ListType, RecordType, ResourceOwn, ResourceType, Store, Value, ValueType,
+    AsContext as _, AsContextMut as _, Component, Engine, Func, FuncType, Instance,
+    InterfaceIdentifier, Linker, List, ListType, RecordType, ResourceOwn, ResourceType, Store,
+    Value, ValueType,
 };
 
-// If android, use wasmi_runtime_layer
-#[cfg(target_os = "android")]
-pub use wasmi_runtime_layer as runtime_layer;
+// Natively the engine is picked by the `runtime-wasmtime` and `runtime-wasmi` features,
+// wasmtime winning when both are enabled. The browser runs plugins itself.
+#[cfg(all(not(target_arch = "wasm32"), feature = "runtime-wasmtime"))]
+pub use wasmtime_runtime_layer as runtime_layer;
 
-// if not android AND not wasm32, use wasmtime_runtime_layer
-#[cfg(not(any(target_os = "android", target_arch = "wasm32")))]
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    feature = "runtime-wasmi",
+    not(feature = "runtime-wasmtime")
+))]
 pub use wasmi_runtime_layer as runtime_layer;
 
 #[cfg(target_arch = "wasm32")]
 pub use js_wasm_runtime_layer as runtime_layer;
 
+#[cfg(not(any(
+    target_arch = "wasm32",
+    feature = "runtime-wasmi",
+    feature = "runtime-wasmtime"
+)))]
+compile_error!("Enable the `runtime-wasmi` or `runtime-wasmtime` feature to pick an engine");
+
+/// The name of the engine plugins run on
+#[cfg(all(not(target_arch = "wasm32"), feature = "runtime-wasmtime"))]
+pub const RUNTIME: &str = "wasmtime";
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    feature = "runtime-wasmi",
+    not(feature = "runtime-wasmtime")
+))]
+pub const RUNTIME: &str = "wasmi";
+#[cfg(target_arch = "wasm32")]
+pub const RUNTIME: &str = "browser";
+
 pub use crate::Error;
 
+/// The interface plugins import the host functions from
+const HOST_INTERFACE: &str = "component:plugin/host";
+
+/// The interface plugins export their functions from
+pub const RUN_INTERFACE: &str = "component:plugin/run";
+
 /// Immutable reference to the [rhai::Scope]
 pub enum ScopeRef {
     Borrowed(Arc<Mutex<rhai::Scope<'static>>>),
@@ -127,6 +186,61 @@ pub trait Inner {
 
     /// Consumes [Inner] to yield Owned Scope
     fn into_scope(self) -> rhai::Scope<'static>;
+
+    /// The plugin's name, which everything it logs is attributed to
+    fn name(&self) -> &str {
+        "plugin"
+    }
+
+    /// The plugin's key/value [storage::Namespace], if the host provides storage
+    fn storage(&self) -> Option<storage::Namespace> {
+        None
+    }
+
+    /// The plugin's way onto the message [bus::Bus], if the host provides one
+    fn bus(&self) -> Option<bus::Endpoint> {
+        None
+    }
+
+    /// The origins the plugin may send HTTP requests to, if granted [Capability::Http]
+    fn http_origins(&self) -> AllowedOrigins {
+        AllowedOrigins::none()
+    }
+
+    /// The [Capabilities] granted to the plugin. Only these imports are linked, and none are
+    /// granted unless the host says so.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::none()
+    }
+
+    /// The [ExecutionLimits] every call into the plugin is held to.
+    fn execution_limits(&self) -> ExecutionLimits {
+        ExecutionLimits::unlimited()
+    }
+
+    /// The [Quotas] on the plugin's memory, resources and scope.
+    fn quotas(&self) -> Quotas {
+        Quotas::unlimited()
+    }
+
+    /// Asks the host to redraw, such as when a call made in the background has finished
+    fn request_repaint(&self) {}
+
+    /// The publishers whose signatures the host accepts, see [signature]
+    fn trusted_keys(&self) -> TrustedKeys {
+        TrustedKeys::none()
+    }
+
+    /// What to do if none of the [Inner::trusted_keys] signed the plugin
+    fn signature_policy(&self) -> SignaturePolicy {
+        SignaturePolicy::Allow
+    }
+
+    /// Where the component is kept pre-processed between runs, if the host caches components
+    #[cfg(not(target_arch = "wasm32"))]
+    fn cache(&self) -> Option<cache::ComponentCache> {
+        None
+    }
 }
 
 /// The sleep resource
@@ -208,9 +322,13 @@ impl Subscribe for Deadline {
                         instant,
                         Instant::now()
                     );
-                    send_wrapper::SendWrapper::new(async move {
-                        if let Err(e) = js_sleep(instant.elapsed().as_millis() as i32).await {
-                            tracing::error!("Error sleeping: {:?}", e);
+                    // checked on every poll, as nothing can wake a guest blocked in the browser
+                    let instant = *instant;
+                    std::future::poll_fn(move |_cx| {
+                        if Instant::now() >= instant {
+                            Poll::Ready(())
+                        } else {
+                            Poll::Pending
                         }
                     })
                     .await;
@@ -243,38 +361,251 @@ fn subscribe_to_duration(
     subscribe(table, sleep)
 }
 
+/// Records a [ResourceTableError::LimitReached] on the [Meter], so the call fails with
+/// [Error::QuotaExceeded] rather than a bare trap.
+fn charge_table_error(meter: &Meter, error: anyhow::Error) -> anyhow::Error {
+    match error.downcast_ref::<ResourceTableError>() {
+        Some(ResourceTableError::LimitReached(limit)) => {
+            meter.exceed_quota(Quota::Resources(*limit)).into()
+        }
+        _ => error,
+    }
+}
+
+/// Handles `[resource-drop]name` for a host resource by deleting it from the table. A resource
+/// can't be dropped while it has children, such as a pollable subscribed to it, and a handle
+/// that outlived its entry is refused rather than dropping whatever took its place.
+pub(crate) fn drop_resource<T: Any>(
+    table: &Mutex<ResourceTable>,
+    name: &str,
+    resource: Resource<T>,
+) -> anyhow::Result<()> {
+    tracing::debug!("[resource-drop]{} {}", name, resource.rep());
+    table
+        .lock()
+        .unwrap()
+        .delete(resource)
+        .map_err(|e| anyhow::anyhow!("[resource-drop]{name}: {e}"))?;
+    Ok(())
+}
+
+/// The component's import of `interface`, such as `wasi:clocks/wall-clock@0.2.2`, at
+/// whichever version compatible with it the component was built against.
+pub(crate) fn imported<'a>(
+    component: &'a Component,
+    interface: &str,
+) -> Option<&'a wasm_component_layer::ComponentTypesInstance> {
+    let interface = InterfaceIdentifier::try_from(interface).ok()?;
+    component
+        .imports()
+        .instances()
+        .find(|(imported, _)| compatible(imported, &interface))
+        .map(|(_, instance)| instance)
+}
+
+/// Whether `a` and `b` are the same interface at semver compatible versions: the same major
+/// version, and before 1.0 the same minor one too.
+fn compatible(a: &InterfaceIdentifier, b: &InterfaceIdentifier) -> bool {
+    let versions = match (a.package().version(), b.package().version()) {
+        (Some(a), Some(b)) => a.major == b.major && (a.major > 0 || a.minor == b.minor),
+        (a, b) => a == b,
+    };
+    a.name() == b.name() && a.package().name() == b.package().name() && versions
+}
+
+/// Links the host's interfaces under every other version the component imports them at, as
+/// long as it is compatible, so a component built against `wasi:clocks@0.2.0` gets the host's
+/// `@0.2.2` clocks. Anything the newer version added is still missing, and fails to link.
+fn link_compatible_versions(linker: &mut Linker, component: &Component) -> anyhow::Result<()> {
+    let aliases = component
+        .imports()
+        .instances()
+        .filter(|(imported, _)| linker.instance(imported).is_none())
+        .filter_map(|(imported, _)| {
+            linker
+                .instances()
+                .find(|(defined, _)| compatible(imported, defined))
+                .map(|(_, instance)| (imported.clone(), instance.clone()))
+        })
+        .collect::<Vec<_>>();
+    for (imported, instance) in aliases {
+        *linker.define_instance(imported)? = instance;
+    }
+    Ok(())
+}
+
+/// Wraps a failure to define or resolve `item` in [Error::Link].
+fn link_error(item: &str) -> impl FnOnce(anyhow::Error) -> Error + '_ {
+    move |source| Error::Link {
+        item: item.to_string(),
+        source,
+    }
+}
+
+/// Host-side state shared between the linked imports and the plugin calling into them.
+#[derive(Debug, Clone)]
+pub struct Host {
+    /// The resources handed out to the guest, such as pollables
+    pub table: Arc<Mutex<ResourceTable>>,
+    /// The guest's resources handed to scripts, kept in the same table
+    pub resources: GuestResources,
+    /// Meters each call into the guest against its [ExecutionLimits]
+    pub meter: Meter,
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        let table = Arc::<Mutex<ResourceTable>>::default();
+        Self {
+            resources: GuestResources::new(table.clone()),
+            table,
+            meter: Meter::default(),
+        }
+    }
+}
+
+/// The engine to compile the component on. On wasmtime it keeps the code it compiles in the
+/// host's [cache::ComponentCache], if there is one.
+#[cfg(all(not(target_arch = "wasm32"), feature = "runtime-wasmtime"))]
+fn runtime_engine<T: Inner>(data: &T) -> runtime_layer::Engine {
+    let mut config = wasmtime::Config::new();
+    if let Some(cache) = data.cache() {
+        let loaded = cache
+            .wasmtime_config()
+            .map_err(anyhow::Error::from)
+            .and_then(|path| config.cache_config_load(path).map(drop));
+        if let Err(e) = loaded {
+            tracing::warn!("Compiling without a cache: {}", e);
+        }
+    }
+    match wasmtime::Engine::new(&config) {
+        Ok(engine) => runtime_layer::Engine::new(engine),
+        Err(e) => {
+            tracing::warn!("Falling back to the default engine: {}", e);
+            runtime_layer::Engine::default()
+        }
+    }
+}
+
+/// The engine to compile the component on.
+#[cfg(not(all(not(target_arch = "wasm32"), feature = "runtime-wasmtime")))]
+fn runtime_engine<T: Inner>(_data: &T) -> runtime_layer::Engine {
+    runtime_layer::Engine::default()
+}
+
+/// Caps the component's memories and meters its fuel, as far as [Inner::quotas] and
+/// [Inner::execution_limits] ask for it. The host's [cache::ComponentCache] keeps the result,
+/// if there is one.
+fn prepare<'a, T: Inner>(bytes: &'a [u8], data: &T) -> Result<Cow<'a, [u8]>, Error> {
+    let max_memory = data.quotas().memory;
+    let metered = data.execution_limits().fuel.is_some();
+    if max_memory.is_none() && !metered {
+        return Ok(Cow::Borrowed(bytes));
+    }
+
+    let process = || {
+        let mut bytes = Cow::Borrowed(bytes);
+        if let Some(max_bytes) = max_memory {
+            bytes = Cow::Owned(memory::limit(&bytes, max_bytes)?);
+        }
+        if metered {
+            bytes = Cow::Owned(fuel::instrument(&bytes)?);
+        }
+        Ok(bytes.into_owned())
+    };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(cache) = data.cache() {
+        let key = cache::ComponentCache::key(bytes, max_memory, metered);
+        return cache.get_or_insert_with(&key, process).map(Cow::Owned);
+    }
+
+    process().map(Cow::Owned)
+}
+
+/// Instantiates the component, linking only the host imports granted by [Inner::capabilities].
+///
+/// A component with a [Manifest] is granted exactly the capabilities it declares, see
+/// [capability::from_manifest].
 pub fn instantiate_instance<T: Inner + 'static>(
     bytes: &[u8],
     data: T,
-) -> (Instance, Store<T, runtime_layer::Engine>) {
-    let table = Arc::new(Mutex::new(ResourceTable::new()));
+) -> Result<(Instance, Store<T, runtime_layer::Engine>), Error> {
+    let manifest = manifest::read(bytes)?;
+    instantiate_with_host(bytes, data, &Host::default(), manifest.as_ref())
+}
+
+/// Same as [instantiate_instance], but the imports share the given [Host] state, so the caller
+/// can meter its calls and reach the guest's resources, and the caller passes the component's
+/// `manifest`, having read it already.
+pub fn instantiate_with_host<T: Inner + 'static>(
+    bytes: &[u8],
+    data: T,
+    host: &Host,
+    manifest: Option<&Manifest>,
+) -> Result<(Instance, Store<T, runtime_layer::Engine>), Error> {
+    let table = host.table.clone();
 
     // Create a new engine for instantiating a component.
-    let engine = Engine::new(runtime_layer::Engine::default());
+    tracing::debug!("Instantiating on {}", RUNTIME);
+    let engine = Engine::new(runtime_engine(&data));
 
     // Create a store for managing WASM data and any custom user-defined state.
     let mut store = Store::new(&engine, data);
 
+    // Cap the linear memory and meter fuel before the runtime ever sees the component.
+    let quotas = store.data().quotas();
+    let bytes = prepare(bytes, store.data())?;
+
     // Parse the component bytes and load its imports and exports.
-    let component = Component::new(&engine, bytes).unwrap();
+    let component = Component::new(&engine, &bytes).map_err(Error::ComponentDecode)?;
+
+    // Refuse to run components that import more than they were granted. One with a manifest
+    // gets exactly what it declares, which must be what it imports.
+    let granted = match manifest {
+        Some(manifest) => capability::from_manifest(
+            &component,
+            &manifest.capabilities,
+            &store.data().capabilities(),
+        )?,
+        None => {
+            let granted = store.data().capabilities();
+            capability::check(&component, &granted)?;
+            granted
+        }
+    };
+
+    host.meter.set_limits(store.data().execution_limits());
+    table.lock().unwrap().set_limit(quotas.resources);
+
     // Create a linker that will be used to resolve the component's imports, if any.
     let mut linker = Linker::default();
 
-    // Pollable resource type
-    let resource_pollable_ty = ResourceType::new::<Resource<Pollable>>(None);
+    // Pollable resource type. Dropping one in the guest deletes it from the table.
+    let table_clone = table.clone();
+    let resource_pollable_ty = ResourceType::with_destructor(
+        &mut store,
+        None,
+        move |_store, pollable: Resource<Pollable>| {
+            tracing::debug!("[resource-drop]pollable {}", pollable.rep());
+            drop_pollable(&table_clone, pollable)
+        },
+    )
+    .map_err(link_error("pollable"))?;
 
     // pollable is wasi:io/poll
     let poll_interface = linker
-        .define_instance("wasi:io/poll@0.2.2".try_into().unwrap())
-        .unwrap();
+        .define_instance("wasi:io/poll@0.2.2".try_into()?)
+        .map_err(link_error("wasi:io/poll"))?;
 
     poll_interface
         .define_resource("pollable", resource_pollable_ty.clone())
-        .unwrap();
+        .map_err(link_error("pollable"))?;
 
     // ready and block are methods on the pollable resource, "[method]pollable.ready" and "[method]pollable.block"
     //ready: func() -> bool;
     let table_clone = table.clone();
+    let meter = host.meter.clone();
     poll_interface
         .define_func(
             "[method]pollable.ready",
@@ -285,10 +616,11 @@ pub fn instantiate_instance<T: Inner + 'static>(
                     [ValueType::Bool],
                 ),
                 move |store, params, results| {
+                    meter.consume()?;
                     tracing::info!("[method]pollable.ready");
 
                     let Value::Borrow(pollable_resource) = &params[0] else {
-                        panic!("Incorrect input type, found {:?}", params[0]);
+                        bail!("Incorrect input type, found {:?}", params[0]);
                     };
 
                     tracing::info!("Got borrow param pollable {:?}", pollable_resource);
@@ -310,46 +642,64 @@ pub fn instantiate_instance<T: Inner + 'static>(
 
                     let ready = (pollable.make_future)(table.get_any_mut(pollable.index)?);
 
-                    tracing::info!("Got ready");
-
-                    let mut fut = pin!(ready);
-                    let waker = noop_waker();
-                    let mut cx = Context::from_waker(&waker);
-
-                    // Poll the future once
-                    let poll_result = fut.as_mut().poll(&mut cx);
-
-                    // Check the result
-                    let ready = matches!(poll_result, Poll::Ready(()));
+                    // Poll the future once, without waiting
+                    let ready = poll_once(ready).is_some();
 
                     tracing::info!("[ready] Poll result: {:?}", ready);
 
-                    // if not ready, save the future to the table
-
                     results[0] = Value::Bool(ready);
                     Ok(())
                 },
             ),
         )
-        .unwrap();
+        .map_err(link_error("[method]pollable.ready"))?;
 
+    // block: func(); waits until the pollable is ready
+    let table_clone = table.clone();
+    let meter = host.meter.clone();
     poll_interface
         .define_func(
             "[method]pollable.block",
             Func::new(
                 &mut store,
-                FuncType::new([], []),
-                move |_store, _params, _results| {
+                FuncType::new([ValueType::Borrow(resource_pollable_ty.clone())], []),
+                move |store, params, _results| {
+                    meter.consume()?;
                     tracing::info!("[method]pollable.block");
-                    //todo!();
+
+                    let Value::Borrow(pollable_resource) = &params[0] else {
+                        bail!("Incorrect input type, found {:?}", params[0]);
+                    };
+
+                    let binding = store.as_context();
+                    let res_pollable: &Resource<Pollable> = pollable_resource.rep(&binding)?;
+
+                    // wait with the resource taken out of the table, so the table isn't
+                    // locked meanwhile
+                    let (index, make_future, mut entry) = {
+                        let mut table = table_clone.lock().unwrap();
+                        let pollable = table.get(res_pollable)?;
+                        let (index, make_future) = (pollable.index, pollable.make_future);
+                        (index, make_future, table.take_any(index)?)
+                    };
+
+                    // don't wait past the call's deadline
+                    let waited = block_on(make_future(&mut *entry), meter.block_timeout());
+                    table_clone.lock().unwrap().put_back(index, entry)?;
+                    if let Err(e) = waited {
+                        meter.consume()?;
+                        bail!("Gave up blocking on pollable: {e}");
+                    }
+
                     Ok(())
                 },
             ),
         )
-        .unwrap();
+        .map_err(link_error("[method]pollable.block"))?;
 
     // poll: func(in: list<borrow<pollable>>) -> list<u32>;
     let table_clone = table.clone();
+    let meter = host.meter.clone();
     poll_interface
         .define_func(
             "poll",
@@ -362,6 +712,7 @@ pub fn instantiate_instance<T: Inner + 'static>(
                     [ValueType::List(ListType::new(ValueType::U32))],
                 ),
                 move |mut store, params, results| {
+                    meter.consume()?;
                     tracing::info!("[method]pollable.poll");
 
                     type ReadylistIndex = u32;
@@ -381,8 +732,10 @@ pub fn instantiate_instance<T: Inner + 'static>(
 
                     tracing::debug!("[poll]: create table futures");
 
-                    let mut table_futures: HashMap<u32, (MakeFuture, Vec<ReadylistIndex>)> =
-                        HashMap::new();
+                    let mut table_futures: HashMap<
+                        HostResourceIndex,
+                        (MakeFuture, Vec<ReadylistIndex>),
+                    > = HashMap::new();
 
                     for (ix, p) in pollables.iter().enumerate() {
                         let ix: u32 = ix.try_into()?;
@@ -404,23 +757,18 @@ pub fn instantiate_instance<T: Inner + 'static>(
                         list.push(ix);
                     }
 
-                    let mut futures: Vec<(PollableFuture<'_>, Vec<ReadylistIndex>)> = Vec::new();
-
-                    let mut binding = table_clone.lock().unwrap();
-
-                    let it = table_futures.into_iter().map(move |(k, v)| {
-                        let item = binding
-                            .occupied_mut(k)
-                            .map(|e| Box::as_mut(&mut e.entry))
-                            // Safety: extending the lifetime of the mutable reference.
-                            .map(|item| unsafe { &mut *(item as *mut dyn Any) });
-                        (item, v)
-                    });
-
-                    for (entry, (make_future, readylist_indices)) in it {
-                        let entry = entry?;
-                        futures.push((make_future(entry), readylist_indices));
-                    }
+                    // wait with the resources taken out of the table, so the table isn't
+                    // locked meanwhile
+                    let mut entries = Vec::with_capacity(table_futures.len());
+                    let taken = {
+                        let mut table = table_clone.lock().unwrap();
+                        table_futures
+                            .into_iter()
+                            .try_for_each(|(index, (make_future, list))| {
+                                entries.push((index, make_future, table.take_any(index)?, list));
+                                Ok::<_, ResourceTableError>(())
+                            })
+                    };
 
                     struct PollList<'a> {
                         futures: Vec<(PollableFuture<'a>, Vec<ReadylistIndex>)>,
@@ -452,169 +800,259 @@ pub fn instantiate_instance<T: Inner + 'static>(
                         }
                     }
 
-                    tracing::debug!("[poll]: return poll list");
+                    tracing::debug!("[poll]: wait for the poll list");
+
+                    // Wait until at least one pollable is ready, but not past the call's deadline
+                    let ready = taken.map_err(anyhow::Error::from).and_then(|()| {
+                        let futures = entries
+                            .iter_mut()
+                            .map(|(_, make_future, entry, list)| {
+                                (make_future(&mut **entry), std::mem::take(list))
+                            })
+                            .collect();
+                        block_on(PollList { futures }, meter.block_timeout())
+                    });
+
+                    let mut table = table_clone.lock().unwrap();
+                    for (index, _, entry, _) in entries {
+                        table.put_back(index, entry)?;
+                    }
+                    drop(table);
+
+                    let ready = match ready {
+                        Ok(ready) => ready,
+                        Err(e) => {
+                            meter.consume()?;
+                            bail!("Gave up polling {} pollables: {e}", pollables.len());
+                        }
+                    };
 
-                    // We set results[0] to be the sync equivalent to: PollList { futures }.await
                     results[0] = Value::List(List::new(
                         ListType::new(ValueType::U32),
-                        futures
-                            .into_iter()
-                            // only add to the returned list if the future is ready, otherwise skip
-                            // the future until next time
-                            .filter_map(|(mut fut, readylist_indices)| {
-                                let waker = noop_waker();
-                                let mut cx = Context::from_waker(&waker);
-                                match fut.as_mut().poll(&mut cx) {
-                                    Poll::Ready(()) => Some(readylist_indices),
-                                    Poll::Pending => None,
-                                }
-                            })
-                            .flatten()
-                            .map(Value::U32)
-                            .collect::<Vec<_>>(),
+                        ready.into_iter().map(Value::U32),
                     )?);
 
                     Ok(())
                 },
             ),
         )
-        .unwrap();
+        .map_err(link_error("poll"))?;
 
     let host_interface = linker
-        .define_instance("component:plugin/host".try_into().unwrap())
-        .unwrap();
+        .define_instance(HOST_INTERFACE.try_into()?)
+        .map_err(link_error(HOST_INTERFACE))?;
+
+    // The guest decides whether its event `value` is a plain string or the typed `value`
+    // variant, so link `emit` with whichever `event` type the component was built against.
+    let event_ty = component
+        .imports()
+        .instance(&HOST_INTERFACE.try_into()?)
+        .and_then(|host| host.func("emit"))
+        .and_then(|emit| emit.params().first().cloned())
+        .unwrap_or_else(event::legacy_event_type);
+
+    if granted.contains(Capability::Log) {
+        // "log" function using tracing
+        let meter = host.meter.clone();
+        host_interface
+            .define_func(
+                "log",
+                Func::new(
+                    &mut store,
+                    FuncType::new([ValueType::String], []),
+                    move |_store, params, _results| {
+                        meter.consume()?;
+                        if let Value::String(s) = &params[0] {
+                            logging::log(logging::Level::Info, "", s, &[]);
+                        }
+                        Ok(())
+                    },
+                ),
+            )
+            .map_err(link_error("log"))?;
+    }
 
-    // params is a record with name and value
-    let record = RecordType::new(
-        None,
-        vec![("name", ValueType::String), ("value", ValueType::String)],
-    )
-    .unwrap();
-    let params = ValueType::Record(record);
-    let results = [];
+    if granted.contains(Capability::Emit) {
+        let meter = host.meter.clone();
+        host_interface
+            .define_func(
+                "emit",
+                Func::new(
+                    &mut store,
+                    FuncType::new([event_ty], []),
+                    move |mut store, params, _results| {
+                        meter.consume()?;
+                        tracing::info!("Emitting event {:?}", params);
+                        let Value::Record(record) = &params[0] else {
+                            bail!("Incorrect input type, found {:?}", params[0]);
+                        };
 
-    // "log" function using tracing
-    host_interface
-        .define_func(
-            "log",
-            Func::new(
-                &mut store,
-                FuncType::new([ValueType::String], []),
-                move |_store, params, _results| {
-                    if let Value::String(s) = &params[0] {
-                        tracing::info!("{}", s);
-                    }
-                    Ok(())
-                },
-            ),
-        )
-        .unwrap();
+                        let (name, value) = event::from_record(record)?;
 
-    host_interface
-        .define_func(
-            "emit",
-            Func::new(
-                &mut store,
-                FuncType::new([params], results),
-                move |mut store, params, _results| {
-                    tracing::info!("Emitting event {:?}", params);
-                    if let Value::Record(record) = &params[0] {
-                        let name = record.field("name").unwrap();
-                        let value = record.field("value").unwrap();
-
-                        if let Value::String(name) = name {
-                            if let Value::String(value) = value {
-                                tracing::info!("Updating state with {:?} {:?}", name, value);
-                                store.data_mut().update(&name, &*value);
+                        if let Some(max) = quotas.scope_entries {
+                            let full = {
+                                let scope = store.data_mut().scope_mut();
+                                scope.len() >= max && !scope.contains(&name)
+                            };
+                            if full {
+                                return Err(meter.exceed_quota(Quota::ScopeEntries(max)).into());
                             }
                         }
-                    }
-
-                    Ok(())
-                },
-            ),
-        )
-        .unwrap();
 
-    // add func get_random
-    host_interface
-        .define_func(
-            "random-byte",
-            Func::new(
-                &mut store,
-                FuncType::new([], [ValueType::U8]),
-                move |_store, _params, results| {
-                    let random = rand::random::<u8>();
-                    results[0] = Value::U8(random);
-                    Ok(())
-                },
-            ),
-        )
-        .unwrap();
+                        tracing::info!("Updating state with {:?} {:?}", name, value);
+                        store.data_mut().update(&name, value);
 
-    // now function
-    host_interface
-        .define_func(
-            "now",
-            Func::new(
-                &mut store,
-                FuncType::new([], [ValueType::S64]),
-                move |_store, _params, results| {
-                    let unix_timestamp = SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs() as i64;
-                    results[0] = Value::S64(unix_timestamp);
-                    Ok(())
-                },
-            ),
-        )
-        .unwrap();
+                        Ok(())
+                    },
+                ),
+            )
+            .map_err(link_error("emit"))?;
+    }
 
-    // sleep takes ms and returns a Pollable resource type
-    let table_clone = table.clone();
-    host_interface
-        .define_func(
-            "subscribe-duration",
-            Func::new(
-                &mut store,
-                FuncType::new(
-                    [ValueType::U64],
-                    [ValueType::Own(resource_pollable_ty.clone())],
+    if granted.contains(Capability::Random) {
+        // add func get_random
+        let meter = host.meter.clone();
+        host_interface
+            .define_func(
+                "random-byte",
+                Func::new(
+                    &mut store,
+                    FuncType::new([], [ValueType::U8]),
+                    move |_store, _params, results| {
+                        meter.consume()?;
+                        let random = rand::random::<u8>();
+                        results[0] = Value::U8(random);
+                        Ok(())
+                    },
                 ),
-                move |mut store, params, results| {
-                    // sleep should take these millis and turn them into pollable
-                    // then return the pollable
+            )
+            .map_err(link_error("random-byte"))?;
+    }
 
-                    let Value::U64(millis) = params[0] else {
-                        panic!("Incorrect input type.")
-                    };
+    if granted.contains(Capability::Clock) {
+        // now function
+        let meter = host.meter.clone();
+        host_interface
+            .define_func(
+                "now",
+                Func::new(
+                    &mut store,
+                    FuncType::new([], [ValueType::S64]),
+                    move |_store, _params, results| {
+                        meter.consume()?;
+                        let unix_timestamp = SystemTime::now()
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs() as i64;
+                        results[0] = Value::S64(unix_timestamp);
+                        Ok(())
+                    },
+                ),
+            )
+            .map_err(link_error("now"))?;
+
+        // sleep takes ms and returns a Pollable resource type
+        let table_clone = table.clone();
+        let pollable_ty = resource_pollable_ty.clone();
+        let meter = host.meter.clone();
+        host_interface
+            .define_func(
+                "subscribe-duration",
+                Func::new(
+                    &mut store,
+                    FuncType::new(
+                        [ValueType::U64],
+                        [ValueType::Own(resource_pollable_ty.clone())],
+                    ),
+                    move |mut store, params, results| {
+                        meter.consume()?;
+                        // sleep should take these millis and turn them into pollable
+                        // then return the pollable
+
+                        let Value::U64(millis) = params[0] else {
+                            bail!("Incorrect input type, found {:?}", params[0]);
+                        };
 
-                    tracing::info!("Subscribing to duration: {:?}", millis);
+                        tracing::info!("Subscribing to duration: {:?}", millis);
 
-                    let resource_pollable =
-                        subscribe_to_duration(table_clone.clone(), Duration::from_millis(millis))
-                            .map_err(|e| {
+                        let resource_pollable = subscribe_to_duration(
+                            table_clone.clone(),
+                            Duration::from_millis(millis),
+                        )
+                        .map_err(|e| {
                             tracing::error!("Error subscribing to duration: {:?}", e);
-                            e
+                            charge_table_error(&meter, e)
                         })?;
 
-                    tracing::info!("Subscribed to duration");
+                        tracing::info!("Subscribed to duration");
 
-                    let pollable_resource = ResourceOwn::new(
-                        &mut store,
-                        resource_pollable,
-                        resource_pollable_ty.clone(),
-                    )?;
+                        let pollable_resource =
+                            ResourceOwn::new(&mut store, resource_pollable, pollable_ty.clone())?;
 
-                    results[0] = Value::Own(pollable_resource);
-                    Ok(())
-                },
-            ),
+                        results[0] = Value::Own(pollable_resource);
+                        Ok(())
+                    },
+                ),
+            )
+            .map_err(link_error("subscribe-duration"))?;
+
+        clocks::add_to_linker(
+            &mut linker,
+            &mut store,
+            &component,
+            &table,
+            &resource_pollable_ty,
+            &host.meter,
         )
-        .unwrap();
+        .map_err(link_error("wasi:clocks"))?;
+    }
+
+    if granted.contains(Capability::Log) {
+        logging::add_to_linker(&mut linker, &mut store, &component, &host.meter)
+            .map_err(link_error("wasi:logging"))?;
+    }
+
+    if granted.contains(Capability::Random) {
+        random::add_to_linker(&mut linker, &mut store, &host.meter)
+            .map_err(link_error("wasi:random"))?;
+    }
 
-    (linker.instantiate(&mut store, &component).unwrap(), store)
+    if granted.contains(Capability::Storage) {
+        storage::add_to_linker(&mut linker, &mut store, &host.meter)
+            .map_err(link_error(storage::INTERFACE))?;
+    }
+
+    if granted.contains(Capability::Http) {
+        http::add_to_linker(
+            &mut linker,
+            &mut store,
+            &component,
+            &table,
+            &resource_pollable_ty,
+            &host.meter,
+        )
+        .map_err(link_error(http::INTERFACE))?;
+    }
+
+    if granted.contains(Capability::Bus) {
+        bus::add_to_linker(
+            &mut linker,
+            &mut store,
+            &component,
+            &table,
+            &resource_pollable_ty,
+            &host.meter,
+        )
+        .map_err(link_error(bus::INTERFACE))?;
+    }
+
+    link_compatible_versions(&mut linker, &component).map_err(link_error("the component"))?;
+
+    let instance = linker
+        .instantiate(&mut store, &component)
+        .map_err(link_error("the component"))?;
+
+    Ok((instance, store))
 }
 
 pub trait Instantiator<T: Inner + Send + Sync>: Send {
@@ -626,23 +1064,110 @@ pub trait Instantiator<T: Inner + Send + Sync>: Send {
 
     // call fn
     fn call(&mut self, name: &str, arguments: &[Value]) -> Result<Option<Value>, Error>;
+
+    /// The functions the plugin exports from [RUN_INTERFACE], with their types
+    fn exports(&self) -> Result<Vec<(String, FuncType)>, Error>;
+
+    /// The guest resources the plugin's exports hand out, to convert them for scripts
+    fn resources(&self) -> GuestResources;
+}
+
+/// A guest resource can only be borrowed through the store, so scripts pass the `own` their
+/// [ResourceHandle](guest_resource::ResourceHandle) holds. Where the parameter is a `borrow`
+/// it is lent for the call instead.
+fn lend(
+    mut ctx: impl wasm_component_layer::AsContextMut,
+    params: &[ValueType],
+    arguments: &[Value],
+) -> anyhow::Result<Vec<Value>> {
+    arguments
+        .iter()
+        .enumerate()
+        .map(|(i, argument)| match (argument, params.get(i)) {
+            (Value::Own(own), Some(ValueType::Borrow(_))) => {
+                Ok(Value::Borrow(own.borrow(&mut ctx)?))
+            }
+            _ => Ok(argument.clone()),
+        })
+        .collect()
 }
 
+/// The browser's runtime isn't `Send`, so there the [Store] may only be used from the thread
+/// that created it. Natively it can be moved to a worker thread.
+#[cfg(target_arch = "wasm32")]
+type PluginStore<T> = SendWrapper<Store<T, runtime_layer::Engine>>;
+#[cfg(not(target_arch = "wasm32"))]
+type PluginStore<T> = Store<T, runtime_layer::Engine>;
+
 /// Plugin struct to store some state
 pub struct LayerPlugin<T: Inner + Send + Sync> {
-    pub(crate) store: SendWrapper<Store<T, runtime_layer::Engine>>,
+    pub(crate) store: PluginStore<T>,
     raw_instance: wasm_component_layer::Instance,
+    host: Host,
+    /// The guest's fuel counters, if it was instantiated with a fuel limit
+    tanks: Tanks,
+    /// The flags the guest raises when it is refused memory, if it has a memory quota
+    gauges: Gauges,
+    /// Who signed the component, if a trusted publisher did
+    publisher: Option<Publisher>,
+    /// What the component says about itself, if it has a manifest
+    manifest: Option<Manifest>,
 }
 
 impl<T: Inner + Send + Sync + 'static> LayerPlugin<T> {
     /// Creates a new plugin instance with the given name and bytes
-    pub fn new(bytes: &[u8], data: T) -> Self {
-        let (instance, store) = instantiate_instance(bytes, data);
+    ///
+    /// The component's signature is checked against [Inner::trusted_keys] first, and one that
+    /// the [Inner::signature_policy] doesn't accept is never instantiated. Neither is one
+    /// with an invalid [Manifest], or whose manifest doesn't declare the capabilities it
+    /// imports.
+    pub fn new(bytes: &[u8], data: T) -> Result<Self, Error> {
+        let host = Host::default();
+        let span = logging::plugin_span(data.name());
+        // the signature and the manifest are read in the same pass
+        let sections = signature::sections(bytes)?;
+        let publisher = span.in_scope(|| {
+            signature::check(&sections, &data.trusted_keys(), data.signature_policy())
+        })?;
+        let manifest = manifest::from_sections(&sections.manifests)?;
+        let (instance, store) =
+            span.in_scope(|| instantiate_with_host(bytes, data, &host, manifest.as_ref()))?;
+
+        if instance
+            .exports()
+            .instance(&RUN_INTERFACE.try_into()?)
+            .is_none()
+        {
+            return Err(Error::MissingExport(RUN_INTERFACE.to_string()));
+        }
 
-        Self {
-            store: SendWrapper::new(store),
+        #[cfg(target_arch = "wasm32")]
+        let store = SendWrapper::new(store);
+
+        Ok(Self {
+            store,
+            tanks: Tanks::new(&instance),
+            gauges: Gauges::new(&instance),
             raw_instance: instance,
-        }
+            host,
+            publisher,
+            manifest,
+        })
+    }
+
+    /// The [Meter] holding this plugin's calls to its [ExecutionLimits]
+    pub fn meter(&self) -> &Meter {
+        &self.host.meter
+    }
+
+    /// The trusted publisher who signed the component, if one did
+    pub fn publisher(&self) -> Option<&Publisher> {
+        self.publisher.as_ref()
+    }
+
+    /// The component's [Manifest], if it has one
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
     }
 }
 
@@ -659,13 +1184,30 @@ impl<T: Inner + Send + Sync + 'static> Instantiator<T> for LayerPlugin<T> {
         &mut self.store
     }
 
+    fn exports(&self) -> Result<Vec<(String, FuncType)>, Error> {
+        let export_instance = self
+            .raw_instance
+            .exports()
+            .instance(&RUN_INTERFACE.try_into()?)
+            .ok_or(Error::InstanceNotFound)?;
+
+        Ok(export_instance
+            .funcs()
+            .map(|(name, func)| (name.to_string(), func.ty()))
+            .collect())
+    }
+
+    fn resources(&self) -> GuestResources {
+        self.host.resources.clone()
+    }
+
     /// Calls the given function name with the given parameters
     fn call(&mut self, name: &str, arguments: &[Value]) -> Result<Option<Value>, Error> {
         tracing::trace!("Calling function: {}", name);
         let export_instance = self
             .raw_instance
             .exports()
-            .instance(&"component:plugin/run".try_into()?)
+            .instance(&RUN_INTERFACE.try_into()?)
             .ok_or(Error::InstanceNotFound)?;
 
         let func = export_instance
@@ -675,11 +1217,47 @@ impl<T: Inner + Send + Sync + 'static> Instantiator<T> for LayerPlugin<T> {
         let func_result_len = func.ty().results().len();
         let mut results = vec![Value::Bool(false); func_result_len];
 
-        func.call(self.store.deref_mut(), arguments, &mut results)
-            .map_err(|e| {
-                tracing::error!("Error calling function: {:?}", e);
-                e
-            })?;
+        let store: &mut Store<T, runtime_layer::Engine> = &mut self.store;
+        self.host.resources.release(&mut *store)?;
+        let arguments = lend(&mut *store, func.ty().params(), arguments)?;
+
+        // every call starts with a full tank
+        let fuel = self.host.meter.limits().fuel.unwrap_or(u64::MAX);
+        self.tanks.fill(&mut *store, fuel)?;
+        // and is only blamed for the memory it is refused itself
+        self.gauges.refused(&mut *store)?;
+
+        let span = logging::plugin_span(store.data().name());
+        self.host.meter.start();
+        let result = span.in_scope(|| func.call(&mut *store, &arguments, &mut results));
+        // handles dropped while the export ran
+        if let Err(e) = self.host.resources.release(&mut *store) {
+            tracing::error!("Failed to release resources after {}: {}", name, e);
+        }
+
+        let finished = self.host.meter.finish();
+        // a guest that ran dry trapped on `unreachable`, which only the tanks can tell apart
+        if result.is_err() && self.tanks.fill(&mut *store, fuel)? {
+            let e = Error::BudgetExceeded(limits::Budget::Fuel(fuel));
+            tracing::error!("Calling {} failed: {}", name, e);
+            return Err(e);
+        }
+        if let Err(e) = finished {
+            tracing::error!("Calling {} failed: {}", name, e);
+            return Err(e);
+        }
+        // a guest refused memory traps, as its allocator aborts, which only the gauges can tell
+        if result.is_err() && self.gauges.refused(&mut *store)? {
+            let max_bytes = store.data().quotas().memory.unwrap_or_default();
+            let e = Error::QuotaExceeded(Quota::Memory(max_bytes));
+            tracing::error!("Calling {} failed: {}", name, e);
+            return Err(e);
+        }
+
+        result.map_err(|e| {
+            tracing::error!("Error calling function: {:?}", e);
+            e
+        })?;
 
         if results.is_empty() {
             Ok(None)
@@ -695,10 +1273,28 @@ mod tests {
 
     use super::*;
 
-    #[derive(Default)]
     struct State {
         count: rhai::Dynamic,
         scope: Arc<Mutex<rhai::Scope<'static>>>,
+        capabilities: Capabilities,
+        limits: ExecutionLimits,
+        quotas: Quotas,
+        trusted_keys: TrustedKeys,
+        signature_policy: SignaturePolicy,
+    }
+
+    impl Default for State {
+        fn default() -> Self {
+            Self {
+                count: Default::default(),
+                scope: Default::default(),
+                capabilities: Capabilities::all(),
+                limits: ExecutionLimits::unlimited(),
+                quotas: Quotas::unlimited(),
+                trusted_keys: TrustedKeys::none(),
+                signature_policy: SignaturePolicy::Allow,
+            }
+        }
     }
 
     impl Inner for State {
@@ -726,6 +1322,26 @@ mod tests {
         fn into_scope(self) -> rhai::Scope<'static> {
             self.scope.lock().unwrap().clone()
         }
+
+        fn capabilities(&self) -> Capabilities {
+            self.capabilities.clone()
+        }
+
+        fn execution_limits(&self) -> ExecutionLimits {
+            self.limits
+        }
+
+        fn quotas(&self) -> Quotas {
+            self.quotas
+        }
+
+        fn trusted_keys(&self) -> TrustedKeys {
+            self.trusted_keys.clone()
+        }
+
+        fn signature_policy(&self) -> SignaturePolicy {
+            self.signature_policy
+        }
     }
 
     #[test]
@@ -737,7 +1353,7 @@ mod tests {
             ..Default::default()
         };
 
-        let (instance, mut store) = instantiate_instance(WASM, data);
+        let (instance, mut store) = instantiate_instance(WASM, data).unwrap();
 
         // Get the interface that the interface exports.
         let exports = instance.exports();
@@ -783,7 +1399,7 @@ mod tests {
             ..Default::default()
         };
 
-        let mut plugin = LayerPlugin::new(WASM, data);
+        let mut plugin = LayerPlugin::new(WASM, data).unwrap();
 
         let _ = plugin.call("increment-count", &[]).unwrap();
 
@@ -793,6 +1409,422 @@ mod tests {
         assert_eq!(result, Some(Value::S32(1)));
     }
 
+    // the counter imports `emit`, so it must not run without the Emit capability
+    #[test]
+    fn test_capability_not_granted() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+
+        let data = State {
+            capabilities: Capabilities::none().grant(Capability::Log),
+            ..Default::default()
+        };
+
+        let Err(Error::CapabilityNotGranted { capability, import }) = LayerPlugin::new(WASM, data)
+        else {
+            panic!("counter.wasm should need the emit capability");
+        };
+
+        assert_eq!(capability, Capability::Emit);
+        assert!(import.ends_with("#emit"));
+    }
+
+    /// A component importing `get-random-u64` from `interface`, into a core module that does
+    /// nothing with it.
+    fn importing_random(interface: &str) -> Vec<u8> {
+        use wasm_encoder::{
+            Alias, CanonicalFunctionSection, ComponentAliasSection, ComponentExportKind,
+            ComponentImportSection, ComponentTypeRef, ComponentTypeSection, EntityType, ExportKind,
+            ImportSection, InstanceSection, InstanceType, Module, ModuleArg, ModuleSection,
+            PrimitiveValType, TypeSection, ValType,
+        };
+
+        let mut module = Module::new();
+        let mut types = TypeSection::new();
+        types.ty().function([], [ValType::I64]);
+        module.section(&types);
+        let mut imports = ImportSection::new();
+        imports.import("random", "get-random-u64", EntityType::Function(0));
+        module.section(&imports);
+
+        let mut random = InstanceType::new();
+        let nothing: [(&str, PrimitiveValType); 0] = [];
+        random
+            .ty()
+            .function()
+            .params(nothing)
+            .result(PrimitiveValType::U64);
+        random.export("get-random-u64", ComponentTypeRef::Func(0));
+        let mut types = ComponentTypeSection::new();
+        types.instance(&random);
+
+        let mut imports = ComponentImportSection::new();
+        imports.import(interface, ComponentTypeRef::Instance(0));
+
+        let mut aliases = ComponentAliasSection::new();
+        aliases.alias(Alias::InstanceExport {
+            instance: 0,
+            kind: ComponentExportKind::Func,
+            name: "get-random-u64",
+        });
+
+        let mut lowers = CanonicalFunctionSection::new();
+        lowers.lower(0, []);
+
+        let mut instances = InstanceSection::new();
+        instances.export_items([("get-random-u64", ExportKind::Func, 0)]);
+        instances.instantiate(0, [("random", ModuleArg::Instance(0))]);
+
+        let mut component = wasm_encoder::Component::new();
+        component.section(&ModuleSection(&module));
+        component.section(&types);
+        component.section(&imports);
+        component.section(&aliases);
+        component.section(&lowers);
+        component.section(&instances);
+        component.finish()
+    }
+
+    #[test]
+    fn test_manifest_capabilities() {
+        let declaring = |capabilities: &[&str]| {
+            let manifest = Manifest {
+                name: "Dice".to_string(),
+                version: "0.1.0".to_string(),
+                description: String::new(),
+                author: String::new(),
+                icon: None,
+                capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+                min_host_version: None,
+            };
+            manifest::embed(&importing_random("wasi:random/random@0.2.2"), &manifest)
+        };
+        let granting = |capabilities: Capabilities| State {
+            capabilities,
+            ..Default::default()
+        };
+
+        // exactly what it imports, and granted
+        instantiate_instance(&declaring(&["random"]), granting(Capabilities::all())).unwrap();
+
+        // asks for what it isn't granted
+        assert!(matches!(
+            instantiate_instance(&declaring(&["random"]), granting(Capabilities::none())),
+            Err(Error::CapabilityNotGranted {
+                capability: Capability::Random,
+                ..
+            })
+        ));
+        // imports what it doesn't declare
+        assert!(matches!(
+            instantiate_instance(&declaring(&[]), granting(Capabilities::all())),
+            Err(Error::CapabilityMismatch(_))
+        ));
+        // declares what it doesn't import
+        assert!(matches!(
+            instantiate_instance(
+                &declaring(&["random", "http"]),
+                granting(Capabilities::all())
+            ),
+            Err(Error::CapabilityMismatch(_))
+        ));
+    }
+
+    // a component built against another 0.2 release of wasi is served the host's
+    #[test]
+    fn test_compatible_wasi_versions() {
+        let data = || State {
+            capabilities: Capabilities::none().grant(Capability::Random),
+            ..Default::default()
+        };
+
+        for version in ["0.2.0", "0.2.2", "0.2.6"] {
+            let bytes = importing_random(&format!("wasi:random/random@{version}"));
+            instantiate_instance(&bytes, data()).unwrap();
+        }
+
+        // but not another minor release
+        assert!(matches!(
+            instantiate_instance(&importing_random("wasi:random/random@0.3.0"), data()),
+            Err(Error::Link { .. })
+        ));
+    }
+
+    /// A component with a memory of one page, exporting the given functions, of no
+    /// parameters and no results, from [RUN_INTERFACE].
+    fn component_exporting(funcs: Vec<(&str, wasm_encoder::Function)>) -> Vec<u8> {
+        use wasm_encoder::{
+            Alias, CanonicalFunctionSection, CodeSection, ComponentAliasSection,
+            ComponentExportKind, ComponentExportSection, ComponentInstanceSection,
+            ComponentTypeSection, ExportKind, ExportSection, FunctionSection, InstanceSection,
+            MemorySection, MemoryType, Module, ModuleArg, ModuleSection, PrimitiveValType,
+            TypeSection,
+        };
+
+        let mut module = Module::new();
+        let mut types = TypeSection::new();
+        types.ty().function([], []);
+        module.section(&types);
+        let mut functions = FunctionSection::new();
+        let mut memories = MemorySection::new();
+        memories.memory(MemoryType {
+            minimum: 1,
+            maximum: None,
+            memory64: false,
+            shared: false,
+            page_size_log2: None,
+        });
+        let mut exports = ExportSection::new();
+        let mut code = CodeSection::new();
+        for (index, (name, body)) in funcs.iter().enumerate() {
+            functions.function(0);
+            exports.export(name, ExportKind::Func, index as u32);
+            code.function(body);
+        }
+        module.section(&functions);
+        module.section(&memories);
+        module.section(&exports);
+        module.section(&code);
+
+        let mut component = wasm_encoder::Component::new();
+        component.section(&ModuleSection(&module));
+        let mut instances = InstanceSection::new();
+        let no_args: [(&str, ModuleArg); 0] = [];
+        instances.instantiate(0, no_args);
+        component.section(&instances);
+        let mut aliases = ComponentAliasSection::new();
+        for (name, _) in &funcs {
+            aliases.alias(Alias::CoreInstanceExport {
+                instance: 0,
+                kind: ExportKind::Func,
+                name,
+            });
+        }
+        component.section(&aliases);
+        let mut types = ComponentTypeSection::new();
+        let nothing: [(&str, PrimitiveValType); 0] = [];
+        types.function().params(nothing).results(nothing);
+        component.section(&types);
+        let mut lifts = CanonicalFunctionSection::new();
+        for index in 0..funcs.len() as u32 {
+            lifts.lift(index, 0, []);
+        }
+        component.section(&lifts);
+        let mut instances = ComponentInstanceSection::new();
+        instances.export_items(
+            funcs
+                .iter()
+                .enumerate()
+                .map(|(index, (name, _))| (*name, ComponentExportKind::Func, index as u32)),
+        );
+        component.section(&instances);
+        let mut exports = ComponentExportSection::new();
+        exports.export(RUN_INTERFACE, ComponentExportKind::Instance, 0, None);
+        component.section(&exports);
+        component.finish()
+    }
+
+    /// A component exporting `spin` from [RUN_INTERFACE], which loops forever without ever
+    /// calling the host.
+    fn spinning_component() -> Vec<u8> {
+        use wasm_encoder::{BlockType, Function, Instruction};
+
+        let mut spin = Function::new([]);
+        spin.instruction(&Instruction::Loop(BlockType::Empty))
+            .instruction(&Instruction::Br(0))
+            .instruction(&Instruction::End)
+            .instruction(&Instruction::End);
+        component_exporting(vec![("spin", spin)])
+    }
+
+    // a guest that never calls the host still runs dry
+    #[test]
+    fn test_budget_exceeded() {
+        let data = State {
+            limits: ExecutionLimits::unlimited().with_fuel(10_000),
+            ..Default::default()
+        };
+
+        let mut plugin = LayerPlugin::new(&spinning_component(), data).unwrap();
+
+        assert!(matches!(
+            plugin.call("spin", &[]),
+            Err(Error::BudgetExceeded(limits::Budget::Fuel(10_000)))
+        ));
+
+        // every call gets its own fuel
+        assert!(matches!(
+            plugin.call("spin", &[]),
+            Err(Error::BudgetExceeded(limits::Budget::Fuel(10_000)))
+        ));
+    }
+
+    // metered plugins work as usual while they have fuel
+    #[test]
+    fn test_fuel_to_spare() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+
+        let data = State {
+            limits: ExecutionLimits::unlimited().with_fuel(1_000_000),
+            ..Default::default()
+        };
+
+        let mut plugin = LayerPlugin::new(WASM, data).unwrap();
+
+        assert_eq!(
+            plugin.call("increment-count", &[]).unwrap(),
+            Some(Value::S32(1))
+        );
+        assert_eq!(plugin.call("current", &[]).unwrap(), Some(Value::S32(1)));
+    }
+
+    // increment-count emits `count` into an empty scope, which a quota of zero doesn't allow
+    #[test]
+    fn test_scope_quota() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+
+        let data = State {
+            quotas: Quotas::unlimited().with_scope_entries(0),
+            ..Default::default()
+        };
+
+        let mut plugin = LayerPlugin::new(WASM, data).unwrap();
+
+        assert!(matches!(
+            plugin.call("increment-count", &[]),
+            Err(Error::QuotaExceeded(Quota::ScopeEntries(0)))
+        ));
+    }
+
+    /// A component exporting `grow`, which grows its memory a page at a time until it is
+    /// refused and then aborts, as Rust's allocator does, and `trap`, which just aborts.
+    fn growing_component() -> Vec<u8> {
+        use wasm_encoder::{BlockType, Function, Instruction};
+
+        let mut grow = Function::new([]);
+        grow.instruction(&Instruction::Loop(BlockType::Empty))
+            .instruction(&Instruction::I32Const(1))
+            .instruction(&Instruction::MemoryGrow(0))
+            .instruction(&Instruction::I32Const(-1))
+            .instruction(&Instruction::I32Ne)
+            .instruction(&Instruction::BrIf(0))
+            .instruction(&Instruction::End)
+            .instruction(&Instruction::Unreachable)
+            .instruction(&Instruction::End);
+        let mut trap = Function::new([]);
+        trap.instruction(&Instruction::Unreachable)
+            .instruction(&Instruction::End);
+        component_exporting(vec![("grow", grow), ("trap", trap)])
+    }
+
+    // a guest that traps once it can't grow any further ran into its quota
+    #[test]
+    fn test_memory_quota_when_growing() {
+        const QUOTA: u64 = 4 * 64 * 1024;
+        let quotas = Quotas::unlimited().with_memory(QUOTA);
+        let metered = State {
+            quotas,
+            limits: ExecutionLimits::unlimited().with_fuel(1_000_000),
+            ..Default::default()
+        };
+        for data in [
+            State {
+                quotas,
+                ..Default::default()
+            },
+            // the fuel counters don't get in the way
+            metered,
+        ] {
+            let mut plugin = LayerPlugin::new(&growing_component(), data).unwrap();
+            assert!(matches!(plugin.call("trap", &[]), Err(Error::Anyhow(_))));
+            assert!(matches!(
+                plugin.call("grow", &[]),
+                Err(Error::QuotaExceeded(Quota::Memory(QUOTA)))
+            ));
+            // the next trap is its own
+            assert!(matches!(plugin.call("trap", &[]), Err(Error::Anyhow(_))));
+        }
+    }
+
+    // no component fits in zero bytes of memory
+    #[test]
+    fn test_memory_quota() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+
+        let data = State {
+            quotas: Quotas::unlimited().with_memory(0),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            LayerPlugin::new(WASM, data),
+            Err(Error::QuotaExceeded(Quota::Memory(0)))
+        ));
+    }
+
+    #[test]
+    fn test_signatures() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+        let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+        let required = || State {
+            trusted_keys: TrustedKeys::none()
+                .trust("acme", key.verifying_key().to_bytes())
+                .unwrap(),
+            signature_policy: SignaturePolicy::Require,
+            ..Default::default()
+        };
+
+        let signed = signature::sign(WASM, &key);
+        let plugin = LayerPlugin::new(&signed, required()).unwrap();
+        assert_eq!(plugin.publisher().unwrap().name, "acme");
+
+        assert!(matches!(
+            LayerPlugin::new(WASM, required()),
+            Err(Error::Untrusted(_))
+        ));
+
+        // a changed component isn't run, whatever the policy
+        let tampered = signature::attach(
+            &[WASM, &b"\0\x02\x01x"[..]].concat(),
+            &signature::sidecar(WASM, &key),
+        );
+        assert!(matches!(
+            LayerPlugin::new(&tampered, required()),
+            Err(Error::BadSignature(_))
+        ));
+
+        // signed by someone the host doesn't know, which is only fine when allowed
+        let by_stranger = signature::sign(WASM, &ed25519_dalek::SigningKey::from_bytes(&[8; 32]));
+        let plugin = LayerPlugin::new(&by_stranger, State::default()).unwrap();
+        assert!(plugin.publisher().is_none());
+    }
+
+    #[test]
+    fn test_manifest() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+
+        // the counter example declares itself in examples/counter/manifest.json
+        let plugin = LayerPlugin::new(WASM, State::default()).unwrap();
+        let counter = plugin.manifest().unwrap().clone();
+        assert_eq!(counter.name, "Counter");
+        assert_eq!(counter.capabilities, vec!["emit".to_string()]);
+
+        // a second manifest makes it ambiguous
+        assert!(matches!(
+            LayerPlugin::new(&manifest::embed(WASM, &counter), State::default()),
+            Err(Error::InvalidManifest(manifest::ManifestError::Duplicate(
+                2
+            )))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_component() {
+        assert!(matches!(
+            LayerPlugin::new(b"not a component", State::default()),
+            Err(Error::ComponentDecode(_))
+        ));
+    }
+
     // test that Sleep can be saved as Any, then downcast back into Sleep
     #[test]
     fn test_sleep_any_rountrip() {
@@ -817,4 +1849,38 @@ mod tests {
 
         is_send::<Sleep>();
     }
+
+    #[test]
+    fn test_dropped_pollable_cant_be_used() {
+        let host = Host::default();
+        let mut store = Store::new(&Engine::new(runtime_layer::Engine::default()), ());
+        let table = host.table.clone();
+        let ty = ResourceType::with_destructor(
+            &mut store,
+            None,
+            move |_store, pollable: Resource<Pollable>| drop_pollable(&table, pollable),
+        )
+        .unwrap();
+
+        let pollable = subscribe_to_duration(host.table.clone(), Duration::ZERO).unwrap();
+        let index = host.table.lock().unwrap().index(&pollable).unwrap();
+        let own = ResourceOwn::new(&mut store, pollable, ty).unwrap();
+
+        // the guest drops it, taking the deadline it owned along
+        own.drop(&mut store).unwrap();
+        assert!(host.table.lock().unwrap().is_empty());
+
+        // then tries to use it again
+        assert!(own.drop(&mut store).is_err());
+        assert!(own.borrow(&mut store).is_err());
+
+        // a handle kept past the drop doesn't reach the pollable that took its slot
+        let _reused = subscribe_to_duration(host.table.clone(), Duration::ZERO).unwrap();
+        let table = host.table.lock().unwrap();
+        assert_eq!(table.len(), 2);
+        assert!(matches!(
+            table.get(&Resource::<Pollable>::new_index(index)),
+            Err(ResourceTableError::Stale)
+        ));
+    }
 }
@@ -0,0 +1,620 @@
+//! A topic bus the host routes messages over, so plugins can tell each other things, exposed
+//! to guests as `component:plugin/bus`.
+//!
+//! ```wit
+//! interface bus {
+//!   use wasi:io/poll@0.2.2.{pollable};
+//!
+//!   record message { topic: string, payload: string, sender: string }
+//!
+//!   resource subscription {
+//!     subscribe: func() -> pollable;
+//!     next: func() -> option<message>;
+//!   }
+//!
+//!   publish: func(topic: string, payload: string) -> result<u32, string>;
+//!   subscribe: func(topic: string) -> result<subscription, string>;
+//! }
+//! ```
+//!
+//! Every plugin talks to the shared [Bus] through its own [Endpoint], which signs its messages
+//! with the plugin's name and holds it to its [TopicPermissions]. A [Subscription] queues the
+//! messages published on its topic until they are taken. Guests can await them through
+//! `wasi:io/poll`, and Rhai scripts have them delivered to an export instead.
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Poll, Waker};
+
+use anyhow::bail;
+use wasm_component_layer::{
+    AsContext as _, Component, Func, FuncType, Linker, OptionType, OptionValue, Record, RecordType,
+    ResourceOwn, ResourceType, ResultType, ResultValue, Store, Value, ValueType,
+};
+
+use super::limits::Meter;
+use super::{
+    charge_table_error, drop_resource, runtime_layer, subscribe, Inner, Resource, ResourceTable,
+    Subscribe,
+};
+use crate::Error;
+
+/// The interface guests import the bus functions from.
+pub const INTERFACE: &str = "component:plugin/bus";
+
+/// How many messages a subscription holds before the oldest are dropped
+const MAILBOX_CAPACITY: usize = 256;
+
+/// A message published on a topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+    /// The name of the plugin that published it
+    pub sender: String,
+}
+
+/// The topics a plugin may publish and subscribe to.
+///
+/// A pattern is a topic name, or a prefix ending in `*`: `user/*` covers `user/login` and
+/// `user/logout`, and `*` covers every topic.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TopicPermissions {
+    publish: BTreeSet<String>,
+    subscribe: BTreeSet<String>,
+}
+
+impl TopicPermissions {
+    /// No topics at all.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Also allows publishing to the topics matching `pattern`.
+    pub fn publish(mut self, pattern: impl Into<String>) -> Self {
+        self.publish.insert(pattern.into());
+        self
+    }
+
+    /// Also allows subscribing to the topics matching `pattern`.
+    pub fn subscribe(mut self, pattern: impl Into<String>) -> Self {
+        self.subscribe.insert(pattern.into());
+        self
+    }
+
+    /// Whether `topic` may be published to.
+    pub fn can_publish(&self, topic: &str) -> bool {
+        self.publish.iter().any(|pattern| matches(pattern, topic))
+    }
+
+    /// Whether `topic` may be subscribed to.
+    pub fn can_subscribe(&self, topic: &str) -> bool {
+        self.subscribe.iter().any(|pattern| matches(pattern, topic))
+    }
+}
+
+fn matches(pattern: &str, topic: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => topic.starts_with(prefix),
+        None => pattern == topic,
+    }
+}
+
+/// The messages waiting for one [Subscription].
+#[derive(Default)]
+struct Mailbox {
+    messages: VecDeque<Message>,
+    /// Woken when a message arrives
+    wakers: Vec<Waker>,
+}
+
+/// The mailboxes subscribed to a topic
+type Subscribers = Vec<Weak<Mutex<Mailbox>>>;
+
+/// Routes messages between the plugins of one app.
+#[derive(Clone, Default)]
+pub struct Bus {
+    /// The mailboxes subscribed to each topic. Dropped subscriptions are pruned on publish.
+    topics: Arc<Mutex<HashMap<String, Subscribers>>>,
+    /// Called after every publish, such as to have the UI deliver the messages
+    on_publish: Option<Arc<dyn Fn() + Send + Sync>>,
+}
+
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bus")
+            .field("topics", &self.topics.lock().unwrap().keys())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `f` after every message published.
+    pub fn on_publish(mut self, f: impl Fn() + Send + Sync + 'static) -> Self {
+        self.on_publish = Some(Arc::new(f));
+        self
+    }
+
+    /// The plugin called `name`'s way onto the bus.
+    pub fn endpoint(&self, name: impl Into<String>, permissions: TopicPermissions) -> Endpoint {
+        Endpoint {
+            name: name.into(),
+            bus: self.clone(),
+            permissions,
+        }
+    }
+
+    /// Queues `message` for every subscriber of its topic. Returns how many there were.
+    fn publish(&self, message: Message) -> usize {
+        let mailboxes = {
+            let mut topics = self.topics.lock().unwrap();
+            let Some(subscribers) = topics.get_mut(&message.topic) else {
+                return 0;
+            };
+            let mailboxes = subscribers
+                .iter()
+                .filter_map(Weak::upgrade)
+                .collect::<Vec<_>>();
+            subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+            if subscribers.is_empty() {
+                topics.remove(&message.topic);
+            }
+            mailboxes
+        };
+
+        for mailbox in &mailboxes {
+            let mut mailbox = mailbox.lock().unwrap();
+            if mailbox.messages.len() >= MAILBOX_CAPACITY {
+                tracing::warn!(
+                    "Dropping the oldest message on {}, the subscriber is falling behind",
+                    message.topic
+                );
+                mailbox.messages.pop_front();
+            }
+            mailbox.messages.push_back(message.clone());
+            mailbox.wakers.drain(..).for_each(Waker::wake);
+        }
+
+        if let Some(on_publish) = &self.on_publish {
+            on_publish();
+        }
+        mailboxes.len()
+    }
+
+    fn subscribe(&self, topic: &str) -> Subscription {
+        let mailbox = Arc::new(Mutex::new(Mailbox::default()));
+        self.topics
+            .lock()
+            .unwrap()
+            .entry(topic.to_string())
+            .or_default()
+            .push(Arc::downgrade(&mailbox));
+        Subscription {
+            topic: topic.to_string(),
+            mailbox,
+        }
+    }
+}
+
+/// The messages published on one topic since subscribing. Dropping it unsubscribes.
+pub struct Subscription {
+    topic: String,
+    mailbox: Arc<Mutex<Mailbox>>,
+}
+
+impl Subscription {
+    /// The topic subscribed to.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Takes the oldest message, if any.
+    pub fn next(&self) -> Option<Message> {
+        self.mailbox.lock().unwrap().messages.pop_front()
+    }
+
+    /// Takes every message, oldest first.
+    pub fn drain(&self) -> Vec<Message> {
+        self.mailbox.lock().unwrap().messages.drain(..).collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for Subscription {
+    async fn ready(&mut self) {
+        std::future::poll_fn(|cx| {
+            let mut mailbox = self.mailbox.lock().unwrap();
+            if mailbox.messages.is_empty() {
+                // checked over and over by `ready`, with the same waker
+                if !mailbox
+                    .wakers
+                    .iter()
+                    .any(|waker| waker.will_wake(cx.waker()))
+                {
+                    mailbox.wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+        .await
+    }
+}
+
+/// One plugin's way onto the [Bus].
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    name: String,
+    bus: Bus,
+    permissions: TopicPermissions,
+}
+
+impl Endpoint {
+    /// The name of the plugin, which its messages are sent from.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Publishes `payload` on `topic`. Returns how many subscribers it was queued for.
+    pub fn publish(&self, topic: &str, payload: &str) -> Result<usize, Error> {
+        if !self.permissions.can_publish(topic) {
+            return Err(Error::TopicNotPermitted {
+                action: "publish",
+                topic: topic.to_string(),
+            });
+        }
+        Ok(self.bus.publish(Message {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            sender: self.name.clone(),
+        }))
+    }
+
+    /// Subscribes to the messages published on `topic` from now on.
+    pub fn subscribe(&self, topic: &str) -> Result<Subscription, Error> {
+        if !self.permissions.can_subscribe(topic) {
+            return Err(Error::TopicNotPermitted {
+                action: "subscribe",
+                topic: topic.to_string(),
+            });
+        }
+        Ok(self.bus.subscribe(topic))
+    }
+}
+
+fn message_type() -> anyhow::Result<RecordType> {
+    RecordType::new(
+        None,
+        [
+            ("topic", ValueType::String),
+            ("payload", ValueType::String),
+            ("sender", ValueType::String),
+        ],
+    )
+}
+
+/// The type of `func` as the component imports it, if it does.
+fn declared(component: &Component, func: &str) -> Option<FuncType> {
+    component
+        .imports()
+        .instance(&INTERFACE.try_into().ok()?)?
+        .func(func)
+}
+
+fn endpoint<T: Inner>(data: &T) -> anyhow::Result<Endpoint> {
+    data.bus()
+        .ok_or_else(|| anyhow::anyhow!("The bus is not available to this plugin"))
+}
+
+fn string(value: &Value) -> anyhow::Result<String> {
+    match value {
+        Value::String(s) => Ok(s.to_string()),
+        other => bail!("Incorrect input type, found {:?}", other),
+    }
+}
+
+/// Defines `component:plugin/bus`, on the [Endpoint] returned by [Inner::bus]. Subscriptions
+/// are kept in `table`, and subscribing to one hands out a `pollable_ty` from `wasi:io/poll`.
+pub(crate) fn add_to_linker<T: Inner + 'static>(
+    linker: &mut Linker,
+    store: &mut Store<T, runtime_layer::Engine>,
+    component: &Component,
+    table: &Arc<Mutex<ResourceTable>>,
+    pollable_ty: &ResourceType,
+    meter: &Meter,
+) -> anyhow::Result<()> {
+    // The message record is named, so link with the one the component was built against.
+    let next_ty = declared(component, "[method]subscription.next")
+        .and_then(|next| next.results().first().cloned())
+        .unwrap_or(ValueType::Option(OptionType::new(ValueType::Record(
+            message_type()?,
+        ))));
+    let ValueType::Option(next_option_ty) = next_ty.clone() else {
+        bail!(
+            "subscription.next should return an option, found {:?}",
+            next_ty
+        );
+    };
+    let ValueType::Record(message_ty) = next_option_ty.some_ty() else {
+        bail!(
+            "subscription.next should return a message, found {:?}",
+            next_ty
+        );
+    };
+
+    // Dropping the subscription in the guest unsubscribes
+    let table_clone = table.clone();
+    let subscription_ty = ResourceType::with_destructor(
+        &mut *store,
+        None,
+        move |_store, subscription: Resource<Subscription>| {
+            drop_resource(&table_clone, "subscription", subscription)
+        },
+    )?;
+
+    let bus_interface = linker.define_instance(INTERFACE.try_into()?)?;
+    bus_interface.define_resource("subscription", subscription_ty.clone())?;
+
+    // publish: func(topic: string, payload: string) -> result<u32, string>;
+    let publish_ty = ResultType::new(Some(ValueType::U32), Some(ValueType::String));
+    let meter_clone = meter.clone();
+    bus_interface.define_func(
+        "publish",
+        Func::new(
+            &mut *store,
+            FuncType::new(
+                [ValueType::String, ValueType::String],
+                [ValueType::Result(publish_ty.clone())],
+            ),
+            move |store, params, results| {
+                meter_clone.consume()?;
+                let topic = string(&params[0])?;
+                let payload = string(&params[1])?;
+
+                let result = match endpoint(store.data())?.publish(&topic, &payload) {
+                    Ok(delivered) => Ok(Some(Value::U32(delivered as u32))),
+                    Err(e) => Err(Some(Value::String(e.to_string().into()))),
+                };
+                results[0] = Value::Result(ResultValue::new(publish_ty.clone(), result)?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    // subscribe: func(topic: string) -> result<subscription, string>;
+    let subscribe_ty = ResultType::new(
+        Some(ValueType::Own(subscription_ty.clone())),
+        Some(ValueType::String),
+    );
+    let table_clone = table.clone();
+    let subscription_ty_clone = subscription_ty.clone();
+    let meter_clone = meter.clone();
+    bus_interface.define_func(
+        "subscribe",
+        Func::new(
+            &mut *store,
+            FuncType::new(
+                [ValueType::String],
+                [ValueType::Result(subscribe_ty.clone())],
+            ),
+            move |mut store, params, results| {
+                meter_clone.consume()?;
+                let topic = string(&params[0])?;
+
+                let result = match endpoint(store.data())?.subscribe(&topic) {
+                    Ok(subscription) => {
+                        let subscription = table_clone
+                            .lock()
+                            .unwrap()
+                            .push(subscription)
+                            .map_err(|e| charge_table_error(&meter_clone, e.into()))?;
+                        Ok(Some(Value::Own(ResourceOwn::new(
+                            &mut store,
+                            subscription,
+                            subscription_ty_clone.clone(),
+                        )?)))
+                    }
+                    Err(e) => Err(Some(Value::String(e.to_string().into()))),
+                };
+                results[0] = Value::Result(ResultValue::new(subscribe_ty.clone(), result)?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    // subscribe: func() -> pollable;
+    let table_clone = table.clone();
+    let pollable_ty_clone = pollable_ty.clone();
+    let meter_clone = meter.clone();
+    bus_interface.define_func(
+        "[method]subscription.subscribe",
+        Func::new(
+            &mut *store,
+            FuncType::new(
+                [ValueType::Borrow(subscription_ty.clone())],
+                [ValueType::Own(pollable_ty.clone())],
+            ),
+            move |mut store, params, results| {
+                meter_clone.consume()?;
+                let Value::Borrow(subscription) = &params[0] else {
+                    bail!("Incorrect input type, found {:?}", params[0]);
+                };
+                let borrowed = {
+                    let binding = store.as_context();
+                    let subscription: &Resource<Subscription> = subscription.rep(&binding)?;
+                    subscription.borrowed()
+                };
+
+                // borrowed, so dropping the pollable keeps the subscription
+                let pollable = subscribe(table_clone.clone(), borrowed)
+                    .map_err(|e| charge_table_error(&meter_clone, e))?;
+
+                results[0] = Value::Own(ResourceOwn::new(
+                    &mut store,
+                    pollable,
+                    pollable_ty_clone.clone(),
+                )?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    // next: func() -> option<message>;
+    let table_clone = table.clone();
+    let meter_clone = meter.clone();
+    bus_interface.define_func(
+        "[method]subscription.next",
+        Func::new(
+            &mut *store,
+            FuncType::new([ValueType::Borrow(subscription_ty)], [next_ty]),
+            move |store, params, results| {
+                meter_clone.consume()?;
+                let Value::Borrow(subscription) = &params[0] else {
+                    bail!("Incorrect input type, found {:?}", params[0]);
+                };
+                let binding = store.as_context();
+                let subscription: &Resource<Subscription> = subscription.rep(&binding)?;
+                let message = table_clone.lock().unwrap().get(subscription)?.next();
+
+                let message = message
+                    .map(|message| {
+                        Record::new(
+                            message_ty.clone(),
+                            [
+                                ("topic", Value::String(message.topic.into())),
+                                ("payload", Value::String(message.payload.into())),
+                                ("sender", Value::String(message.sender.into())),
+                            ],
+                        )
+                        .map(Value::Record)
+                    })
+                    .transpose()?;
+                results[0] = Value::Option(OptionValue::new(next_option_ty.clone(), message)?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::poll_once;
+
+    #[test]
+    fn test_patterns() {
+        let permissions = TopicPermissions::none().publish("user").subscribe("user/*");
+
+        assert!(permissions.can_publish("user"));
+        assert!(!permissions.can_publish("user/login"));
+        assert!(permissions.can_subscribe("user/login"));
+        assert!(!permissions.can_subscribe("user"));
+        assert!(!TopicPermissions::none().can_publish("user"));
+        assert!(TopicPermissions::none()
+            .subscribe("*")
+            .can_subscribe("anything"));
+    }
+
+    #[test]
+    fn test_routes_by_topic() {
+        let bus = Bus::new();
+        let login = bus.endpoint("login", TopicPermissions::none().publish("*"));
+        let todo = bus.endpoint("todo", TopicPermissions::none().subscribe("user"));
+        let other = bus.endpoint("other", TopicPermissions::none().subscribe("*"));
+
+        let user = todo.subscribe("user").unwrap();
+        let weather = other.subscribe("weather").unwrap();
+
+        assert_eq!(login.publish("user", "ada").unwrap(), 1);
+        assert_eq!(login.publish("nobody-listens", "").unwrap(), 0);
+
+        assert_eq!(
+            user.next(),
+            Some(Message {
+                topic: "user".to_string(),
+                payload: "ada".to_string(),
+                sender: "login".to_string(),
+            })
+        );
+        assert_eq!(user.next(), None);
+        assert_eq!(weather.next(), None);
+    }
+
+    #[test]
+    fn test_permissions_are_enforced() {
+        let bus = Bus::new();
+        let todo = bus.endpoint("todo", TopicPermissions::none().subscribe("user"));
+
+        assert!(matches!(
+            todo.publish("user", "mallory"),
+            Err(Error::TopicNotPermitted {
+                action: "publish",
+                ..
+            })
+        ));
+        assert!(matches!(
+            todo.subscribe("secrets"),
+            Err(Error::TopicNotPermitted {
+                action: "subscribe",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_dropping_unsubscribes() {
+        let bus = Bus::new();
+        let endpoint = bus.endpoint(
+            "plugin",
+            TopicPermissions::none().publish("*").subscribe("*"),
+        );
+
+        let subscription = endpoint.subscribe("topic").unwrap();
+        drop(subscription);
+
+        assert_eq!(endpoint.publish("topic", "hello").unwrap(), 0);
+        assert!(bus.topics.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_full_mailbox_drops_the_oldest() {
+        let bus = Bus::new();
+        let endpoint = bus.endpoint(
+            "plugin",
+            TopicPermissions::none().publish("*").subscribe("*"),
+        );
+        let subscription = endpoint.subscribe("topic").unwrap();
+
+        for i in 0..MAILBOX_CAPACITY + 1 {
+            endpoint.publish("topic", &i.to_string()).unwrap();
+        }
+
+        let messages = subscription.drain();
+        assert_eq!(messages.len(), MAILBOX_CAPACITY);
+        assert_eq!(messages[0].payload, "1");
+    }
+
+    #[test]
+    fn test_subscription_is_ready_with_a_message() {
+        let published = Arc::new(Mutex::new(0));
+        let published_clone = published.clone();
+        let bus = Bus::new().on_publish(move || *published_clone.lock().unwrap() += 1);
+        let endpoint = bus.endpoint(
+            "plugin",
+            TopicPermissions::none().publish("*").subscribe("*"),
+        );
+        let mut subscription = endpoint.subscribe("topic").unwrap();
+
+        assert!(poll_once(subscription.ready()).is_none());
+        endpoint.publish("topic", "hello").unwrap();
+        assert!(poll_once(subscription.ready()).is_some());
+        assert_eq!(*published.lock().unwrap(), 1);
+    }
+}
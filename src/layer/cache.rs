@@ -0,0 +1,214 @@
+//! Components kept on disk in the form the host runs them, keyed by a hash of their content.
+//!
+//! Before a component is compiled its memories are capped and its fuel metered, see
+//! [super::memory] and [super::fuel], which rewrite the core modules in it. [ComponentCache]
+//! keeps the rewritten component, so a plugin whose bytes, memory quota and metering haven't
+//! changed since the last start goes straight to compiling.
+//!
+//! Where the engine can serialize what it compiled, that is cached in the same directory too:
+//! wasmtime stores its machine code under `wasmtime/` and loads it back instead of compiling
+//! again. wasmi interprets the modules as they are, so there is nothing more to keep for it.
+//!
+//! What the cache can't skip, on either engine, is parsing and validating the component:
+//! `wasm_component_layer` does both in `Component::new` and has no way to load a component it
+//! checked before. On wasmi that is most of the remaining cost of a load, so there the time to
+//! load every plugin still grows with their number. The app keeps its startup flat by loading
+//! them lazily instead, over the first frames, see [RdxApp::load_pending].
+//!
+//! [RdxApp::load_pending]: crate::RdxApp::load_pending
+//!
+//! Entries are never stale, as a changed component hashes to a new key. Old ones only
+//! take up disk space, and [ComponentCache::clear] removes them.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+
+/// Where the rewritten components are kept, under the cache directory
+const COMPONENTS: &str = "components";
+
+/// Length of the checksum each entry ends with
+const CHECKSUM_LEN: usize = 32;
+
+/// A directory of pre-processed components, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct ComponentCache {
+    dir: PathBuf,
+}
+
+impl ComponentCache {
+    /// Caches components in `dir`, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, Error> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join(COMPONENTS))?;
+        Ok(Self { dir })
+    }
+
+    /// The cache directory
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The key `bytes` are cached under once their memories are capped at `max_memory` bytes,
+    /// and their fuel is metered if `metered`.
+    ///
+    /// The host's version is part of it, so an upgrade that changes the rewrite doesn't pick
+    /// up what an older one wrote.
+    pub fn key(bytes: &[u8], max_memory: Option<u64>, metered: bool) -> String {
+        let hash = Sha256::new()
+            .chain_update(env!("CARGO_PKG_VERSION"))
+            .chain_update([0])
+            .chain_update(max_memory.unwrap_or(0).to_le_bytes())
+            .chain_update([u8::from(max_memory.is_some()), u8::from(metered)])
+            .chain_update(bytes)
+            .finalize();
+        hash.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// The component cached under `key`, if there is one and it is intact.
+    ///
+    /// An entry that was cut short or damaged is removed, so it is written afresh.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.entry(key);
+        let mut bytes = fs::read(&path).ok()?;
+        let split = bytes.len().checked_sub(CHECKSUM_LEN);
+        match split {
+            Some(split) if Sha256::digest(&bytes[..split])[..] == bytes[split..] => {
+                bytes.truncate(split);
+                Some(bytes)
+            }
+            _ => {
+                tracing::warn!("Discarding damaged cache entry {}", path.display());
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    /// Caches `bytes` under `key`.
+    ///
+    /// The entry is written aside and then moved into place, so a host that crashes halfway
+    /// never leaves half an entry behind, and a concurrent [get](Self::get) sees all or none.
+    pub fn insert(&self, key: &str, bytes: &[u8]) -> Result<(), Error> {
+        let path = self.entry(key);
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        let mut contents = Vec::with_capacity(bytes.len() + CHECKSUM_LEN);
+        contents.extend_from_slice(bytes);
+        contents.extend_from_slice(&Sha256::digest(bytes));
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    /// The component cached under `key`, or else the one `process` makes, which is cached
+    /// for next time.
+    ///
+    /// Failing to write the entry isn't an error, the component is only made again next time.
+    pub fn get_or_insert_with(
+        &self,
+        key: &str,
+        process: impl FnOnce() -> Result<Vec<u8>, Error>,
+    ) -> Result<Vec<u8>, Error> {
+        if let Some(bytes) = self.get(key) {
+            tracing::debug!("Component cache hit {}", key);
+            return Ok(bytes);
+        }
+
+        let bytes = process()?;
+        if let Err(e) = self.insert(key, &bytes) {
+            tracing::warn!("Failed to cache component {}: {}", key, e);
+        }
+        Ok(bytes)
+    }
+
+    /// Removes every cached component, and whatever the engine cached.
+    pub fn clear(&self) -> Result<(), Error> {
+        fs::remove_dir_all(&self.dir)?;
+        fs::create_dir_all(self.dir.join(COMPONENTS))?;
+        Ok(())
+    }
+
+    /// Writes the file configuring wasmtime's own cache to keep its compiled code in the
+    /// cache directory, returning its path.
+    #[cfg(feature = "runtime-wasmtime")]
+    pub fn wasmtime_config(&self) -> Result<PathBuf, Error> {
+        let path = self.dir.join("wasmtime.toml");
+        // wasmtime reads strings as TOML basic strings, Debug escapes them the same way
+        let config = format!(
+            "[cache]\nenabled = true\ndirectory = {:?}\n",
+            self.dir.join("wasmtime")
+        );
+        if fs::read_to_string(&path).ok().as_deref() != Some(config.as_str()) {
+            fs::write(&path, config)?;
+        }
+        Ok(path)
+    }
+
+    fn entry(&self, key: &str) -> PathBuf {
+        self.dir.join(COMPONENTS).join(key).with_extension("wasm")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(name: &str) -> ComponentCache {
+        let dir = std::env::temp_dir().join(format!("rdx-cache-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        ComponentCache::new(dir).unwrap()
+    }
+
+    #[test]
+    fn test_key() {
+        let key = ComponentCache::key(b"\0asm", Some(1024), false);
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, ComponentCache::key(b"\0asm", Some(1024), false));
+        // the quota and metering change the rewrite, so they change the key
+        assert_ne!(key, ComponentCache::key(b"\0asm", Some(2048), false));
+        assert_ne!(key, ComponentCache::key(b"\0asm", None, false));
+        assert_ne!(key, ComponentCache::key(b"\0asm", Some(1024), true));
+        assert_ne!(key, ComponentCache::key(b"\0asm\x01", Some(1024), false));
+    }
+
+    #[test]
+    fn test_processed_once() {
+        let cache = cache("once");
+        let key = ComponentCache::key(b"component", Some(1024), false);
+        assert_eq!(cache.get(&key), None);
+
+        let processed = cache
+            .get_or_insert_with(&key, || Ok(b"processed".to_vec()))
+            .unwrap();
+        assert_eq!(processed, b"processed");
+
+        let cached = cache
+            .get_or_insert_with(&key, || panic!("should have been cached"))
+            .unwrap();
+        assert_eq!(cached, b"processed");
+
+        cache.clear().unwrap();
+        assert_eq!(cache.get(&key), None);
+        fs::remove_dir_all(cache.path()).unwrap();
+    }
+
+    #[test]
+    fn test_damaged_entry_is_discarded() {
+        let cache = cache("damaged");
+        let key = ComponentCache::key(b"component", Some(1024), false);
+        cache.insert(&key, b"processed").unwrap();
+
+        let mut entry = fs::read(cache.entry(&key)).unwrap();
+        entry[0] ^= 0xff;
+        fs::write(cache.entry(&key), entry).unwrap();
+        assert_eq!(cache.get(&key), None);
+        assert!(!cache.entry(&key).exists());
+
+        // an entry too short to hold a checksum
+        fs::write(cache.entry(&key), b"short").unwrap();
+        assert_eq!(cache.get(&key), None);
+        fs::remove_dir_all(cache.path()).unwrap();
+    }
+}
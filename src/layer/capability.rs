@@ -0,0 +1,271 @@
+//! Capability-based permissions for host imports.
+//!
+//! Each plugin is granted a set of [Capabilities]. The linker only defines the imports a
+//! plugin was granted, and [check] refuses to instantiate a component that imports anything
+//! it wasn't granted, naming the missing [Capability].
+//!
+//! A plugin with a [Manifest](super::manifest::Manifest) is granted exactly the capabilities
+//! it declares, see [from_manifest].
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use wasm_component_layer::Component;
+
+use crate::Error;
+
+/// A group of host imports that can be granted to a plugin.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub enum Capability {
+    /// `log` and `wasi:logging`: write messages to the host log
+    Log,
+    /// `emit`: set variables in the plugin's Rhai scope
+    Emit,
+    /// `random-byte` and `wasi:random`: host randomness
+    Random,
+    /// `now`, `subscribe-duration` and `wasi:clocks`: read the clock and set timers
+    Clock,
+    /// `component:plugin/storage`: persistent key/value storage
+    Storage,
+    /// `component:plugin/http`: outgoing requests, to the plugin's allowed origins only
+    Http,
+    /// `component:plugin/bus`: messages to and from other plugins, on permitted topics only
+    Bus,
+}
+
+impl Capability {
+    /// Every capability the host knows about.
+    pub const ALL: [Capability; 7] = [
+        Capability::Log,
+        Capability::Emit,
+        Capability::Random,
+        Capability::Clock,
+        Capability::Storage,
+        Capability::Http,
+        Capability::Bus,
+    ];
+
+    /// The capability required to import `func` from `interface`, or `None` if the import
+    /// doesn't need one (such as `wasi:io/poll`) or isn't provided by the host at all.
+    pub fn for_import(interface: &str, func: &str) -> Option<Capability> {
+        // ignore the version, if any
+        let interface = interface.split('@').next().unwrap_or(interface);
+        match (interface, func) {
+            ("component:plugin/host", "log") => Some(Capability::Log),
+            ("wasi:logging/logging" | "component:plugin/logging", _) => Some(Capability::Log),
+            ("component:plugin/host", "emit") => Some(Capability::Emit),
+            ("component:plugin/host", "random-byte") => Some(Capability::Random),
+            ("wasi:random/random" | "wasi:random/insecure-seed", _) => Some(Capability::Random),
+            ("component:plugin/host", "now" | "subscribe-duration") => Some(Capability::Clock),
+            ("wasi:clocks/wall-clock" | "wasi:clocks/monotonic-clock", _) => {
+                Some(Capability::Clock)
+            }
+            ("component:plugin/storage", _) => Some(Capability::Storage),
+            ("component:plugin/http", _) => Some(Capability::Http),
+            ("component:plugin/bus", _) => Some(Capability::Bus),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Capability::Log => "log",
+            Capability::Emit => "emit",
+            Capability::Random => "random",
+            Capability::Clock => "clock",
+            Capability::Storage => "storage",
+            Capability::Http => "http",
+            Capability::Bus => "bus",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Capability {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Capability::ALL
+            .into_iter()
+            .find(|capability| capability.to_string() == s)
+            .ok_or_else(|| Error::Parse(format!("Unknown capability: {s}")))
+    }
+}
+
+/// The set of [Capability]s granted to a plugin.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Capabilities(BTreeSet<Capability>);
+
+impl Capabilities {
+    /// No capabilities at all. The plugin can only use imports that need none.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Every capability.
+    pub fn all() -> Self {
+        Capability::ALL.into_iter().collect()
+    }
+
+    /// Adds the capability to this set.
+    pub fn grant(mut self, capability: Capability) -> Self {
+        self.0.insert(capability);
+        self
+    }
+
+    /// Whether the capability has been granted.
+    pub fn contains(&self, capability: Capability) -> bool {
+        self.0.contains(&capability)
+    }
+
+    /// Iterates over the granted capabilities.
+    pub fn iter(&self) -> impl Iterator<Item = Capability> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+impl FromIterator<Capability> for Capabilities {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// Every import of the component that needs a capability, as `(capability, "interface#func")`.
+fn imports(component: &Component) -> Vec<(Capability, String)> {
+    component
+        .imports()
+        .instances()
+        .flat_map(|(interface, instance)| {
+            let interface = interface.to_string();
+            instance
+                .funcs()
+                .filter_map(|(func, _ty)| {
+                    Capability::for_import(&interface, func)
+                        .map(|capability| (capability, format!("{interface}#{func}")))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The capabilities the component needs, so it can be audited before it is run.
+pub fn required(component: &Component) -> Capabilities {
+    imports(component)
+        .into_iter()
+        .map(|(capability, _)| capability)
+        .collect()
+}
+
+/// Checks that every import of the component which needs a capability has been granted.
+pub fn check(component: &Component, granted: &Capabilities) -> Result<(), Error> {
+    match imports(component)
+        .into_iter()
+        .find(|(capability, _)| !granted.contains(*capability))
+    {
+        Some((capability, import)) => Err(Error::CapabilityNotGranted { capability, import }),
+        None => Ok(()),
+    }
+}
+
+/// The capabilities to grant a plugin whose manifest `declared` them: exactly those.
+///
+/// Fails unless they are the very ones the component's imports need, as a manifest that says
+/// more or less than the component does describes another plugin, and unless the host
+/// `granted` every one of them.
+pub fn from_manifest(
+    component: &Component,
+    declared: &[String],
+    granted: &Capabilities,
+) -> Result<Capabilities, Error> {
+    let declared = declared
+        .iter()
+        .map(|name| name.parse())
+        .collect::<Result<Capabilities, Error>>()?;
+
+    let imports = imports(component);
+    if let Some((capability, import)) = imports.iter().find(|(c, _)| !declared.contains(*c)) {
+        return Err(Error::CapabilityMismatch(format!(
+            "{import} needs `{capability}`, which the manifest doesn't declare"
+        )));
+    }
+    if let Some(capability) = declared
+        .iter()
+        .find(|c| !imports.iter().any(|(imported, _)| imported == c))
+    {
+        return Err(Error::CapabilityMismatch(format!(
+            "the manifest declares `{capability}`, but nothing imports it"
+        )));
+    }
+
+    // every declared capability is imported, so this names the import that needs it
+    match imports
+        .into_iter()
+        .find(|(capability, _)| !granted.contains(*capability))
+    {
+        Some((capability, import)) => Err(Error::CapabilityNotGranted { capability, import }),
+        None => Ok(declared),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_names() {
+        let names = Capability::ALL.map(|capability| capability.to_string());
+        assert_eq!(names, crate::layer::manifest::CAPABILITIES);
+    }
+
+    #[test]
+    fn test_for_import() {
+        assert_eq!(
+            Capability::for_import("component:plugin/host", "emit"),
+            Some(Capability::Emit)
+        );
+        assert_eq!(
+            Capability::for_import("component:plugin/storage@0.1.0", "get"),
+            Some(Capability::Storage)
+        );
+        assert_eq!(
+            Capability::for_import("wasi:clocks/monotonic-clock@0.2.2", "subscribe-instant"),
+            Some(Capability::Clock)
+        );
+        assert_eq!(
+            Capability::for_import("wasi:random/random@0.2.2", "get-random-bytes"),
+            Some(Capability::Random)
+        );
+        assert_eq!(
+            Capability::for_import("component:plugin/http", "fetch"),
+            Some(Capability::Http)
+        );
+        assert_eq!(
+            Capability::for_import("component:plugin/bus", "publish"),
+            Some(Capability::Bus)
+        );
+        assert_eq!(Capability::for_import("wasi:io/poll@0.2.2", "poll"), None);
+    }
+
+    #[test]
+    fn test_display_roundtrip() {
+        for capability in Capability::ALL {
+            assert_eq!(
+                capability.to_string().parse::<Capability>().unwrap(),
+                capability
+            );
+        }
+        assert!("network".parse::<Capability>().is_err());
+    }
+
+    #[test]
+    fn test_grants() {
+        let granted = Capabilities::none().grant(Capability::Emit);
+        assert!(granted.contains(Capability::Emit));
+        assert!(!granted.contains(Capability::Random));
+        assert_eq!(Capabilities::all().iter().count(), Capability::ALL.len());
+    }
+}
@@ -0,0 +1,235 @@
+//! The standard `wasi:clocks` interfaces, so off-the-shelf components can tell the time and
+//! sleep.
+//!
+//! ```wit
+//! interface wall-clock {
+//!   record datetime { seconds: u64, nanoseconds: u32 }
+//!   now: func() -> datetime;
+//!   resolution: func() -> datetime;
+//! }
+//!
+//! interface monotonic-clock {
+//!   type instant = u64;
+//!   type duration = u64;
+//!   now: func() -> instant;
+//!   resolution: func() -> duration;
+//!   subscribe-instant: func(when: instant) -> pollable;
+//!   subscribe-duration: func(when: duration) -> pollable;
+//! }
+//! ```
+//!
+//! Monotonic instants are nanoseconds since the host started. The pollables are the same
+//! [Deadline]s as the custom `component:plugin/host.subscribe-duration` hands out.
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::bail;
+use wasm_component_layer::{
+    Component, Func, FuncType, Linker, Record, RecordType, ResourceOwn, ResourceType, Store, Value,
+    ValueType,
+};
+
+use super::limits::Meter;
+use super::{
+    charge_table_error, imported, runtime_layer, subscribe, subscribe_to_duration, Deadline,
+    Duration, Inner, Instant, Pollable, Resource, ResourceTable, SystemTime,
+};
+
+/// The `wasi:clocks/wall-clock` interface
+pub const WALL_CLOCK: &str = "wasi:clocks/wall-clock@0.2.2";
+
+/// The `wasi:clocks/monotonic-clock` interface
+pub const MONOTONIC_CLOCK: &str = "wasi:clocks/monotonic-clock@0.2.2";
+
+/// Nanosecond resolution, as reported by both clocks
+const RESOLUTION_NANOS: u64 = 1;
+
+/// The instant monotonic-clock counts from.
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// Nanoseconds since [epoch], saturating at `u64::MAX`.
+fn monotonic_now() -> u64 {
+    epoch().elapsed().as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+/// A pollable that is ready once the monotonic clock reaches `when`.
+fn subscribe_to_instant(
+    table: Arc<Mutex<ResourceTable>>,
+    when: u64,
+) -> anyhow::Result<Resource<Pollable>> {
+    let deadline = match epoch().checked_add(Duration::from_nanos(when)) {
+        Some(instant) if instant <= Instant::now() => Deadline::Past,
+        Some(instant) => Deadline::Instant(instant),
+        None => Deadline::Never,
+    };
+    let deadline = table.lock().unwrap().push(deadline)?;
+    subscribe(table, deadline)
+}
+
+/// Defines `wasi:clocks/wall-clock` and `wasi:clocks/monotonic-clock`. Pollables are kept in
+/// `table`, as `pollable_ty` from `wasi:io/poll`.
+pub(crate) fn add_to_linker<T: Inner + 'static>(
+    linker: &mut Linker,
+    store: &mut Store<T, runtime_layer::Engine>,
+    component: &Component,
+    table: &Arc<Mutex<ResourceTable>>,
+    pollable_ty: &ResourceType,
+    meter: &Meter,
+) -> anyhow::Result<()> {
+    // Use the component's own `datetime`, if it imports one, so the types line up exactly.
+    let datetime_ty = imported(component, WALL_CLOCK)
+        .and_then(|wall_clock| wall_clock.func("now"))
+        .and_then(|now| now.results().first().cloned())
+        .unwrap_or(ValueType::Record(RecordType::new(
+            None,
+            [("seconds", ValueType::U64), ("nanoseconds", ValueType::U32)],
+        )?));
+    let ValueType::Record(datetime_record) = datetime_ty.clone() else {
+        bail!(
+            "wall-clock datetime should be a record, found {:?}",
+            datetime_ty
+        );
+    };
+
+    let wall_clock = linker.define_instance(WALL_CLOCK.try_into()?)?;
+
+    // now: func() -> datetime;
+    let meter_clone = meter.clone();
+    let record = datetime_record.clone();
+    wall_clock.define_func(
+        "now",
+        Func::new(
+            &mut *store,
+            FuncType::new([], [datetime_ty.clone()]),
+            move |_store, _params, results| {
+                meter_clone.consume()?;
+                let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+                results[0] = Value::Record(Record::new(
+                    record.clone(),
+                    [
+                        ("seconds", Value::U64(since_epoch.as_secs())),
+                        ("nanoseconds", Value::U32(since_epoch.subsec_nanos())),
+                    ],
+                )?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    // resolution: func() -> datetime;
+    let meter_clone = meter.clone();
+    let record = datetime_record;
+    wall_clock.define_func(
+        "resolution",
+        Func::new(
+            &mut *store,
+            FuncType::new([], [datetime_ty]),
+            move |_store, _params, results| {
+                meter_clone.consume()?;
+                results[0] = Value::Record(Record::new(
+                    record.clone(),
+                    [
+                        ("seconds", Value::U64(0)),
+                        ("nanoseconds", Value::U32(RESOLUTION_NANOS as u32)),
+                    ],
+                )?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    let monotonic_clock = linker.define_instance(MONOTONIC_CLOCK.try_into()?)?;
+
+    // now: func() -> instant;
+    let meter_clone = meter.clone();
+    monotonic_clock.define_func(
+        "now",
+        Func::new(
+            &mut *store,
+            FuncType::new([], [ValueType::U64]),
+            move |_store, _params, results| {
+                meter_clone.consume()?;
+                results[0] = Value::U64(monotonic_now());
+                Ok(())
+            },
+        ),
+    )?;
+
+    // resolution: func() -> duration;
+    let meter_clone = meter.clone();
+    monotonic_clock.define_func(
+        "resolution",
+        Func::new(
+            &mut *store,
+            FuncType::new([], [ValueType::U64]),
+            move |_store, _params, results| {
+                meter_clone.consume()?;
+                results[0] = Value::U64(RESOLUTION_NANOS);
+                Ok(())
+            },
+        ),
+    )?;
+
+    // subscribe-instant: func(when: instant) -> pollable;
+    // subscribe-duration: func(when: duration) -> pollable;
+    for name in ["subscribe-instant", "subscribe-duration"] {
+        let table = table.clone();
+        let pollable_ty_clone = pollable_ty.clone();
+        let meter_clone = meter.clone();
+        monotonic_clock.define_func(
+            name,
+            Func::new(
+                &mut *store,
+                FuncType::new([ValueType::U64], [ValueType::Own(pollable_ty.clone())]),
+                move |mut store, params, results| {
+                    meter_clone.consume()?;
+                    let Value::U64(when) = params[0] else {
+                        bail!("Incorrect input type, found {:?}", params[0]);
+                    };
+
+                    let pollable = match name {
+                        "subscribe-instant" => subscribe_to_instant(table.clone(), when),
+                        _ => subscribe_to_duration(table.clone(), Duration::from_nanos(when)),
+                    }
+                    .map_err(|e| charge_table_error(&meter_clone, e))?;
+
+                    results[0] = Value::Own(ResourceOwn::new(
+                        &mut store,
+                        pollable,
+                        pollable_ty_clone.clone(),
+                    )?);
+                    Ok(())
+                },
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monotonic_now_increases() {
+        let before = monotonic_now();
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(monotonic_now() > before);
+    }
+
+    #[test]
+    fn test_subscribe_to_past_instant() {
+        let table = Arc::new(Mutex::new(ResourceTable::new()));
+        let pollable = subscribe_to_instant(table.clone(), 0).unwrap();
+
+        let table = table.lock().unwrap();
+        let index = table.get(&pollable).unwrap().index;
+        assert!(matches!(
+            table.get(&Resource::<Deadline>::new_index(index)),
+            Ok(Deadline::Past)
+        ));
+    }
+}
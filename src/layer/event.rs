@@ -0,0 +1,165 @@
+//! Conversion of `component:plugin/types.event` payloads into [rhai::Dynamic] values.
+//!
+//! Older plugins declare the event `value` as a plain `string`, newer ones use the
+//! `value` variant from `wit/imp.wit`:
+//!
+//! ```wit
+//! variant value {
+//!   boolean(bool),
+//!   integer(s64),
+//!   float(f64),
+//!   text(string),
+//!   %list(list<string>),
+//!   json(string),
+//! }
+//! ```
+//!
+//! Both shapes are accepted, so existing string-only plugins keep working.
+use anyhow::{bail, Result};
+use rhai::Dynamic;
+use wasm_component_layer::{Record, RecordType, Value, ValueType};
+
+use super::guest_resource::GuestResources;
+use crate::rdx::convert::value_to_dynamic;
+
+/// The name of the variant case whose string payload is parsed as JSON.
+const JSON_CASE: &str = "json";
+
+/// The `event` record as declared by plugins built before typed values existed.
+pub fn legacy_event_type() -> ValueType {
+    ValueType::Record(
+        RecordType::new(
+            None,
+            vec![("name", ValueType::String), ("value", ValueType::String)],
+        )
+        .expect("legacy event record is valid"),
+    )
+}
+
+/// Splits an `event` record into its name and its value converted to [Dynamic].
+pub fn from_record(record: &Record) -> Result<(String, Dynamic)> {
+    let Some(Value::String(name)) = record.field("name") else {
+        bail!("Event is missing a string `name` field");
+    };
+    let Some(value) = record.field("value") else {
+        bail!("Event {name} is missing a `value` field");
+    };
+    Ok((name.to_string(), to_dynamic(&value)?))
+}
+
+/// Converts an event `value` into the matching [Dynamic], so Rhai scripts
+/// can do arithmetic and comparisons on real types.
+///
+/// Payloads convert the same way as export results, see [value_to_dynamic], except for the
+/// `json` case, which is parsed.
+pub fn to_dynamic(value: &Value) -> Result<Dynamic> {
+    // event values carry no resources, so there is nothing to track them in
+    let resources = GuestResources::default();
+    match value {
+        Value::Variant(variant) => {
+            let ty = variant.ty();
+            let Some(case) = ty.cases().get(variant.discriminant()) else {
+                bail!(
+                    "Invalid event value discriminant {}",
+                    variant.discriminant()
+                );
+            };
+            match (case.name(), variant.value()) {
+                (JSON_CASE, Some(Value::String(json))) => parse_json(&json),
+                (_, Some(payload)) => Ok(value_to_dynamic(payload, &resources)),
+                (_, None) => Ok(Dynamic::UNIT),
+            }
+        }
+        other => Ok(value_to_dynamic(other.clone(), &resources)),
+    }
+}
+
+/// Parses a JSON document into a [Dynamic]. Objects become Rhai maps, arrays become Rhai arrays.
+fn parse_json(json: &str) -> Result<Dynamic> {
+    let json: serde_json::Value = serde_json::from_str(json)?;
+    rhai::serde::to_dynamic(json).map_err(|e| anyhow::anyhow!("{e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_component_layer::{List, ListType, Variant, VariantCase, VariantType};
+
+    fn value_type() -> VariantType {
+        VariantType::new(
+            None,
+            [
+                VariantCase::new("boolean", Some(ValueType::Bool)),
+                VariantCase::new("integer", Some(ValueType::S64)),
+                VariantCase::new("float", Some(ValueType::F64)),
+                VariantCase::new("text", Some(ValueType::String)),
+                VariantCase::new(
+                    "list",
+                    Some(ValueType::List(ListType::new(ValueType::String))),
+                ),
+                VariantCase::new("json", Some(ValueType::String)),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn case(discriminant: usize, payload: Value) -> Value {
+        Value::Variant(Variant::new(value_type(), discriminant, Some(payload)).unwrap())
+    }
+
+    #[test]
+    fn test_legacy_string_value() {
+        let value = to_dynamic(&Value::String("0".into())).unwrap();
+        assert_eq!(value.into_string().unwrap(), "0");
+    }
+
+    #[test]
+    fn test_typed_values() {
+        assert!(to_dynamic(&case(0, Value::Bool(true)))
+            .unwrap()
+            .as_bool()
+            .unwrap());
+        assert_eq!(
+            to_dynamic(&case(1, Value::S64(42)))
+                .unwrap()
+                .as_int()
+                .unwrap(),
+            42
+        );
+        assert_eq!(
+            to_dynamic(&case(2, Value::F64(1.5)))
+                .unwrap()
+                .as_float()
+                .unwrap(),
+            1.5
+        );
+        assert_eq!(
+            to_dynamic(&case(3, Value::String("hi".into())))
+                .unwrap()
+                .into_string()
+                .unwrap(),
+            "hi"
+        );
+
+        let list = List::new(
+            ListType::new(ValueType::String),
+            [Value::String("a".into()), Value::String("b".into())],
+        )
+        .unwrap();
+        let array = to_dynamic(&case(4, Value::List(list)))
+            .unwrap()
+            .into_array()
+            .unwrap();
+        assert_eq!(array.len(), 2);
+    }
+
+    #[test]
+    fn test_json_value() {
+        let map = to_dynamic(&case(5, Value::String(r#"{"a": 1, "b": [true]}"#.into())))
+            .unwrap()
+            .try_cast::<rhai::Map>()
+            .unwrap();
+        assert_eq!(map["a"].as_int().unwrap(), 1);
+        assert!(map["b"].clone().into_array().unwrap()[0].as_bool().unwrap());
+    }
+}
@@ -0,0 +1,404 @@
+//! Fuel for calls into a plugin, metered inside the guest's own code.
+//!
+//! `wasm_component_layer` doesn't give the host the engine's store, so neither wasmi's fuel
+//! nor wasmtime's fuel or epochs can be set on it. Instead [instrument] rewrites the
+//! component before it is compiled: every core module that could run forever, as it has a
+//! loop or makes tail calls, gets a fuel counter in a global of its own. One unit is burnt
+//! each time round a loop and on every tail call, and the guest traps on `unreachable` once
+//! the counter drops below zero. A spinning guest is stopped that way even if it never calls
+//! back into the host, on every engine including the browser's.
+//!
+//! Each counter is exported from its module, and from the component as `rdx-fuel-i<n>`: a
+//! function that sets the counter and returns what was left of it. [Tanks] fills them all
+//! before a call, and a call that trapped with a counter below zero failed with
+//! [Error::BudgetExceeded].
+//!
+//! [Error::BudgetExceeded]: crate::Error::BudgetExceeded
+use std::convert::Infallible;
+
+use wasm_encoder::reencode::{self, Reencode};
+use wasm_encoder::{
+    ConstExpr, ExportKind, ExportSection, Function, GlobalSection, GlobalType, Instruction,
+    PrimitiveValType, SectionId, ValType,
+};
+use wasmparser::{Operator, Parser, Payload, TypeRef};
+
+use wasm_component_layer::AsContextMut;
+
+use super::instrument::{position, rewrite_modules, Hook};
+use super::{Func, Instance, Value};
+use crate::Error;
+
+/// What the counter is exported as from an instrumented core module
+const MODULE_EXPORT: &str = "rdx-fuel";
+
+/// What the counter of the `n`th instrumented instance is exported as from the component
+fn component_export(n: usize) -> String {
+    format!("rdx-fuel-i{n}")
+}
+
+/// Rewrites the component so each call can be given a fixed amount of fuel, see the
+/// [module docs](self).
+///
+/// Components nested inside the plugin's can't be reached by [Tanks], so one with core
+/// modules of its own isn't metered but refused.
+pub fn instrument(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let hook = Hook {
+        module_export: MODULE_EXPORT,
+        component_export,
+        // fuel: func(fuel: s64) -> s64
+        ty: |types| {
+            types
+                .function()
+                .params([("fuel", PrimitiveValType::S64)])
+                .result(PrimitiveValType::S64);
+        },
+    };
+    let nested = |component: &[u8]| {
+        // such as the shims tooling wraps exports in, which only adapt types
+        if runs_code(component)? {
+            return Err(malformed("fuel can't be metered in nested components"));
+        }
+        Ok(())
+    };
+    rewrite_modules(bytes, meter_module, nested, &hook)
+}
+
+/// Whether a nested component has core modules of its own, at any depth.
+fn runs_code(component: &[u8]) -> Result<bool, Error> {
+    for payload in Parser::new(0).parse_all(component) {
+        let payload = payload.map_err(|e| Error::ComponentDecode(e.into()))?;
+        if matches!(payload, Payload::ModuleSection { .. }) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Where the counter and its function go in a core module's index spaces
+#[derive(Debug, Default)]
+struct Counter {
+    global: u32,
+    func: u32,
+    ty: u32,
+    /// Whether the module's own global and export sections have been seen yet
+    globals_written: bool,
+    exports_written: bool,
+}
+
+/// Adds a fuel counter to a core module that could run forever, or returns `None` for one
+/// that always finishes by itself.
+fn meter_module(module: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let mut counter = Counter::default();
+    let mut needs_fuel = false;
+    for payload in Parser::new(0).parse_all(module) {
+        match payload.map_err(|e| Error::ComponentDecode(e.into()))? {
+            Payload::TypeSection(section) => {
+                for group in section {
+                    let group = group.map_err(|e| Error::ComponentDecode(e.into()))?;
+                    counter.ty += group.types().len() as u32;
+                }
+            }
+            Payload::ImportSection(section) => {
+                for import in section {
+                    match import.map_err(|e| Error::ComponentDecode(e.into()))?.ty {
+                        TypeRef::Func(_) => counter.func += 1,
+                        TypeRef::Global(_) => counter.global += 1,
+                        _ => {}
+                    }
+                }
+            }
+            Payload::FunctionSection(section) => counter.func += section.count(),
+            Payload::GlobalSection(section) => counter.global += section.count(),
+            Payload::CodeSectionEntry(body) if !needs_fuel => {
+                let mut operators = body
+                    .get_operators_reader()
+                    .map_err(|e| Error::ComponentDecode(e.into()))?;
+                while !operators.eof() {
+                    let op = operators
+                        .read()
+                        .map_err(|e| Error::ComponentDecode(e.into()))?;
+                    if burns_fuel(&op) {
+                        needs_fuel = true;
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !needs_fuel {
+        return Ok(None);
+    }
+
+    let mut metered = wasm_encoder::Module::new();
+    counter
+        .parse_core_module(&mut metered, Parser::new(0), module)
+        .map_err(|e| Error::ComponentDecode(e.into()))?;
+    Ok(Some(metered.finish()))
+}
+
+/// Whether the guest burns fuel on this instruction. Every other way of running forever
+/// recurses, which overflows the stack.
+fn burns_fuel(op: &Operator) -> bool {
+    matches!(
+        op,
+        Operator::Loop { .. }
+            | Operator::ReturnCall { .. }
+            | Operator::ReturnCallIndirect { .. }
+            | Operator::ReturnCallRef { .. }
+    )
+}
+
+impl Counter {
+    fn counter_global(&self) -> (GlobalType, ConstExpr) {
+        let ty = GlobalType {
+            val_type: ValType::I64,
+            mutable: true,
+            shared: false,
+        };
+        (ty, ConstExpr::i64_const(0))
+    }
+
+    /// Burns a unit of fuel, trapping if there was none left.
+    fn burn(&self, f: &mut Function) {
+        f.instruction(&Instruction::GlobalGet(self.global))
+            .instruction(&Instruction::I64Const(1))
+            .instruction(&Instruction::I64Sub)
+            .instruction(&Instruction::GlobalSet(self.global))
+            .instruction(&Instruction::GlobalGet(self.global))
+            .instruction(&Instruction::I64Const(0))
+            .instruction(&Instruction::I64LtS)
+            .instruction(&Instruction::If(wasm_encoder::BlockType::Empty))
+            .instruction(&Instruction::Unreachable)
+            .instruction(&Instruction::End);
+    }
+}
+
+impl Reencode for Counter {
+    type Error = Infallible;
+
+    fn parse_type_section(
+        &mut self,
+        types: &mut wasm_encoder::TypeSection,
+        section: wasmparser::TypeSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_type_section(self, types, section)?;
+        types.ty().function([ValType::I64], [ValType::I64]);
+        Ok(())
+    }
+
+    fn parse_function_section(
+        &mut self,
+        functions: &mut wasm_encoder::FunctionSection,
+        section: wasmparser::FunctionSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_function_section(self, functions, section)?;
+        functions.function(self.ty);
+        Ok(())
+    }
+
+    fn parse_global_section(
+        &mut self,
+        globals: &mut GlobalSection,
+        section: wasmparser::GlobalSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_global_section(self, globals, section)?;
+        let (ty, init) = self.counter_global();
+        globals.global(ty, &init);
+        self.globals_written = true;
+        Ok(())
+    }
+
+    fn parse_export_section(
+        &mut self,
+        exports: &mut ExportSection,
+        section: wasmparser::ExportSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_export_section(self, exports, section)?;
+        exports.export(MODULE_EXPORT, ExportKind::Func, self.func);
+        self.exports_written = true;
+        Ok(())
+    }
+
+    /// Adds the global and export sections where the module has none.
+    fn intersperse_section_hook(
+        &mut self,
+        module: &mut wasm_encoder::Module,
+        _after: Option<SectionId>,
+        before: Option<SectionId>,
+    ) -> Result<(), reencode::Error> {
+        let before = position(before);
+        if !self.globals_written && before > position(Some(SectionId::Global)) {
+            let mut globals = GlobalSection::new();
+            let (ty, init) = self.counter_global();
+            globals.global(ty, &init);
+            module.section(&globals);
+            self.globals_written = true;
+        }
+        if !self.exports_written && before > position(Some(SectionId::Export)) {
+            let mut exports = ExportSection::new();
+            exports.export(MODULE_EXPORT, ExportKind::Func, self.func);
+            module.section(&exports);
+            self.exports_written = true;
+        }
+        Ok(())
+    }
+
+    fn parse_code_section(
+        &mut self,
+        code: &mut wasm_encoder::CodeSection,
+        section: wasmparser::CodeSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_code_section(self, code, section)?;
+
+        // sets the counter, returning what was left
+        let mut swap = Function::new([]);
+        swap.instruction(&Instruction::GlobalGet(self.global))
+            .instruction(&Instruction::LocalGet(0))
+            .instruction(&Instruction::GlobalSet(self.global))
+            .instruction(&Instruction::End);
+        code.function(&swap);
+        Ok(())
+    }
+
+    fn parse_function_body(
+        &mut self,
+        code: &mut wasm_encoder::CodeSection,
+        func: wasmparser::FunctionBody<'_>,
+    ) -> Result<(), reencode::Error> {
+        let mut f = self.new_function_with_parsed_locals(&func)?;
+        let mut reader = func.get_operators_reader()?;
+        while !reader.eof() {
+            let op = reader.read()?;
+            match op {
+                // at the top of every iteration
+                Operator::Loop { .. } => {
+                    f.instruction(&self.instruction(op)?);
+                    self.burn(&mut f);
+                }
+                // before leaving for good
+                _ if burns_fuel(&op) => {
+                    self.burn(&mut f);
+                    f.instruction(&self.instruction(op)?);
+                }
+                _ => {
+                    f.instruction(&self.instruction(op)?);
+                }
+            }
+        }
+        code.function(&f);
+        Ok(())
+    }
+}
+
+fn malformed(reason: &str) -> Error {
+    Error::ComponentDecode(anyhow::anyhow!("can't meter fuel: {reason}"))
+}
+
+/// The fuel counters of an instance made from an [instrument]ed component.
+#[derive(Debug, Clone, Default)]
+pub struct Tanks(Vec<Func>);
+
+impl Tanks {
+    /// Finds the counters the instance exports. A component that wasn't instrumented has none.
+    pub fn new(instance: &Instance) -> Self {
+        let root = instance.exports().root();
+        Self(
+            (0..)
+                .map_while(|n| root.func(component_export(n)))
+                .collect(),
+        )
+    }
+
+    /// Fills every counter with `fuel`, returning whether any of them had run dry.
+    pub fn fill(&self, mut ctx: impl AsContextMut, fuel: u64) -> Result<bool, Error> {
+        let fuel = i64::try_from(fuel).unwrap_or(i64::MAX);
+        let mut dry = false;
+        for counter in &self.0 {
+            let mut left = [Value::S64(0)];
+            counter.call(&mut ctx, &[Value::S64(fuel)], &mut left)?;
+            dry |= matches!(left[0], Value::S64(left) if left < 0);
+        }
+        Ok(dry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmparser::Validator;
+
+    /// A core module with a function that spins forever, and one that returns right away.
+    fn spinning_module() -> wasm_encoder::Module {
+        let mut module = wasm_encoder::Module::new();
+        let mut types = wasm_encoder::TypeSection::new();
+        types.ty().function([], []);
+        module.section(&types);
+        let mut functions = wasm_encoder::FunctionSection::new();
+        functions.function(0).function(0);
+        module.section(&functions);
+        let mut exports = ExportSection::new();
+        exports.export("spin", ExportKind::Func, 0);
+        module.section(&exports);
+        let mut code = wasm_encoder::CodeSection::new();
+        let mut spin = Function::new([]);
+        spin.instruction(&Instruction::Loop(wasm_encoder::BlockType::Empty))
+            .instruction(&Instruction::Br(0))
+            .instruction(&Instruction::End)
+            .instruction(&Instruction::End);
+        code.function(&spin);
+        let mut nothing = Function::new([]);
+        nothing.instruction(&Instruction::End);
+        code.function(&nothing);
+        module.section(&code);
+        module
+    }
+
+    #[test]
+    fn test_meters_loops() {
+        let module = spinning_module().finish();
+        let metered = meter_module(&module).unwrap().unwrap();
+        Validator::new().validate_all(&metered).unwrap();
+
+        let exports = Parser::new(0)
+            .parse_all(&metered)
+            .find_map(|payload| match payload.unwrap() {
+                Payload::ExportSection(section) => Some(section),
+                _ => None,
+            })
+            .unwrap();
+        let names = exports
+            .into_iter()
+            .map(|export| export.unwrap().name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["spin", MODULE_EXPORT]);
+    }
+
+    #[test]
+    fn test_leaves_finite_modules() {
+        let mut module = wasm_encoder::Module::new();
+        let mut types = wasm_encoder::TypeSection::new();
+        types.ty().function([], []);
+        module.section(&types);
+        let mut functions = wasm_encoder::FunctionSection::new();
+        functions.function(0);
+        module.section(&functions);
+        let mut code = wasm_encoder::CodeSection::new();
+        let mut nothing = Function::new([]);
+        nothing.instruction(&Instruction::End);
+        code.function(&nothing);
+        module.section(&code);
+
+        assert!(meter_module(&module.finish()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(matches!(
+            instrument(b"not a component"),
+            Err(Error::ComponentDecode(_))
+        ));
+    }
+}
@@ -0,0 +1,332 @@
+//! Resources the guest defines, a document, a connection or a cursor, handed to scripts as
+//! opaque [ResourceHandle]s.
+//!
+//! An `own` an export returns is kept in the plugin's [ResourceTable] for as long as a script
+//! holds a handle to it. Passed back to an export, the handle is lent for a `borrow` parameter,
+//! or moved into the guest for an `own` one, after which the script can't use it any more.
+//!
+//! Dropping a guest resource needs the [Store], which the script doesn't have. So when the last
+//! copy of a handle goes out of scope it is only queued, and the plugin [release]s the queue
+//! before and after each call.
+//!
+//! [release]: GuestResources::release
+use core::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail};
+use wasm_component_layer::{AsContextMut, ResourceOwn, ResourceType};
+
+use super::{HostResourceIndex, Resource, ResourceTable, ResourceTableError};
+
+/// An `own` the host holds on a script's behalf.
+struct GuestResource {
+    own: ResourceOwn,
+}
+
+/// The table entries whose handles were dropped, waiting for the [Store] to drop them
+type Released = Arc<Mutex<Vec<HostResourceIndex>>>;
+
+/// Tracks the guest resources handed to scripts, in the same [ResourceTable] as the host's own
+/// resources, so they count towards its limit.
+#[derive(Clone, Default)]
+pub struct GuestResources {
+    table: Arc<Mutex<ResourceTable>>,
+    released: Released,
+}
+
+impl GuestResources {
+    /// Tracks guest resources in `table`.
+    pub fn new(table: Arc<Mutex<ResourceTable>>) -> Self {
+        Self {
+            table,
+            released: Default::default(),
+        }
+    }
+
+    /// Keeps `own` in the table, returning the handle scripts hold it by.
+    pub fn track(&self, own: ResourceOwn) -> Result<ResourceHandle, ResourceTableError> {
+        let mut table = self.table.lock().unwrap();
+        let resource = table.push(GuestResource { own })?;
+        Ok(ResourceHandle(Arc::new(Handle {
+            index: table.index(&resource)?,
+            table: self.table.clone(),
+            released: self.released.clone(),
+            moved: AtomicBool::new(false),
+        })))
+    }
+
+    /// Drops the resources whose handles were all dropped, running their destructors.
+    ///
+    /// Every queued resource is tried. One that fails to drop stays in the table and the queue,
+    /// to be tried again next time, and the errors are returned together.
+    pub fn release(&self, mut ctx: impl AsContextMut) -> anyhow::Result<()> {
+        let released = std::mem::take(&mut *self.released.lock().unwrap());
+        let mut failed = Vec::new();
+        let mut errors = Vec::new();
+        for index in released {
+            let resource = Resource::<GuestResource>::new_index(index);
+            // the table isn't locked while the destructor runs, it may call the host
+            let own = match self.table.lock().unwrap().get(&resource) {
+                Ok(entry) => entry.own.clone(),
+                Err(e) => {
+                    errors.push(format!("resource #{}: {e}", index.index()));
+                    continue;
+                }
+            };
+            match own.drop(&mut ctx) {
+                Ok(()) => {
+                    if let Err(e) = self.table.lock().unwrap().delete(resource) {
+                        errors.push(format!("resource #{}: {e}", index.index()));
+                    }
+                }
+                Err(e) => {
+                    errors.push(format!("resource #{}: {e}", index.index()));
+                    failed.push(index);
+                }
+            }
+        }
+        self.released.lock().unwrap().extend(failed);
+
+        if !errors.is_empty() {
+            bail!(
+                "failed to release {} resources: {}",
+                errors.len(),
+                errors.join("; ")
+            );
+        }
+        Ok(())
+    }
+
+    /// The number of handles dropped since the last [release](Self::release).
+    pub fn pending_release(&self) -> usize {
+        self.released.lock().unwrap().len()
+    }
+}
+
+impl fmt::Debug for GuestResources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GuestResources")
+            .field("released", &self.released)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A script's handle to a guest resource. Copies share the resource, which is released once
+/// the last one is dropped.
+#[derive(Clone)]
+pub struct ResourceHandle(Arc<Handle>);
+
+struct Handle {
+    /// The resource's entry in the table
+    index: HostResourceIndex,
+    table: Arc<Mutex<ResourceTable>>,
+    released: Released,
+    /// Set once the resource was moved into the guest
+    moved: AtomicBool,
+}
+
+impl ResourceHandle {
+    /// The resource, still held by the handle, to lend to the guest.
+    pub fn own(&self) -> anyhow::Result<ResourceOwn> {
+        if self.is_moved() {
+            bail!("the resource was moved into the plugin");
+        }
+        let table = self.0.table.lock().unwrap();
+        let resource = table.get(&Resource::<GuestResource>::new_index(self.0.index))?;
+        Ok(resource.own.clone())
+    }
+
+    /// Moves the resource out of the handle, to hand it to the guest. Every copy of the
+    /// handle can't be used afterwards.
+    pub fn take(&self) -> anyhow::Result<ResourceOwn> {
+        if self.0.moved.swap(true, Ordering::SeqCst) {
+            bail!("the resource was moved into the plugin");
+        }
+        let resource = self
+            .0
+            .table
+            .lock()
+            .unwrap()
+            .delete(Resource::<GuestResource>::new_index(self.0.index))
+            .map_err(|e| anyhow!("the resource is gone: {e}"))?;
+        Ok(resource.own)
+    }
+
+    /// Moves the resources out of all the `handles` at once, like [take](Self::take), after
+    /// the [own](Self::own)s lent for them were put into the guest's arguments. Either every
+    /// resource is moved or, if one can't be, none is.
+    pub fn take_all(handles: &[ResourceHandle]) -> anyhow::Result<()> {
+        let Some(first) = handles.first() else {
+            return Ok(());
+        };
+        let mut table = first.0.table.lock().unwrap();
+        for (i, handle) in handles.iter().enumerate() {
+            if !Arc::ptr_eq(&handle.0.table, &first.0.table) {
+                bail!("the resources belong to different plugins");
+            }
+            if handles[..i]
+                .iter()
+                .any(|other| Arc::ptr_eq(&other.0, &handle.0))
+            {
+                bail!("the resource can only be moved into the plugin once");
+            }
+            if handle.is_moved() {
+                bail!("the resource was moved into the plugin");
+            }
+            table
+                .get(&Resource::<GuestResource>::new_index(handle.0.index))
+                .map_err(|e| anyhow!("the resource is gone: {e}"))?;
+        }
+        // all there, and the table stays locked until they're gone
+        for handle in handles {
+            handle.0.moved.store(true, Ordering::SeqCst);
+            table.delete(Resource::<GuestResource>::new_index(handle.0.index))?;
+        }
+        Ok(())
+    }
+
+    /// The type of the resource, if the handle still holds it.
+    pub fn ty(&self) -> Option<ResourceType> {
+        self.own().ok().map(|own| own.ty())
+    }
+
+    /// Whether the resource was moved into the guest.
+    pub fn is_moved(&self) -> bool {
+        self.0.moved.load(Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for ResourceHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_moved() {
+            write!(f, "resource #{} (moved)", self.0.index.index())
+        } else {
+            write!(f, "resource #{}", self.0.index.index())
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if !*self.moved.get_mut() {
+            self.released.lock().unwrap().push(self.index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{runtime_layer, Engine, Store};
+
+    fn store() -> Store<(), runtime_layer::Engine> {
+        Store::new(&Engine::new(runtime_layer::Engine::default()), ())
+    }
+
+    #[test]
+    fn test_released_once_every_copy_is_dropped() {
+        let mut store = store();
+        let ty = ResourceType::new::<u32>(None);
+        let resources = GuestResources::default();
+
+        let handle = resources
+            .track(ResourceOwn::new(&mut store, 7u32, ty.clone()).unwrap())
+            .unwrap();
+        let copy = handle.clone();
+        assert_eq!(handle.ty(), Some(ty));
+        assert_eq!(resources.table.lock().unwrap().len(), 1);
+
+        drop(handle);
+        assert_eq!(resources.pending_release(), 0);
+        assert!(copy.own().is_ok());
+
+        drop(copy);
+        assert_eq!(resources.pending_release(), 1);
+        resources.release(&mut store).unwrap();
+        assert_eq!(resources.pending_release(), 0);
+        assert!(resources.table.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_moved_into_the_guest() {
+        let mut store = store();
+        let ty = ResourceType::new::<u32>(None);
+        let resources = GuestResources::default();
+
+        let handle = resources
+            .track(ResourceOwn::new(&mut store, 7u32, ty).unwrap())
+            .unwrap();
+        let copy = handle.clone();
+        handle.take().unwrap();
+        assert!(resources.table.lock().unwrap().is_empty());
+
+        // no copy can use it any more, or release it
+        assert!(copy.is_moved());
+        assert!(copy.own().is_err());
+        assert!(copy.take().is_err());
+        drop((handle, copy));
+        assert_eq!(resources.pending_release(), 0);
+    }
+
+    #[test]
+    fn test_release_keeps_failures() {
+        let mut store = store();
+        let ty = ResourceType::new::<u32>(None);
+        let resources = GuestResources::default();
+
+        let broken = resources
+            .track(ResourceOwn::new(&mut store, 1u32, ty.clone()).unwrap())
+            .unwrap();
+        let fine = resources
+            .track(ResourceOwn::new(&mut store, 2u32, ty).unwrap())
+            .unwrap();
+        // dropped behind the handle's back, so releasing it fails
+        broken.own().unwrap().drop(&mut store).unwrap();
+        drop((broken, fine));
+
+        // the other one is released all the same, the broken one is kept
+        assert!(resources.release(&mut store).is_err());
+        assert_eq!(resources.pending_release(), 1);
+        assert_eq!(resources.table.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_take_all_or_none() {
+        let mut store = store();
+        let ty = ResourceType::new::<u32>(None);
+        let resources = GuestResources::default();
+
+        let first = resources
+            .track(ResourceOwn::new(&mut store, 1u32, ty.clone()).unwrap())
+            .unwrap();
+        let second = resources
+            .track(ResourceOwn::new(&mut store, 2u32, ty).unwrap())
+            .unwrap();
+
+        // the same resource twice moves neither
+        assert!(ResourceHandle::take_all(&[first.clone(), second.clone(), first.clone()]).is_err());
+        assert!(!first.is_moved() && !second.is_moved());
+        assert_eq!(resources.table.lock().unwrap().len(), 2);
+
+        ResourceHandle::take_all(&[first.clone(), second.clone()]).unwrap();
+        assert!(first.is_moved() && second.is_moved());
+        assert!(resources.table.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_counts_towards_the_table_limit() {
+        let mut store = store();
+        let ty = ResourceType::new::<u32>(None);
+        let resources = GuestResources::default();
+        resources.table.lock().unwrap().set_limit(Some(1));
+
+        let _handle = resources
+            .track(ResourceOwn::new(&mut store, 1u32, ty.clone()).unwrap())
+            .unwrap();
+        assert!(matches!(
+            resources.track(ResourceOwn::new(&mut store, 2u32, ty).unwrap()),
+            Err(ResourceTableError::LimitReached(1))
+        ));
+    }
+}
@@ -0,0 +1,780 @@
+//! Outgoing HTTP requests, exposed to guests as `component:plugin/http`.
+//!
+//! ```wit
+//! interface http {
+//!   use wasi:io/poll@0.2.2.{pollable};
+//!
+//!   record request {
+//!     method: string,
+//!     url: string,
+//!     headers: list<tuple<string, string>>,
+//!     body: option<list<u8>>,
+//!   }
+//!
+//!   record response {
+//!     status: u16,
+//!     headers: list<tuple<string, string>>,
+//!     body: list<u8>,
+//!   }
+//!
+//!   resource future-response {
+//!     subscribe: func() -> pollable;
+//!     get: func() -> option<result<response, string>>;
+//!   }
+//!
+//!   fetch: func(request: request) -> result<future-response, string>;
+//! }
+//! ```
+//!
+//! A plugin can only reach the origins in its [AllowedOrigins], any other request fails in
+//! `fetch` without being sent. Redirects aren't followed either, as they could lead anywhere.
+//!
+//! Each request is sent from its own thread, and a plugin may have at most [MAX_IN_FLIGHT] of
+//! them at once. The response takes its place in the plugin's resource table before the
+//! request is sent, so a plugin at its resource quota sends nothing. `future-response.subscribe`
+//! hands out a pollable that is ready once the response has arrived, so async guests can await
+//! it through `wasi:io/poll`, and `get` takes the response.
+//!
+//! Requests are only sent natively. In the browser `fetch` fails for every request, allowed
+//! origin or not.
+use std::collections::BTreeSet;
+use std::sync::atomic::AtomicUsize;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use anyhow::bail;
+use wasm_component_layer::{
+    AsContext as _, Component, Func, FuncType, Linker, List, ListType, OptionType, OptionValue,
+    Record, RecordType, ResourceOwn, ResourceType, ResultType, ResultValue, Store, Tuple,
+    TupleType, Value, ValueType,
+};
+
+use super::limits::Meter;
+use super::{
+    charge_table_error, drop_resource, runtime_layer, subscribe, Inner, Resource, ResourceTable,
+    Subscribe,
+};
+use crate::Error;
+
+/// The interface guests import the HTTP functions from.
+pub const INTERFACE: &str = "component:plugin/http";
+
+/// Responses with a larger body fail, rather than filling up the guest's memory
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_BODY_BYTES: u64 = 16 * 1024 * 1024;
+
+/// How long a request may take before it fails
+#[cfg(not(target_arch = "wasm32"))]
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How many requests a plugin may have in flight at once
+pub const MAX_IN_FLIGHT: usize = 8;
+
+/// The origins a plugin may send requests to, such as `https://example.com` or
+/// `http://127.0.0.1:8080`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AllowedOrigins(BTreeSet<String>);
+
+impl AllowedOrigins {
+    /// No origins at all, so every request is refused.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Also allows requests to the origin of `url`. Anything after the origin, such as a path,
+    /// is ignored.
+    pub fn allow(mut self, url: &str) -> Result<Self, Error> {
+        self.0.insert(origin_of(url)?);
+        Ok(self)
+    }
+
+    /// Whether requests to `url` are allowed.
+    pub fn allows(&self, url: &str) -> bool {
+        origin_of(url).is_ok_and(|origin| self.0.contains(&origin))
+    }
+}
+
+/// The scheme, host and port of `url`, serialized the way browsers do.
+fn origin_of(url: &str) -> Result<String, Error> {
+    let parsed =
+        url::Url::parse(url).map_err(|e| Error::Parse(format!("Invalid url {url}: {e}")))?;
+    match parsed.origin() {
+        origin @ url::Origin::Tuple(..) => Ok(origin.ascii_serialization()),
+        url::Origin::Opaque(_) => Err(Error::Parse(format!("{url} has no origin"))),
+    }
+}
+
+/// An outgoing request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+/// The response to a [Request], whatever its status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// A response that may not have arrived yet, the host side of `future-response`.
+pub struct FutureResponse(ResponseState);
+
+enum ResponseState {
+    #[cfg(not(target_arch = "wasm32"))]
+    Pending(tokio::sync::oneshot::Receiver<Result<Response, String>>),
+    // the browser can't send requests yet, so nothing arrives there
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    Ready(Result<Response, String>),
+    Taken,
+}
+
+impl FutureResponse {
+    /// Takes the response, or why there is none, once it has arrived. `None` while it is
+    /// still pending.
+    pub fn get(&mut self) -> Option<Result<Response, String>> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let ResponseState::Pending(receiver) = &mut self.0 {
+            use tokio::sync::oneshot::error::TryRecvError;
+            let result = match receiver.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Closed) => Err("The request was dropped".to_string()),
+            };
+            self.0 = ResponseState::Ready(result);
+        }
+
+        match std::mem::replace(&mut self.0, ResponseState::Taken) {
+            ResponseState::Ready(result) => Some(result),
+            ResponseState::Taken => Some(Err("The response was already taken".to_string())),
+            #[cfg(not(target_arch = "wasm32"))]
+            ResponseState::Pending(_) => unreachable!("pending responses return early"),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for FutureResponse {
+    async fn ready(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let ResponseState::Pending(receiver) = &mut self.0 {
+            let result = receiver
+                .await
+                .unwrap_or_else(|_| Err("The request was dropped".to_string()));
+            self.0 = ResponseState::Ready(result);
+        }
+    }
+}
+
+/// Counts a plugin's requests in flight, see [MAX_IN_FLIGHT].
+#[derive(Clone, Default)]
+pub struct InFlight(
+    // the browser sends no requests, so there are none to count
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))] Arc<AtomicUsize>,
+);
+
+impl InFlight {
+    /// Counts one more request until the returned [Sending] is dropped, unless there are
+    /// [MAX_IN_FLIGHT] already.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start(&self) -> Option<Sending> {
+        let sending = Sending(self.0.clone());
+        // counted before the check, so two requests can't both take the last place
+        (self.0.fetch_add(1, Ordering::SeqCst) < MAX_IN_FLIGHT).then_some(sending)
+    }
+}
+
+/// A request in flight, counted until it is dropped.
+#[cfg(not(target_arch = "wasm32"))]
+struct Sending(Arc<AtomicUsize>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for Sending {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Sends the request in the background, if its origin is allowed and the plugin has fewer than
+/// [MAX_IN_FLIGHT] requests in flight.
+///
+/// The response is handed to `reserve` before anything is sent, so its error, such as a full
+/// resource table, stops the request. A refused request is the inner error.
+pub fn fetch<R>(
+    request: Request,
+    allowed: &AllowedOrigins,
+    in_flight: &InFlight,
+    reserve: impl FnOnce(FutureResponse) -> anyhow::Result<R>,
+) -> anyhow::Result<Result<R, String>> {
+    let origin = match origin_of(&request.url) {
+        Ok(origin) => origin,
+        Err(e) => return Ok(Err(e.to_string())),
+    };
+    if !allowed.0.contains(&origin) {
+        return Ok(Err(format!("Requests to {origin} are not allowed")));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let Some(sending) = in_flight.start() else {
+            return Ok(Err(format!(
+                "At most {MAX_IN_FLIGHT} requests may be in flight at once"
+            )));
+        };
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let reserved = reserve(FutureResponse(ResponseState::Pending(receiver)))?;
+        let spawned = std::thread::Builder::new()
+            .name(format!("http {origin}"))
+            .spawn(move || {
+                // the guest may have dropped the response already
+                let _ = sender.send(send(request));
+                drop(sending);
+            });
+        // the sender is gone with the thread, so the response fails rather than never arriving
+        if let Err(e) = spawned {
+            tracing::error!("Failed to send the request: {}", e);
+        }
+        Ok(Ok(reserved))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = (request, in_flight, reserve);
+        Ok(Err(
+            "HTTP requests are only sent natively, not in the browser".to_string(),
+        ))
+    }
+}
+
+/// Sends the request and waits for the whole response.
+#[cfg(not(target_arch = "wasm32"))]
+fn send(request: Request) -> Result<Response, String> {
+    use std::io::Read as _;
+
+    let agent = ureq::AgentBuilder::new()
+        .redirects(0)
+        .timeout(TIMEOUT)
+        .build();
+
+    let mut builder = agent.request(&request.method, &request.url);
+    for (name, value) in &request.headers {
+        builder = builder.set(name, value);
+    }
+
+    let response = match request.body {
+        Some(body) => builder.send_bytes(&body),
+        None => builder.call(),
+    };
+    let response = match response {
+        // an error status is still a response, for the guest to make sense of
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let status = response.status();
+    let headers = response
+        .headers_names()
+        .into_iter()
+        .filter_map(|name| {
+            let value = response.header(&name)?.to_string();
+            Some((name, value))
+        })
+        .collect();
+
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| e.to_string())?;
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return Err(format!(
+            "The response is larger than {MAX_BODY_BYTES} bytes"
+        ));
+    }
+
+    Ok(Response {
+        status,
+        headers,
+        body,
+    })
+}
+
+fn headers_type() -> ValueType {
+    ValueType::List(ListType::new(ValueType::Tuple(TupleType::new(
+        None,
+        [ValueType::String, ValueType::String],
+    ))))
+}
+
+fn bytes_type() -> ValueType {
+    ValueType::List(ListType::new(ValueType::U8))
+}
+
+fn request_type() -> anyhow::Result<RecordType> {
+    RecordType::new(
+        None,
+        [
+            ("method", ValueType::String),
+            ("url", ValueType::String),
+            ("headers", headers_type()),
+            ("body", ValueType::Option(OptionType::new(bytes_type()))),
+        ],
+    )
+}
+
+fn response_type() -> anyhow::Result<RecordType> {
+    RecordType::new(
+        None,
+        [
+            ("status", ValueType::U16),
+            ("headers", headers_type()),
+            ("body", bytes_type()),
+        ],
+    )
+}
+
+fn string_field(record: &Record, name: &str) -> anyhow::Result<String> {
+    match record.field(name) {
+        Some(Value::String(s)) => Ok(s.to_string()),
+        other => bail!("Expected a string {name}, found {:?}", other),
+    }
+}
+
+fn bytes(value: Value) -> anyhow::Result<Vec<u8>> {
+    let Value::List(list) = value else {
+        bail!("Expected a list of bytes, found {:?}", value);
+    };
+    list.into_iter()
+        .map(|byte| match byte {
+            Value::U8(byte) => Ok(byte),
+            other => bail!("Expected a byte, found {:?}", other),
+        })
+        .collect()
+}
+
+fn request_from_record(record: &Record) -> anyhow::Result<Request> {
+    let Some(Value::List(headers)) = record.field("headers") else {
+        bail!(
+            "Expected a list of headers, found {:?}",
+            record.field("headers")
+        );
+    };
+    let headers = headers
+        .into_iter()
+        .map(|header| {
+            let Value::Tuple(pair) = header else {
+                bail!("Expected a header, found {:?}", header);
+            };
+            let mut pair = pair.into_iter();
+            match (pair.next(), pair.next()) {
+                (Some(Value::String(name)), Some(Value::String(value))) => {
+                    Ok((name.to_string(), value.to_string()))
+                }
+                other => bail!("Expected a header name and value, found {:?}", other),
+            }
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let body = match record.field("body") {
+        Some(Value::Option(body)) => Option::<Value>::clone(&body).map(bytes).transpose()?,
+        other => bail!("Expected an optional body, found {:?}", other),
+    };
+
+    Ok(Request {
+        method: string_field(record, "method")?,
+        url: string_field(record, "url")?,
+        headers,
+        body,
+    })
+}
+
+fn response_to_record(ty: &RecordType, response: Response) -> anyhow::Result<Record> {
+    let pair_ty = TupleType::new(None, [ValueType::String, ValueType::String]);
+    let headers = response
+        .headers
+        .into_iter()
+        .map(|(name, value)| {
+            Tuple::new(
+                pair_ty.clone(),
+                [Value::String(name.into()), Value::String(value.into())],
+            )
+            .map(Value::Tuple)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Record::new(
+        ty.clone(),
+        [
+            ("status", Value::U16(response.status)),
+            (
+                "headers",
+                Value::List(List::new(
+                    ListType::new(ValueType::Tuple(pair_ty)),
+                    headers,
+                )?),
+            ),
+            (
+                "body",
+                Value::List(List::new(
+                    ListType::new(ValueType::U8),
+                    response.body.into_iter().map(Value::U8),
+                )?),
+            ),
+        ],
+    )
+}
+
+/// The type of `func` as the component imports it, if it does.
+fn declared(component: &Component, func: &str) -> Option<FuncType> {
+    component
+        .imports()
+        .instance(&INTERFACE.try_into().ok()?)?
+        .func(func)
+}
+
+/// Defines `component:plugin/http`, for requests to the origins returned by
+/// [Inner::http_origins]. Responses are kept in `table`, and subscribing to one hands out a
+/// `pollable_ty` from `wasi:io/poll`.
+pub(crate) fn add_to_linker<T: Inner + 'static>(
+    linker: &mut Linker,
+    store: &mut Store<T, runtime_layer::Engine>,
+    component: &Component,
+    table: &Arc<Mutex<ResourceTable>>,
+    pollable_ty: &ResourceType,
+    meter: &Meter,
+) -> anyhow::Result<()> {
+    // The records are named, so link with the ones the component was built against, if any.
+    let request_ty = match declared(component, "fetch") {
+        Some(fetch) => fetch.params().first().cloned(),
+        None => None,
+    }
+    .unwrap_or(ValueType::Record(request_type()?));
+    let get_ty = match declared(component, "[method]future-response.get") {
+        Some(get) => get.results().first().cloned(),
+        None => None,
+    }
+    .unwrap_or(ValueType::Option(OptionType::new(ValueType::Result(
+        ResultType::new(
+            Some(ValueType::Record(response_type()?)),
+            Some(ValueType::String),
+        ),
+    ))));
+
+    let ValueType::Option(get_option_ty) = get_ty.clone() else {
+        bail!(
+            "future-response.get should return an option, found {:?}",
+            get_ty
+        );
+    };
+    let ValueType::Result(get_result_ty) = get_option_ty.some_ty() else {
+        bail!(
+            "future-response.get should return a result, found {:?}",
+            get_ty
+        );
+    };
+    let Some(ValueType::Record(response_ty)) = get_result_ty.ok_ty() else {
+        bail!(
+            "future-response.get should return a response, found {:?}",
+            get_ty
+        );
+    };
+
+    // Dropping a response in the guest deletes it from the table, cancelling nothing: the
+    // request runs to the end, and its result is thrown away.
+    let table_clone = table.clone();
+    let future_ty = ResourceType::with_destructor(
+        &mut *store,
+        None,
+        move |_store, future: Resource<FutureResponse>| {
+            drop_resource(&table_clone, "future-response", future)
+        },
+    )?;
+
+    let http_interface = linker.define_instance(INTERFACE.try_into()?)?;
+    http_interface.define_resource("future-response", future_ty.clone())?;
+
+    // fetch: func(request: request) -> result<future-response, string>;
+    let fetch_ty = ResultType::new(
+        Some(ValueType::Own(future_ty.clone())),
+        Some(ValueType::String),
+    );
+    let table_clone = table.clone();
+    let future_ty_clone = future_ty.clone();
+    let meter_clone = meter.clone();
+    let in_flight = InFlight::default();
+    http_interface.define_func(
+        "fetch",
+        Func::new(
+            &mut *store,
+            FuncType::new([request_ty], [ValueType::Result(fetch_ty.clone())]),
+            move |mut store, params, results| {
+                meter_clone.consume()?;
+                let Value::Record(record) = &params[0] else {
+                    bail!("Incorrect input type, found {:?}", params[0]);
+                };
+                let request = request_from_record(record)?;
+                tracing::info!("{} {}", request.method, request.url);
+
+                let allowed = store.data().http_origins();
+                let reserve = |future| {
+                    table_clone
+                        .lock()
+                        .unwrap()
+                        .push(future)
+                        .map_err(|e| charge_table_error(&meter_clone, e.into()))
+                };
+                let result = match fetch(request, &allowed, &in_flight, reserve)? {
+                    Ok(future) => Ok(Some(Value::Own(ResourceOwn::new(
+                        &mut store,
+                        future,
+                        future_ty_clone.clone(),
+                    )?))),
+                    Err(e) => {
+                        tracing::warn!("Refused request: {}", e);
+                        Err(Some(Value::String(e.into())))
+                    }
+                };
+
+                results[0] = Value::Result(ResultValue::new(fetch_ty.clone(), result)?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    // subscribe: func() -> pollable;
+    let table_clone = table.clone();
+    let pollable_ty_clone = pollable_ty.clone();
+    let meter_clone = meter.clone();
+    http_interface.define_func(
+        "[method]future-response.subscribe",
+        Func::new(
+            &mut *store,
+            FuncType::new(
+                [ValueType::Borrow(future_ty.clone())],
+                [ValueType::Own(pollable_ty.clone())],
+            ),
+            move |mut store, params, results| {
+                meter_clone.consume()?;
+                let Value::Borrow(future) = &params[0] else {
+                    bail!("Incorrect input type, found {:?}", params[0]);
+                };
+                let borrowed = {
+                    let binding = store.as_context();
+                    let future: &Resource<FutureResponse> = future.rep(&binding)?;
+                    future.borrowed()
+                };
+
+                // borrowed, so dropping the pollable leaves the response in place
+                let pollable = subscribe(table_clone.clone(), borrowed)
+                    .map_err(|e| charge_table_error(&meter_clone, e))?;
+
+                results[0] = Value::Own(ResourceOwn::new(
+                    &mut store,
+                    pollable,
+                    pollable_ty_clone.clone(),
+                )?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    // get: func() -> option<result<response, string>>;
+    let table_clone = table.clone();
+    let meter_clone = meter.clone();
+    http_interface.define_func(
+        "[method]future-response.get",
+        Func::new(
+            &mut *store,
+            FuncType::new([ValueType::Borrow(future_ty)], [get_ty]),
+            move |store, params, results| {
+                meter_clone.consume()?;
+                let Value::Borrow(future) = &params[0] else {
+                    bail!("Incorrect input type, found {:?}", params[0]);
+                };
+                let binding = store.as_context();
+                let future: &Resource<FutureResponse> = future.rep(&binding)?;
+                let response = table_clone.lock().unwrap().get_mut(future)?.get();
+
+                let response = match response {
+                    Some(Ok(response)) => Some(Value::Result(ResultValue::new(
+                        get_result_ty.clone(),
+                        Ok(Some(Value::Record(response_to_record(
+                            &response_ty,
+                            response,
+                        )?))),
+                    )?)),
+                    Some(Err(e)) => Some(Value::Result(ResultValue::new(
+                        get_result_ty.clone(),
+                        Err(Some(Value::String(e.into()))),
+                    )?)),
+                    None => None,
+                };
+                results[0] = Value::Option(OptionValue::new(get_option_ty.clone(), response)?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::io::{Read as _, Write as _};
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::layer::{block_on, Duration};
+
+    /// Answers a single request on a loopback port with `response`, returning the server's url.
+    fn serve_once(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        url
+    }
+
+    fn request(url: &str) -> Request {
+        Request {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: vec![("Accept".to_string(), "application/json".to_string())],
+            body: None,
+        }
+    }
+
+    /// Sends the request, with room for its response and nothing else in flight
+    fn send_now(request: Request, allowed: &AllowedOrigins) -> Result<FutureResponse, String> {
+        fetch(request, allowed, &InFlight::default(), Ok).unwrap()
+    }
+
+    fn wait(mut future: FutureResponse) -> Result<Response, String> {
+        block_on(future.ready(), Duration::from_secs(10)).expect("the request timed out");
+        future.get().expect("the response has arrived")
+    }
+
+    #[test]
+    fn test_origins() {
+        let allowed = AllowedOrigins::none()
+            .allow("https://example.com/some/path")
+            .unwrap()
+            .allow("http://127.0.0.1:8080")
+            .unwrap();
+
+        assert!(allowed.allows("https://example.com:443/api?q=1"));
+        assert!(allowed.allows("http://127.0.0.1:8080/"));
+        assert!(!allowed.allows("http://example.com/"));
+        assert!(!allowed.allows("https://example.com.evil.net/"));
+        assert!(!allowed.allows("http://127.0.0.1:8081/"));
+        assert!(!allowed.allows("not a url"));
+        assert!(AllowedOrigins::none().allow("data:text/plain,hi").is_err());
+    }
+
+    #[test]
+    fn test_fetch_from_loopback() {
+        let url = serve_once(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 13\r\nConnection: close\r\n\r\n{\"ok\": true }",
+        );
+        let allowed = AllowedOrigins::none().allow(&url).unwrap();
+
+        let response =
+            wait(send_now(request(&format!("{url}/status")), &allowed).unwrap()).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"{\"ok\": true }");
+        assert!(response
+            .headers
+            .iter()
+            .any(|(name, value)| name.eq_ignore_ascii_case("content-type")
+                && value == "application/json"));
+    }
+
+    #[test]
+    fn test_redirects_are_not_followed() {
+        let url = serve_once(
+            "HTTP/1.1 302 Found\r\nLocation: https://example.com/\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        );
+        let allowed = AllowedOrigins::none().allow(&url).unwrap();
+
+        let response = wait(send_now(request(&url), &allowed).unwrap()).unwrap();
+        assert_eq!(response.status, 302);
+    }
+
+    #[test]
+    fn test_refuses_other_origins() {
+        let allowed = AllowedOrigins::none().allow("http://127.0.0.1:1").unwrap();
+        let error = send_now(request("http://127.0.0.1:2/"), &allowed)
+            .err()
+            .expect("the origin isn't allowed");
+        assert!(error.contains("not allowed"), "{error}");
+    }
+
+    #[test]
+    fn test_nothing_sent_without_room() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let allowed = AllowedOrigins::none().allow(&url).unwrap();
+
+        let result = fetch(request(&url), &allowed, &InFlight::default(), |_| {
+            Err::<(), _>(anyhow::anyhow!("the table is full"))
+        });
+        assert!(result.is_err());
+
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(
+            listener.accept().err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::WouldBlock)
+        );
+    }
+
+    #[test]
+    fn test_in_flight_limit() {
+        let url = serve_once("HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n");
+        let allowed = AllowedOrigins::none().allow(&url).unwrap();
+        let in_flight = InFlight::default();
+        let mut sending: Vec<_> = std::iter::from_fn(|| in_flight.start()).collect();
+        assert_eq!(sending.len(), MAX_IN_FLIGHT);
+
+        let error = fetch(
+            request(&url),
+            &allowed,
+            &in_flight,
+            |_| -> anyhow::Result<()> { panic!("a refused request has no response to reserve") },
+        )
+        .unwrap()
+        .unwrap_err();
+        assert!(error.contains("in flight"), "{error}");
+
+        // room again once one of them is done
+        sending.pop();
+        let future = fetch(request(&url), &allowed, &in_flight, Ok)
+            .unwrap()
+            .unwrap();
+        assert_eq!(wait(future).unwrap().status, 204);
+    }
+
+    #[test]
+    fn test_response_is_taken_once() {
+        let mut future = FutureResponse(ResponseState::Ready(Err("failed".to_string())));
+        assert_eq!(future.get(), Some(Err("failed".to_string())));
+        assert!(future.get().unwrap().is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! Rewrites the core modules of a component so the host can call into them, see
+//! [fuel](super::fuel) and [memory](super::memory).
+//!
+//! Each rewritten module gains a function of its own, which the component doesn't otherwise
+//! export. [rewrite_modules] lifts it from every instance of the module and exports it from
+//! the component under a name of its own, numbered by instance, for the host to look up.
+use wasm_encoder::{
+    Alias, CanonicalFunctionSection, ComponentAliasSection, ComponentExportKind,
+    ComponentExportSection, ComponentSectionId, ComponentTypeSection, ExportKind, RawSection,
+    SectionId,
+};
+use wasmparser::{Chunk, Encoding, Parser, Payload, Validator};
+
+use crate::Error;
+
+/// The function a rewritten module gains, and how it is exported.
+pub struct Hook {
+    /// What the module exports it as
+    pub module_export: &'static str,
+    /// What the component exports it as, for the `n`th rewritten instance
+    pub component_export: fn(usize) -> String,
+    /// Adds its type, as the component exports it
+    pub ty: fn(&mut ComponentTypeSection),
+}
+
+/// Rewrites the core modules at the top of a component with `rewrite`, which returns `None`
+/// for a module it leaves as it is, and exports the [Hook] of every instance of a rewritten
+/// module. `nested` is shown each component nested in it, which is left as it is, or refused.
+pub fn rewrite_modules(
+    bytes: &[u8],
+    mut rewrite: impl FnMut(&[u8]) -> Result<Option<Vec<u8>>, Error>,
+    mut nested: impl FnMut(&[u8]) -> Result<(), Error>,
+    hook: &Hook,
+) -> Result<Vec<u8>, Error> {
+    // the index spaces the hooks are appended to
+    let types = Validator::new()
+        .validate_all(bytes)
+        .map_err(|e| Error::ComponentDecode(e.into()))?;
+    let types = types.as_ref();
+
+    let mut component = wasm_encoder::Component::new();
+    let mut rewritten_modules = Vec::new();
+    let mut module_count = 0;
+    let mut hooked_instances = Vec::new();
+    let mut instance_count = 0;
+
+    for payload in top_level(bytes) {
+        match payload? {
+            Payload::Version { encoding, .. } if encoding != Encoding::Component => {
+                return Err(Error::ComponentDecode(anyhow::anyhow!("not a component")));
+            }
+            Payload::ModuleSection {
+                unchecked_range, ..
+            } => {
+                let module = &bytes[unchecked_range];
+                let rewritten = rewrite(module)?;
+                if rewritten.is_some() {
+                    rewritten_modules.push(module_count);
+                }
+                module_count += 1;
+                component.section(&RawSection {
+                    id: ComponentSectionId::CoreModule as u8,
+                    data: rewritten.as_deref().unwrap_or(module),
+                });
+            }
+            Payload::ComponentSection {
+                unchecked_range, ..
+            } => {
+                let component_bytes = &bytes[unchecked_range];
+                nested(component_bytes)?;
+                component.section(&RawSection {
+                    id: ComponentSectionId::Component as u8,
+                    data: component_bytes,
+                });
+            }
+            Payload::InstanceSection(section) => {
+                for instance in section.clone() {
+                    if let wasmparser::Instance::Instantiate { module_index, .. } =
+                        instance.map_err(|e| Error::ComponentDecode(e.into()))?
+                    {
+                        if rewritten_modules.contains(&module_index) {
+                            hooked_instances.push(instance_count);
+                        }
+                    }
+                    instance_count += 1;
+                }
+                component.section(&RawSection {
+                    id: ComponentSectionId::CoreInstance as u8,
+                    data: &bytes[section.range()],
+                });
+            }
+            other => {
+                if let Some((id, range)) = other.as_section() {
+                    component.section(&RawSection {
+                        id,
+                        data: &bytes[range],
+                    });
+                }
+            }
+        }
+    }
+
+    if hooked_instances.is_empty() {
+        return Ok(component.finish());
+    }
+
+    let ty = types.component_type_count();
+    let mut hook_ty = ComponentTypeSection::new();
+    (hook.ty)(&mut hook_ty);
+    component.section(&hook_ty);
+
+    let mut aliases = ComponentAliasSection::new();
+    let mut lifts = CanonicalFunctionSection::new();
+    let mut exports = ComponentExportSection::new();
+    let core_funcs = types.function_count();
+    let funcs = types.component_function_count();
+    for (n, instance) in hooked_instances.into_iter().enumerate() {
+        let n_u32 = n as u32;
+        aliases.alias(Alias::CoreInstanceExport {
+            instance,
+            kind: ExportKind::Func,
+            name: hook.module_export,
+        });
+        lifts.lift(core_funcs + n_u32, ty, []);
+        exports.export(
+            &(hook.component_export)(n),
+            ComponentExportKind::Func,
+            funcs + n_u32,
+            None,
+        );
+    }
+    component.section(&aliases);
+    component.section(&lifts);
+    component.section(&exports);
+
+    Ok(component.finish())
+}
+
+/// The sections of a component, without descending into the modules and components nested
+/// in it.
+fn top_level(bytes: &[u8]) -> impl Iterator<Item = Result<Payload<'_>, Error>> {
+    let mut parser = Parser::new(0);
+    let mut offset = 0;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let payload = match parser.parse(&bytes[offset..], true) {
+            Ok(Chunk::Parsed { consumed, payload }) => {
+                offset += consumed;
+                payload
+            }
+            // all the input is there, so it's never short of data
+            Ok(Chunk::NeedMoreData(_)) => unreachable!(),
+            Err(e) => {
+                done = true;
+                return Some(Err(Error::ComponentDecode(e.into())));
+            }
+        };
+        match &payload {
+            Payload::ModuleSection {
+                unchecked_range, ..
+            }
+            | Payload::ComponentSection {
+                unchecked_range, ..
+            } => offset = unchecked_range.end,
+            Payload::End(_) => done = true,
+            _ => {}
+        }
+        Some(Ok(payload))
+    })
+}
+
+/// The order sections come in within a core module
+const SECTION_ORDER: [SectionId; 13] = [
+    SectionId::Type,
+    SectionId::Import,
+    SectionId::Function,
+    SectionId::Table,
+    SectionId::Memory,
+    SectionId::Tag,
+    SectionId::Global,
+    SectionId::Export,
+    SectionId::Start,
+    SectionId::Element,
+    SectionId::DataCount,
+    SectionId::Code,
+    SectionId::Data,
+];
+
+/// Where a section comes in a core module, for adding the sections a module lacks from
+/// [Reencode::intersperse_section_hook](wasm_encoder::reencode::Reencode::intersperse_section_hook).
+/// The end of the module for `None`.
+pub fn position(id: Option<SectionId>) -> usize {
+    id.and_then(|id| SECTION_ORDER.iter().position(|section| *section == id))
+        .unwrap_or(SECTION_ORDER.len())
+}
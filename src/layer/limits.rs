@@ -0,0 +1,293 @@
+//! Execution limits for calls into a plugin, and [Quotas] on what it may hold on to.
+//!
+//! Fuel is burnt by the guest itself, in the counters [super::fuel] adds to its code, so even a
+//! guest spinning without ever calling back into the host runs dry. The deadline is checked
+//! on the [Meter] every time the guest calls a host import: once a call has run past it, the
+//! next import traps and [Instantiator::call] returns [Error::BudgetExceeded] instead of
+//! handing control back to the guest.
+//!
+//! [Quotas] bound the plugin's store instead: its linear memory (see [super::memory]), the live
+//! entries in its [ResourceTable], and the variables it can `emit` into its Rhai scope. Hitting
+//! one during a call fails that call with [Error::QuotaExceeded].
+//!
+//! [ResourceTable]: super::ResourceTable
+//! [Instantiator::call]: super::Instantiator::call
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use super::{Duration, Instant};
+use crate::Error;
+
+/// How long a guest may wait in `wasi:io/poll` during a call without a deadline, so a pollable
+/// that never becomes ready can't hang the host.
+pub const MAX_BLOCK: Duration = Duration::from_secs(30);
+
+/// Per-call bounds on how much work a plugin may do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ExecutionLimits {
+    /// Units of fuel per call. The guest burns one each time round a loop and on every tail
+    /// call, see [super::fuel]. Only metered if set when the plugin was instantiated.
+    pub fuel: Option<u64>,
+    /// How long a single call may run for.
+    pub deadline: Option<Duration>,
+}
+
+impl ExecutionLimits {
+    /// No limits at all.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Sets the fuel available to each call.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Sets how long each call may run for.
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+/// Bounds on how much a plugin may keep in its store. `None` means unbounded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Quotas {
+    /// Bytes of linear memory the plugin may grow to, split between all its memories
+    pub memory: Option<u64>,
+    /// Live entries in the plugin's resource table, such as pollables
+    pub resources: Option<usize>,
+    /// Variables in the Rhai scope, counting those added through `emit`
+    pub scope_entries: Option<usize>,
+}
+
+impl Quotas {
+    /// No quotas at all.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many bytes of linear memory the plugin may use.
+    pub fn with_memory(mut self, bytes: u64) -> Self {
+        self.memory = Some(bytes);
+        self
+    }
+
+    /// Sets how many resources the plugin may hold at once.
+    pub fn with_resources(mut self, resources: usize) -> Self {
+        self.resources = Some(resources);
+        self
+    }
+
+    /// Sets how many variables the plugin's Rhai scope may hold.
+    pub fn with_scope_entries(mut self, entries: usize) -> Self {
+        self.scope_entries = Some(entries);
+        self
+    }
+}
+
+/// The quota a plugin ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quota {
+    /// Linear memory, in bytes
+    Memory(u64),
+    /// Live resource table entries
+    Resources(usize),
+    /// Rhai scope variables
+    ScopeEntries(usize),
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quota::Memory(bytes) => write!(f, "linear memory is limited to {bytes} bytes"),
+            Quota::Resources(max) => write!(f, "at most {max} resources may be live"),
+            Quota::ScopeEntries(max) => write!(f, "the scope may hold at most {max} variables"),
+        }
+    }
+}
+
+/// The budget a plugin call ran out of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Budget {
+    /// Used up all of its fuel
+    Fuel(u64),
+    /// Ran past its deadline
+    Deadline(Duration),
+}
+
+impl fmt::Display for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Budget::Fuel(fuel) => write!(f, "used all {fuel} units of fuel"),
+            Budget::Deadline(deadline) => write!(f, "ran longer than {deadline:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MeterState {
+    limits: ExecutionLimits,
+    started: Option<Instant>,
+    exceeded: Option<Budget>,
+    quota: Option<Quota>,
+}
+
+/// Meters a plugin's calls against its [ExecutionLimits].
+///
+/// Cloning gives another handle to the same meter, so the host imports and the plugin can
+/// share it.
+#[derive(Debug, Clone, Default)]
+pub struct Meter(Arc<Mutex<MeterState>>);
+
+impl Meter {
+    /// Creates a meter enforcing the given limits.
+    pub fn new(limits: ExecutionLimits) -> Self {
+        let meter = Self::default();
+        meter.set_limits(limits);
+        meter
+    }
+
+    /// The limits this meter enforces.
+    pub fn limits(&self) -> ExecutionLimits {
+        self.0.lock().unwrap().limits
+    }
+
+    /// Replaces the limits, taking effect from the next call.
+    pub fn set_limits(&self, limits: ExecutionLimits) {
+        self.0.lock().unwrap().limits = limits;
+    }
+
+    /// Starts metering a new call.
+    pub fn start(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.started = Some(Instant::now());
+        state.exceeded = None;
+        state.quota = None;
+    }
+
+    /// Checks the deadline. Called on every host import.
+    pub fn consume(&self) -> Result<(), Error> {
+        let mut state = self.0.lock().unwrap();
+        let Some(started) = state.started else {
+            // not inside a metered call, e.g. while linking
+            return Ok(());
+        };
+
+        if let Some(budget) = state.exceeded {
+            return Err(Error::BudgetExceeded(budget));
+        }
+
+        match state.limits.deadline {
+            Some(deadline) if started.elapsed() > deadline => {
+                let budget = Budget::Deadline(deadline);
+                state.exceeded = Some(budget);
+                Err(Error::BudgetExceeded(budget))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// How much time the current call has left before its deadline, if it has one.
+    pub fn remaining(&self) -> Option<Duration> {
+        let state = self.0.lock().unwrap();
+        let deadline = state.limits.deadline?;
+        let elapsed = state.started.map(|started| started.elapsed())?;
+        Some(deadline.saturating_sub(elapsed))
+    }
+
+    /// How long a host import may block the guest for: until the call's deadline, or
+    /// [MAX_BLOCK] if it has none.
+    pub fn block_timeout(&self) -> Duration {
+        self.remaining().unwrap_or(MAX_BLOCK)
+    }
+
+    /// Records that the current call ran into a quota, returning the error for the host import
+    /// to trap with.
+    pub fn exceed_quota(&self, quota: Quota) -> Error {
+        self.0.lock().unwrap().quota.get_or_insert(quota);
+        Error::QuotaExceeded(quota)
+    }
+
+    /// Stops metering the current call, failing if it ran out of budget or into a quota.
+    pub fn finish(&self) -> Result<(), Error> {
+        let mut state = self.0.lock().unwrap();
+        state.started = None;
+        if let Some(budget) = state.exceeded.take() {
+            return Err(Error::BudgetExceeded(budget));
+        }
+        match state.quota.take() {
+            Some(quota) => Err(Error::QuotaExceeded(quota)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited() {
+        let meter = Meter::new(ExecutionLimits::unlimited());
+        meter.start();
+        for _ in 0..1000 {
+            meter.consume().unwrap();
+        }
+        assert!(meter.finish().is_ok());
+    }
+
+    #[test]
+    fn test_deadline() {
+        let deadline = Duration::from_millis(1);
+        let meter = Meter::new(ExecutionLimits::unlimited().with_deadline(deadline));
+        meter.start();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(meter.remaining(), Some(Duration::ZERO));
+        assert!(meter.consume().is_err());
+        // stays exceeded for the rest of the call
+        assert!(meter.consume().is_err());
+        assert!(matches!(
+            meter.finish(),
+            Err(Error::BudgetExceeded(Budget::Deadline(d))) if d == deadline
+        ));
+
+        // and starts afresh with the next one
+        meter.start();
+        meter.consume().unwrap();
+        assert!(meter.finish().is_ok());
+    }
+
+    #[test]
+    fn test_quota_fails_call() {
+        let meter = Meter::new(ExecutionLimits::unlimited());
+        meter.start();
+        meter.exceed_quota(Quota::ScopeEntries(1));
+        assert!(matches!(
+            meter.finish(),
+            Err(Error::QuotaExceeded(Quota::ScopeEntries(1)))
+        ));
+
+        meter.start();
+        assert!(meter.finish().is_ok());
+    }
+
+    #[test]
+    fn test_block_timeout() {
+        let meter = Meter::new(ExecutionLimits::unlimited());
+        meter.start();
+        assert_eq!(meter.block_timeout(), MAX_BLOCK);
+
+        let meter = Meter::new(ExecutionLimits::unlimited().with_deadline(Duration::from_secs(1)));
+        meter.start();
+        assert!(meter.block_timeout() <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_not_metered_outside_calls() {
+        let meter = Meter::new(ExecutionLimits::unlimited().with_deadline(Duration::ZERO));
+        std::thread::sleep(Duration::from_millis(1));
+        meter.consume().unwrap();
+    }
+}
@@ -0,0 +1,263 @@
+//! Leveled logging for guests, through the standard `wasi:logging` interface and a
+//! `component:plugin/logging` variant that also takes structured fields.
+//!
+//! ```wit
+//! interface logging {
+//!   enum level { trace, debug, info, warn, error, critical }
+//!   log: func(level: level, context: string, message: string);
+//! }
+//!
+//! interface logging {
+//!   use wasi:logging/logging@0.1.0-draft.{level};
+//!   log: func(level: level, message: string, fields: list<tuple<string, string>>);
+//! }
+//! ```
+//!
+//! Every call into a plugin runs inside its [plugin_span], so whatever it logs, through these
+//! or the plain `log` in `component:plugin/host`, is attributed to the plugin by name.
+use std::fmt::Write as _;
+
+use anyhow::bail;
+use wasm_component_layer::{
+    Component, EnumType, Func, FuncType, Linker, ListType, Store, TupleType, Value, ValueType,
+};
+
+use super::limits::Meter;
+use super::{imported, runtime_layer, Inner};
+
+/// The standard `wasi:logging/logging` interface
+pub const WASI_LOGGING: &str = "wasi:logging/logging@0.1.0-draft";
+
+/// The interface for messages with structured fields
+pub const INTERFACE: &str = "component:plugin/logging";
+
+/// The cases of the `level` enum, in order
+const LEVELS: [&str; 6] = ["trace", "debug", "info", "warn", "error", "critical"];
+
+/// How severe a guest's message is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    /// Logged as an error, with `critical` set
+    Critical,
+}
+
+impl Level {
+    /// The level of a `level` enum value.
+    fn from_value(value: &Value) -> anyhow::Result<Self> {
+        let Value::Enum(level) = value else {
+            bail!("Expected a log level, found {:?}", value);
+        };
+        Ok(match level.discriminant() {
+            0 => Level::Trace,
+            1 => Level::Debug,
+            2 => Level::Info,
+            3 => Level::Warn,
+            4 => Level::Error,
+            5 => Level::Critical,
+            other => bail!("Unknown log level {other}"),
+        })
+    }
+}
+
+/// The span a call into the plugin called `name` runs in.
+pub fn plugin_span(name: &str) -> tracing::Span {
+    tracing::info_span!("plugin", name = %name)
+}
+
+/// Formats fields as `key=value` pairs, separated by spaces.
+fn format_fields(fields: &[(String, String)]) -> String {
+    let mut formatted = String::new();
+    for (key, value) in fields {
+        if !formatted.is_empty() {
+            formatted.push(' ');
+        }
+        let _ = write!(formatted, "{key}={value:?}");
+    }
+    formatted
+}
+
+/// Logs a guest's message at its level.
+pub fn log(level: Level, context: &str, message: &str, fields: &[(String, String)]) {
+    let fields = format_fields(fields);
+    match level {
+        Level::Trace => {
+            tracing::trace!(target: "plugin", context, fields, "{}", message)
+        }
+        Level::Debug => {
+            tracing::debug!(target: "plugin", context, fields, "{}", message)
+        }
+        Level::Info => tracing::info!(target: "plugin", context, fields, "{}", message),
+        Level::Warn => tracing::warn!(target: "plugin", context, fields, "{}", message),
+        Level::Error => {
+            tracing::error!(target: "plugin", context, fields, "{}", message)
+        }
+        Level::Critical => {
+            tracing::error!(target: "plugin", critical = true, context, fields, "{}", message)
+        }
+    }
+}
+
+fn string(value: &Value) -> anyhow::Result<String> {
+    match value {
+        Value::String(s) => Ok(s.to_string()),
+        other => bail!("Expected a string, found {:?}", other),
+    }
+}
+
+fn fields(value: &Value) -> anyhow::Result<Vec<(String, String)>> {
+    let Value::List(list) = value else {
+        bail!("Expected a list of fields, found {:?}", value);
+    };
+    list.iter()
+        .map(|field| {
+            let Value::Tuple(pair) = field else {
+                bail!("Expected a field, found {:?}", field);
+            };
+            match (pair.first(), pair.get(1)) {
+                (Some(key), Some(value)) => Ok((string(key)?, string(value)?)),
+                _ => bail!("Expected a key and value, found {:?}", pair),
+            }
+        })
+        .collect()
+}
+
+/// The `level` enum as `interface` declares it in the component, if it imports `func`.
+fn declared_level(component: &Component, interface: &str, func: &str) -> Option<ValueType> {
+    imported(component, interface)?
+        .func(func)?
+        .params()
+        .first()
+        .cloned()
+}
+
+/// Defines `wasi:logging/logging` and `component:plugin/logging`.
+pub(crate) fn add_to_linker<T: Inner + 'static>(
+    linker: &mut Linker,
+    store: &mut Store<T, runtime_layer::Engine>,
+    component: &Component,
+    meter: &Meter,
+) -> anyhow::Result<()> {
+    let level_ty =
+        || -> anyhow::Result<ValueType> { Ok(ValueType::Enum(EnumType::new(None, LEVELS)?)) };
+
+    // log: func(level: level, context: string, message: string);
+    let wasi_level_ty = match declared_level(component, WASI_LOGGING, "log") {
+        Some(ty) => ty,
+        None => level_ty()?,
+    };
+    let wasi_logging = linker.define_instance(WASI_LOGGING.try_into()?)?;
+    let meter_clone = meter.clone();
+    wasi_logging.define_func(
+        "log",
+        Func::new(
+            &mut *store,
+            FuncType::new([wasi_level_ty, ValueType::String, ValueType::String], []),
+            move |_store, params, _results| {
+                meter_clone.consume()?;
+                let level = Level::from_value(&params[0])?;
+                log(level, &string(&params[1])?, &string(&params[2])?, &[]);
+                Ok(())
+            },
+        ),
+    )?;
+
+    // log: func(level: level, message: string, fields: list<tuple<string, string>>);
+    let plugin_level_ty = match declared_level(component, INTERFACE, "log") {
+        Some(ty) => ty,
+        None => level_ty()?,
+    };
+    let fields_ty = ValueType::List(ListType::new(ValueType::Tuple(TupleType::new(
+        None,
+        [ValueType::String, ValueType::String],
+    ))));
+    let plugin_logging = linker.define_instance(INTERFACE.try_into()?)?;
+    let meter_clone = meter.clone();
+    plugin_logging.define_func(
+        "log",
+        Func::new(
+            &mut *store,
+            FuncType::new([plugin_level_ty, ValueType::String, fields_ty], []),
+            move |_store, params, _results| {
+                meter_clone.consume()?;
+                let level = Level::from_value(&params[0])?;
+                log(level, "", &string(&params[1])?, &fields(&params[2])?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Collects everything the subscriber writes.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_format_fields() {
+        assert_eq!(format_fields(&[]), "");
+        assert_eq!(
+            format_fields(&[
+                ("user".to_string(), "ada".to_string()),
+                ("attempt".to_string(), "2".to_string()),
+            ]),
+            r#"user="ada" attempt="2""#
+        );
+    }
+
+    #[test]
+    fn test_log_is_attributed_to_the_plugin() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = plugin_span("counter").entered();
+            log(
+                Level::Warn,
+                "",
+                "running low",
+                &[("left".to_string(), "3".to_string())],
+            );
+            log(Level::Critical, "db", "out of space", &[]);
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{output}");
+        assert!(lines[0].contains("WARN"), "{output}");
+        assert!(lines[0].contains("plugin{name=counter}"), "{output}");
+        assert!(lines[0].contains("running low"), "{output}");
+        assert!(lines[0].contains(r#"left=\"3\""#), "{output}");
+        assert!(lines[1].contains("ERROR"), "{output}");
+        assert!(lines[1].contains("critical=true"), "{output}");
+        assert!(lines[1].contains("context=\"db\""), "{output}");
+    }
+}
@@ -0,0 +1,302 @@
+//! The manifest a plugin describes itself with: its name, version and author, what it needs
+//! from the host, and which host it runs on.
+//!
+//! The manifest is JSON in an [SECTION] custom section, either at the top of the component or
+//! in one of its core modules, where a guest puts it with `#[link_section]`:
+//!
+//! ```ignore
+//! #[link_section = "rdx-manifest"]
+//! static MANIFEST: [u8; include_bytes!("../manifest.json").len()] =
+//!     *include_bytes!("../manifest.json");
+//! ```
+//!
+//! A plugin without one is known by its file name alone. One whose manifest is invalid, or asks
+//! for a newer host, is refused, both by the build script bundling the builtin plugins and by
+//! the host loading a plugin.
+//!
+//! This file depends on nothing else in the crate, so the build script includes it as is.
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use wasm_encoder::{CustomSection, Section};
+use wasmparser::{Parser, Payload};
+
+/// The name of the custom section holding the manifest
+pub const SECTION: &str = "rdx-manifest";
+
+/// The version of this host, which plugins' `min-host-version` is checked against
+pub const HOST_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The names of the capabilities a manifest may require, as in `capability::Capability`
+pub const CAPABILITIES: [&str; 7] = ["log", "emit", "random", "clock", "storage", "http", "bus"];
+
+/// The longest name the catalogue shows in full
+const MAX_NAME_LEN: usize = 64;
+/// An icon is an emoji or a symbol, a few chars at most
+const MAX_ICON_CHARS: usize = 8;
+
+/// Why a plugin's manifest was refused.
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum ManifestError {
+    /// The component couldn't be parsed to look for a manifest
+    #[error("failed to read the component: {0}")]
+    Component(String),
+
+    /// More than one manifest section was found
+    #[error("the component has {0} manifests, expected one")]
+    Duplicate(usize),
+
+    /// The manifest isn't the JSON it should be
+    #[error("{0}")]
+    Json(String),
+
+    /// A field has a value it can't have
+    #[error("bad {field}: {reason}")]
+    Field { field: &'static str, reason: String },
+
+    /// The plugin needs a newer host than this one
+    #[error("requires host version {required}, but this is {host}")]
+    HostTooOld { required: Version, host: Version },
+}
+
+/// A plugin's manifest, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Manifest {
+    /// The name the plugin is shown by
+    pub name: String,
+    /// The plugin's version, `major.minor.patch`
+    pub version: String,
+    /// What the plugin does, in a sentence or two
+    #[serde(default)]
+    pub description: String,
+    /// Who wrote the plugin
+    #[serde(default)]
+    pub author: String,
+    /// An emoji or symbol shown next to the name
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// The capabilities the plugin needs granted to run, by name, such as `http`
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// The oldest host version the plugin runs on, `major.minor.patch`
+    #[serde(default)]
+    pub min_host_version: Option<String>,
+}
+
+impl Manifest {
+    /// Parses and validates a manifest, and checks it runs on this host.
+    pub fn parse(json: &[u8]) -> Result<Self, ManifestError> {
+        let manifest: Self =
+            serde_json::from_slice(json).map_err(|e| ManifestError::Json(e.to_string()))?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Checks every field, and that this host is at least the `min_host_version`.
+    pub fn validate(&self) -> Result<(), ManifestError> {
+        let name = self.name.trim();
+        if name.is_empty() || name.len() > MAX_NAME_LEN || name.chars().any(char::is_control) {
+            return Err(field(
+                "name",
+                format!("expected 1 to {MAX_NAME_LEN} printable characters"),
+            ));
+        }
+
+        self.version
+            .parse::<Version>()
+            .map_err(|e| field("version", e))?;
+
+        if let Some(icon) = &self.icon {
+            let chars = icon.chars().count();
+            if chars == 0 || chars > MAX_ICON_CHARS || icon.chars().any(char::is_whitespace) {
+                return Err(field("icon", "expected an emoji or symbol".to_string()));
+            }
+        }
+
+        if let Some(capability) = self
+            .capabilities
+            .iter()
+            .find(|capability| !CAPABILITIES.contains(&capability.as_str()))
+        {
+            return Err(field(
+                "capabilities",
+                format!("unknown capability {capability}"),
+            ));
+        }
+
+        if let Some(required) = &self.min_host_version {
+            let required = required
+                .parse::<Version>()
+                .map_err(|e| field("min-host-version", e))?;
+            let host = HOST_VERSION
+                .parse::<Version>()
+                .expect("the host's own version");
+            if host < required {
+                return Err(ManifestError::HostTooOld { required, host });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The manifest as the JSON [read] expects.
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).expect("a manifest always serializes")
+    }
+}
+
+/// Reads the component's manifest, if it has one.
+///
+/// Fails if the manifest is invalid, if there is more than one, or if the plugin asks for a
+/// newer host.
+pub fn read(bytes: &[u8]) -> Result<Option<Manifest>, ManifestError> {
+    let mut manifests = Vec::new();
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload.map_err(|e| ManifestError::Component(e.to_string()))? {
+            Payload::CustomSection(section) if section.name() == SECTION => {
+                manifests.push(section.data())
+            }
+            _ => {}
+        }
+    }
+    from_sections(&manifests)
+}
+
+/// The manifest in the contents of a component's [SECTION]s, for a caller that already went
+/// through the component. Fails like [read].
+pub fn from_sections(sections: &[&[u8]]) -> Result<Option<Manifest>, ManifestError> {
+    match sections {
+        [] => Ok(None),
+        [json] => Manifest::parse(json).map(Some),
+        _ => Err(ManifestError::Duplicate(sections.len())),
+    }
+}
+
+/// Adds `manifest` to the end of the component, as a custom section. Sign the component
+/// afterwards, as a signature has to come last.
+pub fn embed(bytes: &[u8], manifest: &Manifest) -> Vec<u8> {
+    let mut embedded = bytes.to_vec();
+    CustomSection {
+        name: SECTION.into(),
+        data: manifest.to_json().into(),
+    }
+    .append_to(&mut embedded);
+    embedded
+}
+
+/// A `major.minor.patch` version. A pre-release or build suffix, as in `1.0.0-beta`, is
+/// allowed but not compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl std::str::FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let core = s.split(['-', '+']).next().unwrap_or(s);
+        let mut parts = core.split('.').map(str::parse::<u64>);
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) => Ok(Self {
+                major,
+                minor,
+                patch,
+            }),
+            _ => Err(format!("expected major.minor.patch, found {s:?}")),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+fn field(field: &'static str, reason: String) -> ManifestError {
+    ManifestError::Field { field, reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty component
+    const COMPONENT: &[u8] = b"\0asm\x0d\0\x01\0";
+
+    fn manifest() -> Manifest {
+        Manifest {
+            name: "Counter".to_string(),
+            version: "0.1.0".to_string(),
+            description: "Counts up and down".to_string(),
+            author: "RDX".to_string(),
+            icon: Some("🔢".to_string()),
+            capabilities: vec!["emit".to_string()],
+            min_host_version: Some("0.1.0".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_read() {
+        assert_eq!(read(COMPONENT).unwrap(), None);
+
+        let embedded = embed(COMPONENT, &manifest());
+        assert_eq!(read(&embedded).unwrap(), Some(manifest()));
+
+        let twice = embed(&embedded, &manifest());
+        assert_eq!(read(&twice), Err(ManifestError::Duplicate(2)));
+    }
+
+    #[test]
+    fn test_parse() {
+        let minimal = Manifest::parse(br#"{ "name": "Todo", "version": "1.2.3-beta" }"#).unwrap();
+        assert_eq!(minimal.capabilities, Vec::<String>::new());
+        assert_eq!(minimal.icon, None);
+
+        for json in [
+            &br#"{ "version": "1.0.0" }"#[..],
+            br#"{ "name": "Todo", "version": "1.0" }"#,
+            br#"{ "name": "", "version": "1.0.0" }"#,
+            br#"{ "name": "Todo", "version": "1.0.0", "colour": "red" }"#,
+            br#"{ "name": "Todo", "version": "1.0.0", "capabilities": ["teleport"] }"#,
+            br#"{ "name": "Todo", "version": "1.0.0", "icon": "a very long icon" }"#,
+            b"not json",
+        ] {
+            assert!(
+                Manifest::parse(json).is_err(),
+                "{}",
+                String::from_utf8_lossy(json)
+            );
+        }
+    }
+
+    #[test]
+    fn test_min_host_version() {
+        let newer = Manifest {
+            min_host_version: Some("999.0.0".to_string()),
+            ..manifest()
+        };
+        assert!(matches!(
+            newer.validate(),
+            Err(ManifestError::HostTooOld { .. })
+        ));
+
+        let this_host = Manifest {
+            min_host_version: Some(HOST_VERSION.to_string()),
+            ..manifest()
+        };
+        assert_eq!(this_host.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_version_order() {
+        let version = |s: &str| s.parse::<Version>().unwrap();
+        assert!(version("0.10.0") > version("0.9.9"));
+        assert!(version("1.0.0-rc.1") == version("1.0.0"));
+        assert!("1.x.0".parse::<Version>().is_err());
+    }
+}
@@ -0,0 +1,591 @@
+//! Caps the linear memory of a component's core modules.
+//!
+//! Neither runtime lets the host veto `memory.grow` through `wasm_component_layer`, so the
+//! quota is written into the binary itself: every memory declared by a core module gets a
+//! maximum before the component is compiled. Growing past it then fails inside the guest, the
+//! same way it would on a machine that ran out of memory.
+//!
+//! The quota is for the whole plugin, so it is split evenly between the memories the plugin
+//! will have: a component that instantiates two modules with a memory each may grow both to
+//! half of it.
+//!
+//! Most guests trap when they are refused memory, as Rust's allocator aborts, which would only
+//! tell the host that the guest trapped. So every `memory.grow` in a core module of the
+//! component also raises a flag when it fails. The flag is exported from its module, and from
+//! the component as `rdx-memory-i<n>`: a function that lowers it and returns whether it was
+//! raised. [Gauges] reads them all when a call traps, and a guest that was refused memory
+//! during the call failed with [Error::QuotaExceeded]. Modules in components nested inside the
+//! plugin's are capped, but raise no flag.
+use wasm_component_layer::AsContextMut;
+use wasm_encoder::reencode::{self, Reencode, ReencodeComponent};
+use wasm_encoder::{
+    ConstExpr, ExportKind, ExportSection, Function, GlobalSection, GlobalType, Instruction,
+    PrimitiveValType, SectionId, ValType,
+};
+use wasmparser::{Chunk, Operator, Parser, Payload, TypeRef};
+
+use super::instrument::{position, rewrite_modules, Hook};
+use super::limits::Quota;
+use super::{Func, Instance, Value};
+use crate::Error;
+
+/// What the flag is exported as from a core module that grows memory
+const MODULE_EXPORT: &str = "rdx-memory";
+
+/// What the flag of the `n`th flagged instance is exported as from the component
+fn component_export(n: usize) -> String {
+    format!("rdx-memory-i{n}")
+}
+
+/// Rewrites `bytes` so the memories of its core modules can't grow past `max_bytes` between
+/// them. A component's modules also flag when they are refused memory, see the
+/// [module docs](self).
+///
+/// Fails with [Error::QuotaExceeded] if a memory's initial size is already over its share.
+pub fn limit(bytes: &[u8], max_bytes: u64) -> Result<Vec<u8>, Error> {
+    let mut cap = Cap {
+        share: max_bytes / memories(bytes)?.max(1),
+        quota: Quota::Memory(max_bytes),
+    };
+    let cap_error = |e| match e {
+        reencode::Error::UserError(quota) => Error::QuotaExceeded(quota),
+        e => Error::ComponentDecode(anyhow::anyhow!("{e}")),
+    };
+
+    if !Parser::is_component(bytes) {
+        let mut module = wasm_encoder::Module::new();
+        cap.parse_core_module(&mut module, Parser::new(0), bytes)
+            .map_err(cap_error)?;
+        return Ok(module.finish());
+    }
+
+    let mut component = wasm_encoder::Component::new();
+    cap.parse_component(&mut component, Parser::new(0), bytes)
+        .map_err(cap_error)?;
+
+    let hook = Hook {
+        module_export: MODULE_EXPORT,
+        component_export,
+        // refused: func() -> bool
+        ty: |types| {
+            let nothing: [(&str, PrimitiveValType); 0] = [];
+            types
+                .function()
+                .params(nothing)
+                .result(PrimitiveValType::Bool);
+        },
+    };
+    rewrite_modules(&component.finish(), flag_module, |_| Ok(()), &hook)
+}
+
+/// How many memories instantiating `bytes` creates. Each module counts once for every time
+/// its component instantiates it, and the modules of a nested component once each.
+fn memories(bytes: &[u8]) -> Result<u64, Error> {
+    if !Parser::is_component(bytes) {
+        return declared(bytes);
+    }
+
+    let mut modules = Vec::new();
+    let mut total = 0;
+    let mut parser = Parser::new(0);
+    let mut offset = 0;
+    loop {
+        let payload = match parser.parse(&bytes[offset..], true).map_err(decode_error)? {
+            Chunk::Parsed { consumed, payload } => {
+                offset += consumed;
+                payload
+            }
+            // all the input is there, so it's never short of data
+            Chunk::NeedMoreData(_) => unreachable!(),
+        };
+        match payload {
+            Payload::ModuleSection {
+                unchecked_range, ..
+            } => {
+                modules.push(declared(&bytes[unchecked_range.clone()])?);
+                offset = unchecked_range.end;
+            }
+            Payload::ComponentSection {
+                unchecked_range, ..
+            } => {
+                total += memories(&bytes[unchecked_range.clone()])?;
+                offset = unchecked_range.end;
+            }
+            Payload::InstanceSection(section) => {
+                for instance in section {
+                    if let wasmparser::Instance::Instantiate { module_index, .. } =
+                        instance.map_err(decode_error)?
+                    {
+                        total += modules.get(module_index as usize).copied().unwrap_or(0);
+                    }
+                }
+            }
+            Payload::End(_) => return Ok(total),
+            _ => {}
+        }
+    }
+}
+
+/// How many memories a core module declares, not counting those it imports.
+fn declared(module: &[u8]) -> Result<u64, Error> {
+    for payload in Parser::new(0).parse_all(module) {
+        if let Payload::MemorySection(section) = payload.map_err(decode_error)? {
+            return Ok(section.count().into());
+        }
+    }
+    Ok(0)
+}
+
+fn decode_error(e: wasmparser::BinaryReaderError) -> Error {
+    Error::ComponentDecode(e.into())
+}
+
+/// Gives every memory a module declares a maximum within its `share` of the quota. Imported
+/// memories are capped where they are declared.
+struct Cap {
+    share: u64,
+    quota: Quota,
+}
+
+impl Reencode for Cap {
+    type Error = Quota;
+
+    fn parse_memory_section(
+        &mut self,
+        memories: &mut wasm_encoder::MemorySection,
+        section: wasmparser::MemorySectionReader<'_>,
+    ) -> Result<(), reencode::Error<Quota>> {
+        for memory in section {
+            let mut memory = self.memory_type(memory?);
+            let page_size_log2 = memory.page_size_log2.unwrap_or(DEFAULT_PAGE_SIZE_LOG2);
+            let max_pages = self.share >> page_size_log2.min(63);
+            if memory.minimum > max_pages {
+                return Err(reencode::Error::UserError(self.quota));
+            }
+            memory.maximum = Some(memory.maximum.map_or(max_pages, |max| max.min(max_pages)));
+            memories.memory(memory);
+        }
+        Ok(())
+    }
+}
+
+impl ReencodeComponent for Cap {}
+
+/// 64KiB pages, unless the memory says otherwise
+const DEFAULT_PAGE_SIZE_LOG2: u32 = 16;
+
+/// Where the flag and the functions around it go in a core module's index spaces
+#[derive(Debug, Default)]
+struct Flag {
+    global: u32,
+    /// The first of the functions, one growing each memory and then the one lowering the flag
+    func: u32,
+    /// The first of the types, growing a 32 bit and a 64 bit memory and then lowering the flag
+    ty: u32,
+    /// Whether each memory, imported ones first, is 64 bit
+    memory64: Vec<bool>,
+    /// Whether the module's own global and export sections have been seen yet
+    globals_written: bool,
+    exports_written: bool,
+}
+
+/// Adds the flag to a core module that grows memory, or returns `None` for one that doesn't.
+fn flag_module(module: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let mut flag = Flag::default();
+    let mut grows = false;
+    for payload in Parser::new(0).parse_all(module) {
+        match payload.map_err(decode_error)? {
+            Payload::TypeSection(section) => {
+                for group in section {
+                    flag.ty += group.map_err(decode_error)?.types().len() as u32;
+                }
+            }
+            Payload::ImportSection(section) => {
+                for import in section {
+                    match import.map_err(decode_error)?.ty {
+                        TypeRef::Func(_) => flag.func += 1,
+                        TypeRef::Global(_) => flag.global += 1,
+                        TypeRef::Memory(memory) => flag.memory64.push(memory.memory64),
+                        _ => {}
+                    }
+                }
+            }
+            Payload::FunctionSection(section) => flag.func += section.count(),
+            Payload::GlobalSection(section) => flag.global += section.count(),
+            Payload::MemorySection(section) => {
+                for memory in section {
+                    flag.memory64.push(memory.map_err(decode_error)?.memory64);
+                }
+            }
+            Payload::CodeSectionEntry(body) if !grows => {
+                let mut operators = body.get_operators_reader().map_err(decode_error)?;
+                while !operators.eof() {
+                    if let Operator::MemoryGrow { .. } = operators.read().map_err(decode_error)? {
+                        grows = true;
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !grows {
+        return Ok(None);
+    }
+
+    let mut flagged = wasm_encoder::Module::new();
+    flag.parse_core_module(&mut flagged, Parser::new(0), module)
+        .map_err(|e| Error::ComponentDecode(anyhow::anyhow!("{e}")))?;
+    Ok(Some(flagged.finish()))
+}
+
+impl Flag {
+    fn flag_global(&self) -> (GlobalType, ConstExpr) {
+        let ty = GlobalType {
+            val_type: ValType::I32,
+            mutable: true,
+            shared: false,
+        };
+        (ty, ConstExpr::i32_const(0))
+    }
+
+    /// The function lowering the flag
+    fn lower(&self) -> u32 {
+        self.func + self.memory64.len() as u32
+    }
+
+    /// Grows the memory, raising the flag if it can't.
+    fn grow(&self, memory: u32, memory64: bool) -> Function {
+        let (ty, refused) = if memory64 {
+            (ValType::I64, Instruction::I64Const(-1))
+        } else {
+            (ValType::I32, Instruction::I32Const(-1))
+        };
+        let mut f = Function::new([(1, ty)]);
+        f.instruction(&Instruction::LocalGet(0))
+            .instruction(&Instruction::MemoryGrow(memory))
+            .instruction(&Instruction::LocalTee(1))
+            .instruction(&refused)
+            .instruction(if memory64 {
+                &Instruction::I64Eq
+            } else {
+                &Instruction::I32Eq
+            })
+            .instruction(&Instruction::If(wasm_encoder::BlockType::Empty))
+            .instruction(&Instruction::I32Const(1))
+            .instruction(&Instruction::GlobalSet(self.global))
+            .instruction(&Instruction::End)
+            .instruction(&Instruction::LocalGet(1))
+            .instruction(&Instruction::End);
+        f
+    }
+}
+
+impl Reencode for Flag {
+    type Error = std::convert::Infallible;
+
+    fn parse_type_section(
+        &mut self,
+        types: &mut wasm_encoder::TypeSection,
+        section: wasmparser::TypeSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_type_section(self, types, section)?;
+        types.ty().function([ValType::I32], [ValType::I32]);
+        types.ty().function([ValType::I64], [ValType::I64]);
+        types.ty().function([], [ValType::I32]);
+        Ok(())
+    }
+
+    fn parse_function_section(
+        &mut self,
+        functions: &mut wasm_encoder::FunctionSection,
+        section: wasmparser::FunctionSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_function_section(self, functions, section)?;
+        for memory64 in &self.memory64 {
+            functions.function(self.ty + u32::from(*memory64));
+        }
+        functions.function(self.ty + 2);
+        Ok(())
+    }
+
+    fn parse_global_section(
+        &mut self,
+        globals: &mut GlobalSection,
+        section: wasmparser::GlobalSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_global_section(self, globals, section)?;
+        let (ty, init) = self.flag_global();
+        globals.global(ty, &init);
+        self.globals_written = true;
+        Ok(())
+    }
+
+    fn parse_export_section(
+        &mut self,
+        exports: &mut ExportSection,
+        section: wasmparser::ExportSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_export_section(self, exports, section)?;
+        exports.export(MODULE_EXPORT, ExportKind::Func, self.lower());
+        self.exports_written = true;
+        Ok(())
+    }
+
+    /// Adds the global and export sections where the module has none.
+    fn intersperse_section_hook(
+        &mut self,
+        module: &mut wasm_encoder::Module,
+        _after: Option<SectionId>,
+        before: Option<SectionId>,
+    ) -> Result<(), reencode::Error> {
+        let before = position(before);
+        if !self.globals_written && before > position(Some(SectionId::Global)) {
+            let mut globals = GlobalSection::new();
+            let (ty, init) = self.flag_global();
+            globals.global(ty, &init);
+            module.section(&globals);
+            self.globals_written = true;
+        }
+        if !self.exports_written && before > position(Some(SectionId::Export)) {
+            let mut exports = ExportSection::new();
+            exports.export(MODULE_EXPORT, ExportKind::Func, self.lower());
+            module.section(&exports);
+            self.exports_written = true;
+        }
+        Ok(())
+    }
+
+    fn parse_code_section(
+        &mut self,
+        code: &mut wasm_encoder::CodeSection,
+        section: wasmparser::CodeSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        reencode::utils::parse_code_section(self, code, section)?;
+        for (memory, memory64) in self.memory64.iter().enumerate() {
+            code.function(&self.grow(memory as u32, *memory64));
+        }
+
+        // lowers the flag, returning whether it was raised
+        let mut lower = Function::new([]);
+        lower
+            .instruction(&Instruction::GlobalGet(self.global))
+            .instruction(&Instruction::I32Const(0))
+            .instruction(&Instruction::GlobalSet(self.global))
+            .instruction(&Instruction::End);
+        code.function(&lower);
+        Ok(())
+    }
+
+    fn parse_function_body(
+        &mut self,
+        code: &mut wasm_encoder::CodeSection,
+        func: wasmparser::FunctionBody<'_>,
+    ) -> Result<(), reencode::Error> {
+        let mut f = self.new_function_with_parsed_locals(&func)?;
+        let mut reader = func.get_operators_reader()?;
+        while !reader.eof() {
+            match reader.read()? {
+                Operator::MemoryGrow { mem } => f.instruction(&Instruction::Call(self.func + mem)),
+                op => f.instruction(&self.instruction(op)?),
+            };
+        }
+        code.function(&f);
+        Ok(())
+    }
+}
+
+/// The memory flags of an instance made from a [limit]ed component.
+#[derive(Debug, Clone, Default)]
+pub struct Gauges(Vec<Func>);
+
+impl Gauges {
+    /// Finds the flags the instance exports. A component that wasn't limited has none.
+    pub fn new(instance: &Instance) -> Self {
+        let root = instance.exports().root();
+        Self(
+            (0..)
+                .map_while(|n| root.func(component_export(n)))
+                .collect(),
+        )
+    }
+
+    /// Lowers every flag, returning whether any of them was raised.
+    pub fn refused(&self, mut ctx: impl AsContextMut) -> Result<bool, Error> {
+        let mut refused = false;
+        for flag in &self.0 {
+            let mut raised = [Value::Bool(false)];
+            flag.call(&mut ctx, &[], &mut raised)?;
+            refused |= matches!(raised[0], Value::Bool(true));
+        }
+        Ok(refused)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_encoder::{
+        Component, InstanceSection, MemorySection, MemoryType, Module, ModuleArg, ModuleSection,
+    };
+
+    const PAGE: u64 = 1 << DEFAULT_PAGE_SIZE_LOG2;
+
+    fn memory(minimum: u64, maximum: Option<u64>) -> MemoryType {
+        MemoryType {
+            minimum,
+            maximum,
+            memory64: false,
+            shared: false,
+            page_size_log2: None,
+        }
+    }
+
+    /// A core module declaring the given memories.
+    fn module(memories: &[MemoryType]) -> Module {
+        let mut section = MemorySection::new();
+        for memory in memories {
+            section.memory(*memory);
+        }
+        let mut module = Module::new();
+        module.section(&section);
+        module
+    }
+
+    /// A component instantiating each of the given modules once.
+    fn component(modules: &[Module]) -> Vec<u8> {
+        let mut component = Component::new();
+        for module in modules {
+            component.section(&ModuleSection(module));
+        }
+        let mut instances = InstanceSection::new();
+        for index in 0..modules.len() as u32 {
+            let no_args: [(&str, ModuleArg); 0] = [];
+            instances.instantiate(index, no_args);
+        }
+        component.section(&instances);
+        component.finish()
+    }
+
+    /// The memories of every core module in `bytes`.
+    fn memories_in(bytes: &[u8]) -> Vec<wasmparser::MemoryType> {
+        let mut memories = Vec::new();
+        for payload in Parser::new(0).parse_all(bytes) {
+            if let Payload::MemorySection(section) = payload.unwrap() {
+                memories.extend(section.into_iter().map(Result::unwrap));
+            }
+        }
+        memories
+    }
+
+    #[test]
+    fn test_adds_maximum() {
+        let limited = limit(&module(&[memory(2, None)]).finish(), 4 * PAGE).unwrap();
+        assert_eq!(module(&[memory(2, Some(4))]).finish(), limited);
+    }
+
+    #[test]
+    fn test_lowers_maximum() {
+        let large = module(&[memory(1, Some(128))]).finish();
+        let limited = limit(&large, 3 * PAGE).unwrap();
+        assert_eq!(limited, module(&[memory(1, Some(3))]).finish());
+
+        // a smaller maximum is kept
+        let small = module(&[memory(1, Some(2))]).finish();
+        assert_eq!(limit(&small, 3 * PAGE).unwrap(), small);
+    }
+
+    #[test]
+    fn test_initial_over_quota() {
+        assert!(matches!(
+            limit(&module(&[memory(5, None)]).finish(), 4 * PAGE),
+            Err(Error::QuotaExceeded(Quota::Memory(_)))
+        ));
+    }
+
+    #[test]
+    fn test_nested_in_component() {
+        let component = component(&[module(&[memory(1, None)])]);
+        let limited = limit(&component, PAGE).unwrap();
+        assert_eq!(memories_in(&limited)[0].maximum, Some(1));
+    }
+
+    // two memories may only grow to the quota between them
+    #[test]
+    fn test_split_between_memories() {
+        let two = module(&[memory(1, None), memory(1, None)]).finish();
+        let maximums = memories_in(&limit(&two, 8 * PAGE).unwrap())
+            .iter()
+            .map(|memory| memory.maximum)
+            .collect::<Vec<_>>();
+        assert_eq!(maximums, [Some(4), Some(4)]);
+
+        // the same for two instances of modules with one each
+        let component = component(&[module(&[memory(1, None)]), module(&[memory(1, None)])]);
+        let maximums = memories_in(&limit(&component, 8 * PAGE).unwrap())
+            .iter()
+            .map(|memory| memory.maximum)
+            .collect::<Vec<_>>();
+        assert_eq!(maximums, [Some(4), Some(4)]);
+
+        // and each has to start within its share
+        assert!(matches!(
+            limit(
+                &module(&[memory(3, None), memory(1, None)]).finish(),
+                4 * PAGE
+            ),
+            Err(Error::QuotaExceeded(Quota::Memory(_)))
+        ));
+    }
+
+    #[test]
+    fn test_flags_growing_modules() {
+        // a function growing the memory by a page
+        let mut grows = Module::new();
+        let mut types = wasm_encoder::TypeSection::new();
+        types.ty().function([], [ValType::I32]);
+        grows.section(&types);
+        let mut functions = wasm_encoder::FunctionSection::new();
+        functions.function(0);
+        grows.section(&functions);
+        let mut memories = MemorySection::new();
+        memories.memory(memory(1, None));
+        grows.section(&memories);
+        let mut code = wasm_encoder::CodeSection::new();
+        let mut grow = Function::new([]);
+        grow.instruction(&Instruction::I32Const(1))
+            .instruction(&Instruction::MemoryGrow(0))
+            .instruction(&Instruction::End);
+        code.function(&grow);
+        grows.section(&code);
+
+        let flagged = flag_module(&grows.finish()).unwrap().unwrap();
+        wasmparser::Validator::new().validate_all(&flagged).unwrap();
+        let exports = Parser::new(0)
+            .parse_all(&flagged)
+            .find_map(|payload| match payload.unwrap() {
+                Payload::ExportSection(section) => Some(section),
+                _ => None,
+            })
+            .unwrap()
+            .into_iter()
+            .map(|export| export.unwrap().name)
+            .collect::<Vec<_>>();
+        assert_eq!(exports, [MODULE_EXPORT]);
+
+        // one that never grows is left as it is
+        assert!(flag_module(&module(&[memory(1, None)]).finish())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(matches!(
+            limit(b"not wasm", PAGE),
+            Err(Error::ComponentDecode(_))
+        ));
+        assert!(limit(b"\0asm", PAGE).is_err());
+    }
+}
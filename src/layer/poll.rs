@@ -1,10 +1,11 @@
 //! Ported from >https://github.com/bytecodealliance/wasmtime/blob/main/crates/wasi/src/poll.rs> to support async/await
-use super::{Resource, ResourceTable};
+use super::{noop_waker, Duration, HostResourceIndex, Resource, ResourceTable};
 use anyhow::Result;
 use std::any::Any;
 use std::future::Future;
-use std::pin::Pin;
+use std::pin::{pin, Pin};
 use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 
 pub type PollableFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
 pub type MakeFuture = for<'a> fn(&'a mut dyn Any) -> PollableFuture<'a>;
@@ -16,8 +17,12 @@ pub type MakeFuture = for<'a> fn(&'a mut dyn Any) -> PollableFuture<'a>;
 /// or writable. So, rather than containing a Future, which can only become Ready once, a
 /// Pollable contains a way to create a Future in each call to `poll`.
 pub struct Pollable {
-    pub index: u32,
+    /// The entry of the subscribed-to resource
+    pub index: HostResourceIndex,
     pub make_future: MakeFuture,
+    /// Removes the subscribed-to resource at `index` when this pollable is dropped, if the
+    /// pollable owned it
+    pub remove_index_on_delete: Option<fn(&mut ResourceTable, HostResourceIndex) -> Result<()>>,
 }
 
 /// A trait used internally within a [`Pollable`] to create a `pollable`
@@ -99,10 +104,138 @@ where
         stream.downcast_mut::<T>().unwrap().ready()
     }
 
+    fn remove_index_on_delete<T>(table: &mut ResourceTable, index: HostResourceIndex) -> Result<()>
+    where
+        T: Subscribe,
+    {
+        let resource = Resource::<T>::new_index(index);
+        table.delete(resource)?;
+        Ok(())
+    }
+
+    let mut table = table.lock().unwrap();
     let pollable = Pollable {
-        index: resource.rep(),
+        index: table.index(&resource)?,
         make_future: make_future::<T>,
+        remove_index_on_delete: if resource.owned() {
+            Some(remove_index_on_delete::<T>)
+        } else {
+            None
+        },
     };
 
-    Ok(table.lock().unwrap().push_child(pollable, &resource)?)
+    Ok(table.push_child(pollable, &resource)?)
+}
+
+/// Handles `[resource-drop]pollable`: deletes the pollable, and the resource it was subscribed
+/// to if it owned that.
+pub fn drop_pollable(table: &Mutex<ResourceTable>, pollable: Resource<Pollable>) -> Result<()> {
+    let mut table = table.lock().unwrap();
+    let pollable = table.delete(pollable)?;
+    if let Some(remove_index_on_delete) = pollable.remove_index_on_delete {
+        remove_index_on_delete(&mut table, pollable.index)?;
+    }
+    Ok(())
+}
+
+/// Polls the future once without blocking, returning its output if it was ready.
+pub fn poll_once<F: Future>(future: F) -> Option<F::Output> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let _runtime = enter_runtime();
+
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    match pin!(future).poll(&mut cx) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
+/// Blocks the calling thread until the future is ready, which is what a guest calling
+/// `wasi:io/poll` expects. Gives up after `timeout`.
+///
+/// Host timers are driven by the app's tokio runtime, or by a fallback one when there is none
+/// (as in tests). The app's runtime must be multi-threaded, so its timers keep running while
+/// this thread is blocked.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn block_on<F: Future>(future: F, timeout: Duration) -> Result<F::Output> {
+    let _runtime = enter_runtime();
+
+    futures_lite::future::block_on(futures_lite::future::or(
+        async { Ok(future.await) },
+        async {
+            tokio::time::sleep(timeout).await;
+            Err(anyhow::anyhow!("timed out after {timeout:?}"))
+        },
+    ))
+}
+
+/// Returns the future's output if it is ready, as a guest calling `wasi:io/poll` expects.
+///
+/// The browser's event loop can't run while a guest holds the thread, so nothing could make
+/// the future ready while it waited: it is polled once, and fails if it would have to wait.
+/// Plugins that run in the browser check `ready` instead, or have the host call them back with
+/// a timer.
+#[cfg(target_arch = "wasm32")]
+pub fn block_on<F: Future>(future: F, _timeout: Duration) -> Result<F::Output> {
+    poll_once(future).ok_or_else(|| anyhow::anyhow!("can't wait on the browser's thread"))
+}
+
+/// Enters a tokio runtime for host timers, unless the caller is already inside one.
+#[cfg(not(target_arch = "wasm32"))]
+fn enter_runtime() -> Option<tokio::runtime::EnterGuard<'static>> {
+    use std::sync::OnceLock;
+
+    static FALLBACK: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return None;
+    }
+    let runtime = FALLBACK.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_time()
+            .build()
+            .expect("failed to start the fallback runtime for host timers")
+    });
+    Some(runtime.enter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Never;
+
+    #[async_trait::async_trait]
+    impl Subscribe for Never {
+        async fn ready(&mut self) {
+            std::future::pending().await
+        }
+    }
+
+    #[test]
+    fn test_drop_pollable_removes_owned_resource() {
+        let table = Arc::new(Mutex::new(ResourceTable::new()));
+        let never = table.lock().unwrap().push(Never).unwrap();
+        let pollable = subscribe(table.clone(), never).unwrap();
+        assert_eq!(table.lock().unwrap().len(), 2);
+
+        drop_pollable(&table, pollable).unwrap();
+        assert!(table.lock().unwrap().is_empty());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_block_on() {
+        // the sleep is created inside block_on, where there is a runtime for it
+        let slept = block_on(
+            async { tokio::time::sleep(Duration::from_millis(10)).await },
+            Duration::from_secs(5),
+        );
+        assert!(slept.is_ok());
+
+        let timed_out = block_on(std::future::pending::<()>(), Duration::from_millis(10));
+        assert!(timed_out.is_err());
+    }
 }
@@ -0,0 +1,141 @@
+//! The standard `wasi:random` interfaces, so guests built with `getrandom`'s WASI backend get
+//! their randomness in one call rather than a byte at a time through `random-byte`.
+//!
+//! ```wit
+//! interface random {
+//!   get-random-bytes: func(len: u64) -> list<u8>;
+//!   get-random-u64: func() -> u64;
+//! }
+//!
+//! interface insecure-seed {
+//!   insecure-seed: func() -> tuple<u64, u64>;
+//! }
+//! ```
+//!
+//! The seed is drawn once per instance, as it's only meant for things like hash map DoS
+//! protection.
+use anyhow::bail;
+use rand::RngCore as _;
+use wasm_component_layer::{
+    Func, FuncType, Linker, List, ListType, Store, Tuple, TupleType, Value, ValueType,
+};
+
+use super::limits::Meter;
+use super::{runtime_layer, Inner};
+
+/// The `wasi:random/random` interface
+pub const RANDOM: &str = "wasi:random/random@0.2.2";
+
+/// The `wasi:random/insecure-seed` interface
+pub const INSECURE_SEED: &str = "wasi:random/insecure-seed@0.2.2";
+
+/// The most bytes `get-random-bytes` hands out in one call
+const MAX_RANDOM_BYTES: u64 = 1024 * 1024;
+
+/// `len` bytes from the host's cryptographically secure generator.
+fn random_bytes(len: u64) -> anyhow::Result<Vec<u8>> {
+    if len > MAX_RANDOM_BYTES {
+        bail!("Asked for {len} random bytes, more than the {MAX_RANDOM_BYTES} allowed per call");
+    }
+    let mut bytes = vec![0; len as usize];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    Ok(bytes)
+}
+
+/// Defines `wasi:random/random` and `wasi:random/insecure-seed`.
+pub(crate) fn add_to_linker<T: Inner + 'static>(
+    linker: &mut Linker,
+    store: &mut Store<T, runtime_layer::Engine>,
+    meter: &Meter,
+) -> anyhow::Result<()> {
+    let random = linker.define_instance(RANDOM.try_into()?)?;
+
+    // get-random-bytes: func(len: u64) -> list<u8>;
+    let meter_clone = meter.clone();
+    random.define_func(
+        "get-random-bytes",
+        Func::new(
+            &mut *store,
+            FuncType::new(
+                [ValueType::U64],
+                [ValueType::List(ListType::new(ValueType::U8))],
+            ),
+            move |_store, params, results| {
+                meter_clone.consume()?;
+                let Value::U64(len) = params[0] else {
+                    bail!("Incorrect input type, found {:?}", params[0]);
+                };
+                results[0] = Value::List(List::new(
+                    ListType::new(ValueType::U8),
+                    random_bytes(len)?.into_iter().map(Value::U8),
+                )?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    // get-random-u64: func() -> u64;
+    let meter_clone = meter.clone();
+    random.define_func(
+        "get-random-u64",
+        Func::new(
+            &mut *store,
+            FuncType::new([], [ValueType::U64]),
+            move |_store, _params, results| {
+                meter_clone.consume()?;
+                results[0] = Value::U64(rand::thread_rng().next_u64());
+                Ok(())
+            },
+        ),
+    )?;
+
+    let insecure_seed = linker.define_instance(INSECURE_SEED.try_into()?)?;
+
+    // insecure-seed: func() -> tuple<u64, u64>;
+    let seed_ty = TupleType::new(None, [ValueType::U64, ValueType::U64]);
+    let seed: (u64, u64) = rand::random();
+    let meter_clone = meter.clone();
+    insecure_seed.define_func(
+        "insecure-seed",
+        Func::new(
+            &mut *store,
+            FuncType::new([], [ValueType::Tuple(seed_ty.clone())]),
+            move |_store, _params, results| {
+                meter_clone.consume()?;
+                results[0] = Value::Tuple(Tuple::new(
+                    seed_ty.clone(),
+                    [Value::U64(seed.0), Value::U64(seed.1)],
+                )?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_bytes() {
+        assert!(random_bytes(0).unwrap().is_empty());
+
+        let bytes = random_bytes(32).unwrap();
+        assert_eq!(bytes.len(), 32);
+        // 32 zero bytes from a working generator would be astronomically unlikely
+        assert!(bytes.iter().any(|byte| *byte != 0));
+        assert_ne!(bytes, random_bytes(32).unwrap());
+    }
+
+    #[test]
+    fn test_random_bytes_are_bounded() {
+        assert_eq!(
+            random_bytes(MAX_RANDOM_BYTES).unwrap().len() as u64,
+            MAX_RANDOM_BYTES
+        );
+        assert!(random_bytes(MAX_RANDOM_BYTES + 1).is_err());
+        assert!(random_bytes(u64::MAX).is_err());
+    }
+}
@@ -36,6 +36,46 @@ where
         }
     }
 
+    /// Creates a new borrowed resource with the `rep` specified.
+    ///
+    /// A pollable [subscribe](super::subscribe)d to a borrowed resource becomes its child,
+    /// rather than deleting it when the pollable is dropped.
+    pub fn new_borrow(rep: u32) -> Resource<T> {
+        Resource {
+            state: AtomicResourceState::BORROW,
+            rep,
+            _marker: marker::PhantomData,
+        }
+    }
+
+    /// Creates a new owned resource for the table entry at `index`. Using it after the entry
+    /// was deleted fails, even once the slot holds another entry.
+    pub(crate) fn new_index(index: HostResourceIndex) -> Resource<T> {
+        Resource {
+            state: AtomicResourceState::index(index),
+            rep: index.index(),
+            _marker: marker::PhantomData,
+        }
+    }
+
+    /// A borrow of the same entry, which [subscribe](super::subscribe) makes a pollable the
+    /// child of.
+    ///
+    /// Only the [ResourceTable](super::ResourceTable) checks the entry's generation, so the
+    /// borrow is meant for the host call it was made in.
+    pub fn borrowed(&self) -> Resource<T> {
+        Resource::new_borrow(self.rep)
+    }
+
+    /// The table entry the resource was pushed to, with its generation, if it came from
+    /// [ResourceTable::push](super::ResourceTable::push).
+    pub fn index(&self) -> Option<HostResourceIndex> {
+        match self.state.get() {
+            ResourceState::Index(index) => Some(index),
+            ResourceState::Borrow | ResourceState::NotInTable => None,
+        }
+    }
+
     /// Returns the underlying 32-bit representation used to originally create
     /// this resource.
     pub fn rep(&self) -> u32 {
@@ -49,23 +89,30 @@ where
     pub fn owned(&self) -> bool {
         match self.state.get() {
             ResourceState::Borrow => false,
-            ResourceState::Taken | ResourceState::NotInTable | ResourceState::Index(_) => true,
+            ResourceState::NotInTable | ResourceState::Index(_) => true,
         }
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 enum ResourceState {
+    /// Made from a rep, owned somewhere else
     Borrow,
+    /// Made from a rep, owned
     NotInTable,
-    Taken,
+    /// Pushed to the table, owned
     Index(HostResourceIndex),
 }
 
 #[cfg(target_arch = "wasm32")]
 impl AtomicResourceState {
+    const BORROW: Self = Self(ResourceState::BORROW);
     const NOT_IN_TABLE: Self = Self(ResourceState::NOT_IN_TABLE);
 
+    fn index(index: HostResourceIndex) -> Self {
+        Self(ResourceState::Index(index).encode())
+    }
+
     /// get
     fn get(&self) -> ResourceState {
         // the u64 equivalent of AtomicU64 load
@@ -75,9 +122,15 @@ impl AtomicResourceState {
 
 #[cfg(not(target_arch = "wasm32"))]
 impl AtomicResourceState {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const BORROW: Self = Self(AtomicU64::new(ResourceState::BORROW));
     #[allow(clippy::declare_interior_mutable_const)]
     const NOT_IN_TABLE: Self = Self(AtomicU64::new(ResourceState::NOT_IN_TABLE));
 
+    fn index(index: HostResourceIndex) -> Self {
+        Self(AtomicU64::new(ResourceState::Index(index).encode()))
+    }
+
     fn get(&self) -> ResourceState {
         ResourceState::decode(self.0.load(Relaxed))
     }
@@ -87,26 +140,22 @@ impl ResourceState {
     // See comments on `state` above for info about these values.
     const BORROW: u64 = u64::MAX;
     const NOT_IN_TABLE: u64 = u64::MAX - 1;
-    const TAKEN: u64 = u64::MAX - 2;
 
     fn decode(bits: u64) -> ResourceState {
         match bits {
             Self::BORROW => Self::Borrow,
             Self::NOT_IN_TABLE => Self::NotInTable,
-            Self::TAKEN => Self::Taken,
             other => Self::Index(HostResourceIndex(other)),
         }
     }
 
-    // unused
-    //fn encode(&self) -> u64 {
-    //    match self {
-    //        Self::Borrow => Self::BORROW,
-    //        Self::NotInTable => Self::NOT_IN_TABLE,
-    //        Self::Taken => Self::TAKEN,
-    //        Self::Index(index) => index.0,
-    //    }
-    //}
+    fn encode(&self) -> u64 {
+        match self {
+            Self::Borrow => Self::BORROW,
+            Self::NotInTable => Self::NOT_IN_TABLE,
+            Self::Index(index) => index.0,
+        }
+    }
 }
 
 /// Host representation of an index into a table slot.
@@ -114,20 +163,25 @@ impl ResourceState {
 /// This is morally (u32, u32) but is encoded as a 64-bit integer. The low
 /// 32-bits are the table index and the upper 32-bits are the generation
 /// counter.
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+///
+/// The two largest values mark borrowed resources and those not in the table,
+/// which is why the table never hands out its last two slots.
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 #[repr(transparent)]
 pub struct HostResourceIndex(u64);
 
-//impl HostResourceIndex {
-//    fn new(idx: u32, gen: u32) -> HostResourceIndex {
-//        HostResourceIndex(u64::from(idx) | (u64::from(gen) << 32))
-//    }
-//
-//    fn index(&self) -> u32 {
-//        u32::try_from(self.0 & 0xffffffff).unwrap()
-//    }
-//
-//    fn gen(&self) -> u32 {
-//        u32::try_from(self.0 >> 32).unwrap()
-//    }
-//}
+impl HostResourceIndex {
+    pub(crate) fn new(idx: u32, gen: u32) -> HostResourceIndex {
+        HostResourceIndex(u64::from(idx) | (u64::from(gen) << 32))
+    }
+
+    /// The table slot
+    pub fn index(&self) -> u32 {
+        u32::try_from(self.0 & 0xffffffff).unwrap()
+    }
+
+    /// How many times the slot was reused before this entry
+    pub fn gen(&self) -> u32 {
+        u32::try_from(self.0 >> 32).unwrap()
+    }
+}
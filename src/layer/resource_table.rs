@@ -1,5 +1,5 @@
 #![allow(unused)]
-use super::Resource;
+use super::{HostResourceIndex, Resource};
 use core::any::Any;
 use core::fmt;
 use std::collections::BTreeSet;
@@ -9,10 +9,16 @@ use std::collections::BTreeSet;
 pub enum ResourceTableError {
     /// ResourceTable has no free keys
     Full,
+    /// ResourceTable already holds as many live entries as its limit allows
+    LimitReached(usize),
     /// Resource not present in table
     NotPresent,
+    /// The resource's entry was deleted, and its slot now holds another entry
+    Stale,
     /// Resource present in table, but with a different type
     WrongType,
+    /// Resource taken out of the table for a while, see [ResourceTable::take_any]
+    Taken,
     /// Resource cannot be deleted because child resources exist in the table. Consult wit docs for
     /// the particular resource to see which methods may return child resources.
     HasChildren,
@@ -22,8 +28,13 @@ impl fmt::Display for ResourceTableError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Full => write!(f, "resource table has no free keys"),
+            Self::LimitReached(limit) => {
+                write!(f, "resource table is limited to {limit} live entries")
+            }
             Self::NotPresent => write!(f, "resource not present"),
+            Self::Stale => write!(f, "resource was deleted, its handle is stale"),
             Self::WrongType => write!(f, "resource is of another type"),
+            Self::Taken => write!(f, "resource is in use by the host"),
             Self::HasChildren => write!(f, "resource has children"),
         }
     }
@@ -32,34 +43,62 @@ impl fmt::Display for ResourceTableError {
 impl std::error::Error for ResourceTableError {}
 
 /// The `ResourceTable` type maps a `Resource<T>` to its `T`.
+///
+/// Each slot counts how many times it was reused. The resources [push](ResourceTable::push)
+/// hands out remember the count, so one kept past its [delete](ResourceTable::delete) fails
+/// with [ResourceTableError::Stale] rather than reaching whatever took its slot.
 #[derive(Debug)]
 pub struct ResourceTable {
     entries: Vec<Entry>,
     free_head: Option<usize>,
+    /// Number of occupied entries
+    live: usize,
+    /// Maximum number of occupied entries, if any
+    limit: Option<usize>,
 }
 
 #[derive(Debug)]
 enum Entry {
-    Free { next: Option<usize> },
-    Occupied { entry: TableEntry },
+    Free {
+        next: Option<usize>,
+        /// The generation the slot's next entry gets
+        generation: u32,
+    },
+    Occupied {
+        entry: TableEntry,
+        generation: u32,
+    },
 }
 
 impl Entry {
     pub fn occupied(&self) -> Option<&TableEntry> {
         match self {
-            Self::Occupied { entry } => Some(entry),
+            Self::Occupied { entry, .. } => Some(entry),
             Self::Free { .. } => None,
         }
     }
 
     pub fn occupied_mut(&mut self) -> Option<&mut TableEntry> {
         match self {
-            Self::Occupied { entry } => Some(entry),
+            Self::Occupied { entry, .. } => Some(entry),
             Self::Free { .. } => None,
         }
     }
+
+    fn generation(&self) -> u32 {
+        match self {
+            Self::Free { generation, .. } | Self::Occupied { generation, .. } => *generation,
+        }
+    }
 }
 
+/// Stands in for an entry while it is [taken](ResourceTable::take_any) out of the table
+#[derive(Debug)]
+struct Taken;
+
+/// Slots from here on would collide with the states [Resource] encodes in its index.
+const MAX_SLOTS: usize = u32::MAX as usize - 1;
+
 /// This structure tracks parent and child relationships for a given table entry.
 ///
 /// Parents and children are referred to by table index. We maintain the
@@ -103,6 +142,8 @@ impl ResourceTable {
         ResourceTable {
             entries: Vec::new(),
             free_head: None,
+            live: 0,
+            limit: None,
         }
     }
 
@@ -111,28 +152,49 @@ impl ResourceTable {
         ResourceTable {
             entries: Vec::with_capacity(capacity),
             free_head: None,
+            live: 0,
+            limit: None,
         }
     }
 
+    /// Limit the number of live entries. Pushing past it fails with
+    /// [`ResourceTableError::LimitReached`], entries already in the table are kept.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// The number of live entries in the table.
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    /// Whether the table has no live entries.
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
     /// Inserts a new value `T` into this table, returning a corresponding
     /// `Resource<T>` which can be used to refer to it after it was inserted.
     pub fn push<T>(&mut self, entry: T) -> Result<Resource<T>, ResourceTableError>
     where
         T: Send + 'static,
     {
-        let idx = self.push_(TableEntry::new(Box::new(entry), None))?;
-        Ok(Resource::new_own(idx))
+        let index = self.push_(TableEntry::new(Box::new(entry), None))?;
+        Ok(Resource::new_index(index))
     }
 
-    /// Pop an index off of the free list, if it's not empty.
-    fn pop_free_list(&mut self) -> Option<usize> {
+    /// Pop an index off of the free list, if it's not empty, with the generation its next
+    /// entry gets.
+    fn pop_free_list(&mut self) -> Option<(usize, u32)> {
         if let Some(ix) = self.free_head {
             // Advance free_head to the next entry if one is available.
             match &self.entries[ix] {
-                Entry::Free { next } => self.free_head = *next,
+                Entry::Free { next, generation } => {
+                    self.free_head = *next;
+                    Some((ix, *generation))
+                }
                 Entry::Occupied { .. } => unreachable!(),
             }
-            Some(ix)
         } else {
             None
         }
@@ -140,36 +202,50 @@ impl ResourceTable {
 
     /// Free an entry in the table, returning its [`TableEntry`]. Add the index to the free list.
     fn free_entry(&mut self, ix: usize) -> TableEntry {
+        // a handle to the deleted entry won't match the slot's next one
+        let generation = self.entries[ix].generation().wrapping_add(1);
         let entry = match core::mem::replace(
             &mut self.entries[ix],
             Entry::Free {
                 next: self.free_head,
+                generation,
             },
         ) {
-            Entry::Occupied { entry } => entry,
+            Entry::Occupied { entry, .. } => entry,
             Entry::Free { .. } => unreachable!(),
         };
 
         self.free_head = Some(ix);
+        self.live -= 1;
 
         entry
     }
 
     /// Push a new entry into the table, returning its handle. This will prefer to use free entries
     /// if they exist, falling back on pushing new entries onto the end of the table.
-    fn push_(&mut self, e: TableEntry) -> Result<u32, ResourceTableError> {
-        if let Some(free) = self.pop_free_list() {
-            self.entries[free] = Entry::Occupied { entry: e };
-            Ok(free.try_into().unwrap())
-        } else {
-            let ix = self
-                .entries
-                .len()
-                .try_into()
-                .map_err(|_| ResourceTableError::Full)?;
-            self.entries.push(Entry::Occupied { entry: e });
-            Ok(ix)
+    fn push_(&mut self, e: TableEntry) -> Result<HostResourceIndex, ResourceTableError> {
+        if let Some(limit) = self.limit.filter(|limit| self.live >= *limit) {
+            return Err(ResourceTableError::LimitReached(limit));
         }
+        let (ix, generation) = if let Some((free, generation)) = self.pop_free_list() {
+            self.entries[free] = Entry::Occupied {
+                entry: e,
+                generation,
+            };
+            (free, generation)
+        } else {
+            let ix = self.entries.len();
+            if ix >= MAX_SLOTS {
+                return Err(ResourceTableError::Full);
+            }
+            self.entries.push(Entry::Occupied {
+                entry: e,
+                generation: 0,
+            });
+            (ix, 0)
+        };
+        self.live += 1;
+        Ok(HostResourceIndex::new(ix.try_into().unwrap(), generation))
     }
 
     fn occupied(&self, key: u32) -> Result<&TableEntry, ResourceTableError> {
@@ -179,13 +255,52 @@ impl ResourceTable {
             .ok_or(ResourceTableError::NotPresent)
     }
 
-    pub fn occupied_mut(&mut self, key: u32) -> Result<&mut TableEntry, ResourceTableError> {
+    fn occupied_mut(&mut self, key: u32) -> Result<&mut TableEntry, ResourceTableError> {
         self.entries
             .get_mut(key as usize)
             .and_then(Entry::occupied_mut)
             .ok_or(ResourceTableError::NotPresent)
     }
 
+    /// The slot `index` refers to, if its entry is still the one `index` was handed out for.
+    fn check(&self, index: HostResourceIndex) -> Result<u32, ResourceTableError> {
+        match self.entries.get(index.index() as usize) {
+            Some(entry @ Entry::Occupied { .. }) if entry.generation() == index.gen() => {
+                Ok(index.index())
+            }
+            Some(Entry::Occupied { .. }) => Err(ResourceTableError::Stale),
+            Some(Entry::Free { .. }) | None => Err(ResourceTableError::NotPresent),
+        }
+    }
+
+    /// The entry of `resource`, checking the generation of those the table handed out.
+    /// Resources made from a bare rep can only be checked for being present, and get the
+    /// generation of whatever is in their slot.
+    pub fn index<T>(&self, resource: &Resource<T>) -> Result<HostResourceIndex, ResourceTableError>
+    where
+        T: 'static,
+    {
+        match resource.index() {
+            Some(index) => self.check(index).map(|_| index),
+            None => {
+                let rep = resource.rep();
+                self.occupied(rep)?;
+                Ok(HostResourceIndex::new(
+                    rep,
+                    self.entries[rep as usize].generation(),
+                ))
+            }
+        }
+    }
+
+    /// The slot of `resource`, see [ResourceTable::index].
+    fn key<T>(&self, resource: &Resource<T>) -> Result<u32, ResourceTableError>
+    where
+        T: 'static,
+    {
+        self.index(resource).map(|index| index.index())
+    }
+
     /// Insert a resource at the next available index, and track that it has a
     /// parent resource.
     ///
@@ -215,11 +330,10 @@ impl ResourceTable {
         T: Send + 'static,
         U: 'static,
     {
-        let parent = parent.rep();
-        self.occupied(parent)?;
+        let parent = self.key(parent)?;
         let child = self.push_(TableEntry::new(Box::new(entry), Some(parent)))?;
-        self.occupied_mut(parent)?.add_child(child);
-        Ok(Resource::new_own(child))
+        self.occupied_mut(parent)?.add_child(child.index());
+        Ok(Resource::new_index(child))
     }
 
     /// Get an immutable reference to a resource of a given type at a given
@@ -227,13 +341,16 @@ impl ResourceTable {
     ///
     /// Multiple shared references can be borrowed at any given time.
     pub fn get<T: Any + Sized>(&self, key: &Resource<T>) -> Result<&T, ResourceTableError> {
-        self.get_(key.rep())?
+        self.get_(self.key(key)?)?
             .downcast_ref()
             .ok_or(ResourceTableError::WrongType)
     }
 
     fn get_(&self, key: u32) -> Result<&dyn Any, ResourceTableError> {
         let r = self.occupied(key)?;
+        if r.entry.is::<Taken>() {
+            return Err(ResourceTableError::Taken);
+        }
         Ok(&*r.entry)
     }
 
@@ -243,24 +360,66 @@ impl ResourceTable {
         &mut self,
         key: &Resource<T>,
     ) -> Result<&mut T, ResourceTableError> {
-        self.get_any_mut(key.rep())?
+        let key = self.key(key)?;
+        self.get_any_mut_(key)?
             .downcast_mut()
             .ok_or(ResourceTableError::WrongType)
     }
 
-    /// Returns the raw `Any` at the `key` index provided.
-    pub fn get_any_mut(&mut self, key: u32) -> Result<&mut dyn Any, ResourceTableError> {
+    /// Returns the raw `Any` at the `index` provided.
+    pub fn get_any_mut(
+        &mut self,
+        index: HostResourceIndex,
+    ) -> Result<&mut dyn Any, ResourceTableError> {
+        let key = self.check(index)?;
+        self.get_any_mut_(key)
+    }
+
+    fn get_any_mut_(&mut self, key: u32) -> Result<&mut dyn Any, ResourceTableError> {
         let r = self.occupied_mut(key)?;
+        if r.entry.is::<Taken>() {
+            return Err(ResourceTableError::Taken);
+        }
         Ok(&mut *r.entry)
     }
 
+    /// Takes the entry at `index` out of the table until it is [put back], so the host can
+    /// wait on it without holding the table. Its slot stays reserved, and the entry can't be
+    /// reached meanwhile.
+    ///
+    /// [put back]: ResourceTable::put_back
+    pub fn take_any(
+        &mut self,
+        index: HostResourceIndex,
+    ) -> Result<Box<dyn Any + Send>, ResourceTableError> {
+        let key = self.check(index)?;
+        self.get_any_mut_(key)?;
+        let r = self.occupied_mut(key)?;
+        Ok(std::mem::replace(&mut r.entry, Box::new(Taken)))
+    }
+
+    /// Puts an entry [taken](ResourceTable::take_any) from `index` back.
+    pub fn put_back(
+        &mut self,
+        index: HostResourceIndex,
+        entry: Box<dyn Any + Send>,
+    ) -> Result<(), ResourceTableError> {
+        let key = self.check(index)?;
+        let r = self.occupied_mut(key)?;
+        if !r.entry.is::<Taken>() {
+            return Err(ResourceTableError::NotPresent);
+        }
+        r.entry = entry;
+        Ok(())
+    }
+
     /// Same as `delete`, but typed
     pub fn delete<T>(&mut self, resource: Resource<T>) -> Result<T, ResourceTableError>
     where
         T: Any,
     {
         debug_assert!(resource.owned());
-        let entry = self.delete_entry(resource.rep())?;
+        let entry = self.delete_entry(self.key(&resource)?)?;
         match entry.entry.downcast() {
             Ok(t) => Ok(*t),
             Err(_e) => Err(ResourceTableError::WrongType),
@@ -268,7 +427,11 @@ impl ResourceTable {
     }
 
     fn delete_entry(&mut self, key: u32) -> Result<TableEntry, ResourceTableError> {
-        if !self.occupied(key)?.children.is_empty() {
+        let entry = self.occupied(key)?;
+        if entry.entry.is::<Taken>() {
+            return Err(ResourceTableError::Taken);
+        }
+        if !entry.children.is_empty() {
             return Err(ResourceTableError::HasChildren);
         }
         let e = self.free_entry(key as usize);
@@ -288,12 +451,11 @@ impl ResourceTable {
     /// with the same lifetime as the mutable reference to the [ResourceTable].
     pub fn iter_entries<T>(
         &mut self,
-        map: std::collections::HashMap<u32, T>,
+        map: std::collections::HashMap<HostResourceIndex, T>,
     ) -> impl Iterator<Item = (Result<&mut dyn Any, ResourceTableError>, T)> {
         map.into_iter().map(move |(k, v)| {
             let item = self
-                .occupied_mut(k)
-                .map(|e| Box::as_mut(&mut e.entry))
+                .get_any_mut(k)
                 // Safety: extending the lifetime of the mutable reference.
                 .map(|item| unsafe { &mut *(item as *mut dyn Any) });
             (item, v)
@@ -308,7 +470,7 @@ impl ResourceTable {
     where
         T: 'static,
     {
-        let parent_entry = self.occupied(parent.rep())?;
+        let parent_entry = self.occupied(self.key(parent)?)?;
         Ok(parent_entry.children.iter().map(|child_index| {
             let child = self.occupied(*child_index).expect("missing child");
             child.entry.as_ref()
@@ -351,3 +513,105 @@ pub fn test_free_list() {
     let x = table.push(()).unwrap();
     assert_eq!(x.rep(), 2);
 }
+
+#[test]
+pub fn test_limit() {
+    let mut table = ResourceTable::new();
+    table.set_limit(Some(2));
+
+    let x = table.push(()).unwrap();
+    let _y = table.push(()).unwrap();
+    assert!(matches!(
+        table.push(()),
+        Err(ResourceTableError::LimitReached(2))
+    ));
+    assert_eq!(table.len(), 2);
+
+    // Deleting an entry makes room for another.
+    table.delete(x).unwrap();
+    assert_eq!(table.len(), 1);
+    table.push(()).unwrap();
+}
+
+#[test]
+pub fn test_stale_handle() {
+    let mut table = ResourceTable::new();
+
+    let x = table.push(1u32).unwrap();
+    let stale = table.index(&x).unwrap();
+    table.delete(x).unwrap();
+
+    // the freed slot is empty
+    assert!(matches!(
+        table.get(&Resource::<u32>::new_index(stale)),
+        Err(ResourceTableError::NotPresent)
+    ));
+
+    // and once reused, the old handle doesn't reach the new entry
+    let y = table.push(2u32).unwrap();
+    assert_eq!(y.rep(), stale.index());
+    assert!(matches!(
+        table.get(&Resource::<u32>::new_index(stale)),
+        Err(ResourceTableError::Stale)
+    ));
+    assert!(matches!(
+        table.get_mut(&Resource::<u32>::new_index(stale)),
+        Err(ResourceTableError::Stale)
+    ));
+    assert!(matches!(
+        table.get_any_mut(stale),
+        Err(ResourceTableError::Stale)
+    ));
+    assert!(matches!(
+        table.delete(Resource::<u32>::new_index(stale)),
+        Err(ResourceTableError::Stale)
+    ));
+    assert!(matches!(
+        table.push_child((), &Resource::<u32>::new_index(stale)),
+        Err(ResourceTableError::Stale)
+    ));
+
+    assert_eq!(*table.get(&y).unwrap(), 2);
+    assert_eq!(table.delete(y).unwrap(), 2);
+}
+
+#[test]
+pub fn test_take_and_put_back() {
+    let mut table = ResourceTable::new();
+
+    let x = table.push(1u32).unwrap();
+    let index = table.index(&x).unwrap();
+    let taken = table.take_any(index).unwrap();
+    assert!(matches!(table.get(&x), Err(ResourceTableError::Taken)));
+    assert!(matches!(
+        table.take_any(index),
+        Err(ResourceTableError::Taken)
+    ));
+    // the slot isn't handed out again meanwhile
+    assert_eq!(table.len(), 1);
+
+    table.put_back(index, taken).unwrap();
+    assert_eq!(*table.get(&x).unwrap(), 1);
+}
+
+#[test]
+pub fn test_children_outlive_parents() {
+    let mut table = ResourceTable::new();
+
+    let parent = table.push("parent").unwrap();
+    let child = table.push_child("child", &parent).unwrap();
+    assert_eq!(table.iter_children(&parent).unwrap().count(), 1);
+
+    // the parent can't go while the child is there
+    let parent_index = table.index(&parent).unwrap();
+    assert!(matches!(
+        table.delete(parent),
+        Err(ResourceTableError::HasChildren)
+    ));
+    let parent = Resource::<&str>::new_index(parent_index);
+
+    table.delete(child).unwrap();
+    assert_eq!(table.iter_children(&parent).unwrap().count(), 0);
+    table.delete(parent).unwrap();
+    assert!(table.is_empty());
+}
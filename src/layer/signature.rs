@@ -0,0 +1,397 @@
+//! Signed plugins, so the host only runs components from publishers it trusts.
+//!
+//! A signature is an ed25519 public key followed by the signature it made, 96 bytes in all. It
+//! is kept in a [SECTION] custom section at the very end of the component, and signs every
+//! byte before that section. A plugin can also be signed without touching its component, by a
+//! sidecar file next to it holding the same 96 bytes over the whole component: [attach] turns
+//! it into the section, so both are checked the same way.
+//!
+//! The key must be one of the host's [TrustedKeys], which names its [Publisher]. What happens
+//! to a plugin no trusted publisher signed is up to the [SignaturePolicy]. A signature that
+//! doesn't match the component means it was changed after it was signed, and is always refused.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use ed25519_dalek::{Signature, Signer as _, SigningKey, VerifyingKey};
+use wasm_encoder::{CustomSection, Section as _};
+use wasmparser::{Parser, Payload};
+
+use super::manifest;
+use crate::Error;
+
+/// The name of the custom section holding the signature
+pub const SECTION: &str = "rdx-signature";
+
+/// The extension of a sidecar signature, added to the component's file name:
+/// `counter.wasm.sig`
+pub const SIDECAR_EXTENSION: &str = "sig";
+
+const KEY_LEN: usize = ed25519_dalek::PUBLIC_KEY_LENGTH;
+const SIGNATURE_LEN: usize = ed25519_dalek::SIGNATURE_LENGTH;
+
+/// What to do with a plugin no trusted publisher signed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignaturePolicy {
+    /// Refuse to run it
+    Require,
+    /// Run it, but log a warning
+    #[default]
+    Warn,
+    /// Run it
+    Allow,
+}
+
+impl std::str::FromStr for SignaturePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "require" => Ok(Self::Require),
+            "warn" => Ok(Self::Warn),
+            "allow" => Ok(Self::Allow),
+            other => Err(Error::Parse(format!(
+                "Unknown signature policy {other}, expected require, warn or allow"
+            ))),
+        }
+    }
+}
+
+/// Who signed a plugin, as the host knows them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Publisher {
+    /// The name the host trusts the key under
+    pub name: String,
+    /// The publisher's ed25519 public key
+    pub key: [u8; KEY_LEN],
+}
+
+impl Publisher {
+    /// The first bytes of the key in hex, enough to tell keys apart at a glance
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.key)
+    }
+}
+
+impl fmt::Display for Publisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.fingerprint())
+    }
+}
+
+/// The public keys of the publishers the host trusts, by key.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrustedKeys(BTreeMap<[u8; KEY_LEN], String>);
+
+impl TrustedKeys {
+    /// No keys at all, so no plugin counts as signed.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Also trusts the ed25519 public `key`, which signs as `publisher`.
+    pub fn trust(
+        mut self,
+        publisher: impl Into<String>,
+        key: [u8; KEY_LEN],
+    ) -> Result<Self, Error> {
+        VerifyingKey::from_bytes(&key)
+            .map_err(|e| Error::BadSignature(format!("invalid public key: {e}")))?;
+        self.0.insert(key, publisher.into());
+        Ok(self)
+    }
+
+    /// Reads a list of keys, one per line: the public key in hex, then the publisher's name.
+    /// Blank lines and those starting with `#` are skipped.
+    ///
+    /// ```text
+    /// # key                                                            publisher
+    /// 3b6a27bcceb6a42d62a3a8d02a6f0d73653215771de243a63ac048a18b59da29 Acme Plugins
+    /// ```
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut keys = Self::none();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, publisher) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| Error::Parse(format!("Expected a key and a publisher: {line}")))?;
+            keys = keys.trust(publisher.trim(), decode_key(key)?)?;
+        }
+        Ok(keys)
+    }
+
+    /// The publisher who owns `key`, if it is trusted.
+    pub fn publisher(&self, key: &[u8; KEY_LEN]) -> Option<Publisher> {
+        self.0.get(key).map(|name| Publisher {
+            name: name.clone(),
+            key: *key,
+        })
+    }
+
+    /// Whether no key is trusted at all
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Checks the component's signature against the trusted `keys`, before it is instantiated.
+///
+/// Returns who signed it, or `None` for a plugin no trusted publisher signed that the `policy`
+/// lets run anyway.
+pub fn verify(
+    bytes: &[u8],
+    keys: &TrustedKeys,
+    policy: SignaturePolicy,
+) -> Result<Option<Publisher>, Error> {
+    check(&sections(bytes)?, keys, policy)
+}
+
+/// Checks the signature among the component's [Sections], like [verify].
+pub fn check(
+    sections: &Sections<'_>,
+    keys: &TrustedKeys,
+    policy: SignaturePolicy,
+) -> Result<Option<Publisher>, Error> {
+    let untrusted = |reason: String| match policy {
+        SignaturePolicy::Require => Err(Error::Untrusted(reason)),
+        SignaturePolicy::Warn => {
+            tracing::warn!("Running a plugin no trusted publisher signed: {}", reason);
+            Ok(None)
+        }
+        SignaturePolicy::Allow => Ok(None),
+    };
+
+    let Some(section) = &sections.signature else {
+        return untrusted("it isn't signed".to_string());
+    };
+    let (key, signature) = parse(section.signature)?;
+    let Some(publisher) = keys.publisher(&key) else {
+        return untrusted(format!("key {} isn't trusted", fingerprint(&key)));
+    };
+
+    // a bad signature from a trusted key means the component was tampered with
+    let verifying_key = VerifyingKey::from_bytes(&key)
+        .map_err(|e| Error::BadSignature(format!("invalid public key: {e}")))?;
+    verifying_key
+        .verify_strict(section.signed, &signature)
+        .map_err(|_| Error::BadSignature(format!("the component isn't what {publisher} signed")))?;
+
+    tracing::debug!("Signed by {}", publisher);
+    Ok(Some(publisher))
+}
+
+/// The sidecar signature of `bytes`, to save next to the component.
+pub fn sidecar(bytes: &[u8], key: &SigningKey) -> Vec<u8> {
+    let mut sidecar = key.verifying_key().to_bytes().to_vec();
+    sidecar.extend_from_slice(&key.sign(bytes).to_bytes());
+    sidecar
+}
+
+/// Signs the component with `key`, adding the signature section to its end.
+pub fn sign(bytes: &[u8], key: &SigningKey) -> Vec<u8> {
+    attach(bytes, &sidecar(bytes, key))
+}
+
+/// Adds a sidecar signature to the end of the component, where [verify] finds it.
+///
+/// The sidecar signs the whole component, which is exactly what comes before the new section.
+pub fn attach(bytes: &[u8], sidecar: &[u8]) -> Vec<u8> {
+    let mut signed = bytes.to_vec();
+    CustomSection {
+        name: SECTION.into(),
+        data: sidecar.into(),
+    }
+    .append_to(&mut signed);
+    signed
+}
+
+/// A signature section and what it signs
+struct SignatureSection<'a> {
+    /// The component up to the section
+    signed: &'a [u8],
+    /// The key and the signature
+    signature: &'a [u8],
+}
+
+/// The sections the host reads from a component before it runs it.
+pub struct Sections<'a> {
+    /// The signature, if the component's last section is one
+    signature: Option<SignatureSection<'a>>,
+    /// The contents of every [manifest::SECTION], in the component or the modules in it, see
+    /// [manifest::from_sections]
+    pub manifests: Vec<&'a [u8]>,
+}
+
+/// Goes through the component once, for its signature and its manifests.
+pub fn sections(bytes: &[u8]) -> Result<Sections<'_>, Error> {
+    let mut depth = 0usize;
+    let mut last = None;
+    let mut manifests = Vec::new();
+    for payload in Parser::new(0).parse_all(bytes) {
+        let payload = payload.map_err(|e| Error::ComponentDecode(e.into()))?;
+        if let Payload::CustomSection(section) = &payload {
+            if section.name() == manifest::SECTION {
+                manifests.push(section.data());
+            }
+        }
+        match &payload {
+            Payload::Version { .. } => depth += 1,
+            Payload::End(_) => depth -= 1,
+            // only the component's own sections, not those of the modules nested in it
+            _ if depth == 1 => last = Some(payload),
+            _ => {}
+        }
+    }
+
+    let signature = match last {
+        Some(Payload::CustomSection(section)) if section.name() == SECTION => {
+            // the section as `attach` writes it, so exactly the bytes before it are signed
+            let signature = section.data();
+            let rebuilt = attach(&[], signature);
+            match bytes.strip_suffix(rebuilt.as_slice()) {
+                Some(signed) => Some(SignatureSection { signed, signature }),
+                None => {
+                    return Err(Error::BadSignature(
+                        "malformed signature section".to_string(),
+                    ))
+                }
+            }
+        }
+        _ => None,
+    };
+
+    Ok(Sections {
+        signature,
+        manifests,
+    })
+}
+
+fn parse(payload: &[u8]) -> Result<([u8; KEY_LEN], Signature), Error> {
+    if payload.len() != KEY_LEN + SIGNATURE_LEN {
+        return Err(Error::BadSignature(format!(
+            "expected {} bytes, found {}",
+            KEY_LEN + SIGNATURE_LEN,
+            payload.len()
+        )));
+    }
+    let (key, signature) = payload.split_at(KEY_LEN);
+    let key = key.try_into().expect("split at the key length");
+    let signature = Signature::from_slice(signature).expect("split at the signature length");
+    Ok((key, signature))
+}
+
+fn decode_key(hex: &str) -> Result<[u8; KEY_LEN], Error> {
+    let invalid = || Error::Parse(format!("Expected a {KEY_LEN} byte key in hex: {hex}"));
+    if hex.len() != KEY_LEN * 2 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut key = [0; KEY_LEN];
+    for (byte, pair) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+    }
+    Ok(key)
+}
+
+fn fingerprint(key: &[u8; KEY_LEN]) -> String {
+    key[..8].iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty component
+    const COMPONENT: &[u8] = b"\0asm\x0d\0\x01\0";
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn trusting(key: &SigningKey) -> TrustedKeys {
+        TrustedKeys::none()
+            .trust("acme", key.verifying_key().to_bytes())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_signed_by_a_trusted_key() {
+        let key = key(1);
+        let signed = sign(COMPONENT, &key);
+
+        let publisher = verify(&signed, &trusting(&key), SignaturePolicy::Require)
+            .unwrap()
+            .unwrap();
+        assert_eq!(publisher.name, "acme");
+        assert_eq!(publisher.key, key.verifying_key().to_bytes());
+
+        // a sidecar signs the same way
+        let attached = attach(COMPONENT, &sidecar(COMPONENT, &key));
+        assert_eq!(attached, signed);
+    }
+
+    #[test]
+    fn test_parse_trusted_keys() {
+        let key = key(1).verifying_key().to_bytes();
+        let hex: String = key.iter().map(|byte| format!("{byte:02x}")).collect();
+        let keys = TrustedKeys::parse(&format!("# publishers\n\n{hex} Acme Plugins\n")).unwrap();
+        assert_eq!(keys.publisher(&key).unwrap().name, "Acme Plugins");
+
+        assert!(TrustedKeys::parse(&hex).is_err());
+        assert!(TrustedKeys::parse("abcd Acme").is_err());
+        assert_eq!(
+            "require".parse::<SignaturePolicy>().unwrap(),
+            SignaturePolicy::Require
+        );
+    }
+
+    #[test]
+    fn test_policy() {
+        let trusted = trusting(&key(1));
+        let by_stranger = sign(COMPONENT, &key(2));
+
+        for bytes in [COMPONENT, &by_stranger] {
+            assert!(matches!(
+                verify(bytes, &trusted, SignaturePolicy::Require),
+                Err(Error::Untrusted(_))
+            ));
+            assert_eq!(
+                verify(bytes, &trusted, SignaturePolicy::Warn).unwrap(),
+                None
+            );
+            assert_eq!(
+                verify(bytes, &trusted, SignaturePolicy::Allow).unwrap(),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn test_tampered() {
+        let key = key(1);
+        let mut signed = sign(COMPONENT, &key);
+        // flip a bit in the signature
+        let last = signed.len() - 1;
+        signed[last] ^= 1;
+
+        // refused even when unsigned plugins are allowed
+        assert!(matches!(
+            verify(&signed, &trusting(&key), SignaturePolicy::Allow),
+            Err(Error::BadSignature(_))
+        ));
+    }
+
+    #[test]
+    fn test_signature_must_come_last() {
+        let key = key(1);
+        let mut signed = sign(COMPONENT, &key);
+        // an empty custom section after the signature
+        signed.extend_from_slice(b"\0\x02\x01x");
+
+        assert!(matches!(
+            verify(&signed, &trusting(&key), SignaturePolicy::Require),
+            Err(Error::Untrusted(_))
+        ));
+    }
+}
@@ -0,0 +1,484 @@
+//! Persistent per-plugin key/value storage, exposed to guests as `component:plugin/storage`.
+//!
+//! ```wit
+//! interface storage {
+//!   get: func(key: string) -> option<string>;
+//!   set: func(key: string, value: string);
+//!   delete: func(key: string);
+//!   list-keys: func() -> list<string>;
+//! }
+//! ```
+//!
+//! Every plugin gets its own [Namespace] on top of a shared [StorageBackend], so one plugin
+//! can never read or overwrite the keys of another.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use anyhow::bail;
+use wasm_component_layer::{
+    Func, FuncType, Linker, List, ListType, OptionType, OptionValue, Store, Value, ValueType,
+};
+
+use super::{runtime_layer, Inner, Meter};
+use crate::Error;
+
+/// The interface name guests import the storage functions from.
+pub const INTERFACE: &str = "component:plugin/storage";
+
+/// Where the plugins' key/value pairs are kept.
+pub trait StorageBackend: Send + Sync {
+    /// Returns the value stored under `key` in `namespace`, if any.
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<String>, Error>;
+
+    /// Stores `value` under `key` in `namespace`, replacing any previous value.
+    fn set(&self, namespace: &str, key: &str, value: &str) -> Result<(), Error>;
+
+    /// Removes `key` from `namespace`. Removing a missing key is not an error.
+    fn delete(&self, namespace: &str, key: &str) -> Result<(), Error>;
+
+    /// Lists all keys in `namespace`.
+    fn keys(&self, namespace: &str) -> Result<Vec<String>, Error>;
+
+    /// Persists any buffered writes.
+    fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// A plugin's view into a [StorageBackend].
+#[derive(Clone)]
+pub struct Namespace {
+    name: String,
+    backend: Arc<dyn StorageBackend>,
+}
+
+impl fmt::Debug for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Namespace")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Namespace {
+    /// Creates the namespace `name` on the given backend.
+    pub fn new(name: impl Into<String>, backend: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            name: name.into(),
+            backend,
+        }
+    }
+
+    /// The name of this namespace, usually the plugin name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        self.backend.get(&self.name, key)
+    }
+
+    pub fn set(&self, key: &str, value: &str) -> Result<(), Error> {
+        self.backend.set(&self.name, key, value)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), Error> {
+        self.backend.delete(&self.name, key)
+    }
+
+    pub fn keys(&self) -> Result<Vec<String>, Error> {
+        self.backend.keys(&self.name)
+    }
+
+    pub fn flush(&self) -> Result<(), Error> {
+        self.backend.flush()
+    }
+}
+
+/// Every namespace with its key/value pairs.
+pub type Snapshot = BTreeMap<String, BTreeMap<String, String>>;
+
+/// Keeps everything in memory. Handy for tests, and the base of [EframeStorage].
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: Mutex<Snapshot>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the storage pre-filled with the given [Snapshot].
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        Self {
+            entries: Mutex::new(snapshot),
+        }
+    }
+
+    /// A copy of every namespace and its entries.
+    pub fn snapshot(&self) -> Snapshot {
+        self.entries.lock().unwrap().clone()
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .get(namespace)
+            .and_then(|entries| entries.get(key))
+            .cloned())
+    }
+
+    fn set(&self, namespace: &str, key: &str, value: &str) -> Result<(), Error> {
+        self.entries
+            .lock()
+            .unwrap()
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> Result<(), Error> {
+        if let Some(entries) = self.entries.lock().unwrap().get_mut(namespace) {
+            entries.remove(key);
+        }
+        Ok(())
+    }
+
+    fn keys(&self, namespace: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .get(namespace)
+            .map(|entries| entries.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+}
+
+/// Keeps the entries in memory and reads/writes them from the [eframe::Storage] of the app,
+/// for when RDX is embedded in an eframe app (including on the web).
+#[derive(Debug, Default)]
+pub struct EframeStorage {
+    memory: MemoryStorage,
+}
+
+impl EframeStorage {
+    /// The key the plugin entries are saved under in the [eframe::Storage].
+    pub const KEY: &'static str = "rdx_plugin_storage";
+
+    /// Loads the previously saved entries, if any.
+    pub fn load(storage: Option<&dyn eframe::Storage>) -> Self {
+        let snapshot = storage
+            .and_then(|storage| eframe::get_value::<Snapshot>(storage, Self::KEY))
+            .unwrap_or_default();
+        Self {
+            memory: MemoryStorage::from_snapshot(snapshot),
+        }
+    }
+
+    /// Writes all entries into the [eframe::Storage]. Call this from [eframe::App::save].
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, Self::KEY, &self.memory.snapshot());
+    }
+}
+
+impl StorageBackend for EframeStorage {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<String>, Error> {
+        self.memory.get(namespace, key)
+    }
+
+    fn set(&self, namespace: &str, key: &str, value: &str) -> Result<(), Error> {
+        self.memory.set(namespace, key, value)
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> Result<(), Error> {
+        self.memory.delete(namespace, key)
+    }
+
+    fn keys(&self, namespace: &str) -> Result<Vec<String>, Error> {
+        self.memory.keys(namespace)
+    }
+}
+
+/// Stores each entry as a file in a directory per namespace.
+///
+/// Namespaces and keys are hex encoded into the file names, so a guest can't escape its
+/// directory with a key like `../other.wasm/secret`.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    root: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileStorage {
+    /// Stores the entries under the `root` directory, which is created on first write.
+    pub fn new(root: impl Into<std::path::PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn namespace_dir(&self, namespace: &str) -> std::path::PathBuf {
+        self.root.join(hex_encode(namespace))
+    }
+
+    fn entry_path(&self, namespace: &str, key: &str) -> std::path::PathBuf {
+        self.namespace_dir(namespace).join(hex_encode(key))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StorageBackend for FileStorage {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<String>, Error> {
+        match std::fs::read_to_string(self.entry_path(namespace, key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set(&self, namespace: &str, key: &str, value: &str) -> Result<(), Error> {
+        std::fs::create_dir_all(self.namespace_dir(namespace))?;
+        std::fs::write(self.entry_path(namespace, key), value)?;
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> Result<(), Error> {
+        match std::fs::remove_file(self.entry_path(namespace, key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn keys(&self, namespace: &str) -> Result<Vec<String>, Error> {
+        let dir = match std::fs::read_dir(self.namespace_dir(namespace)) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut keys = Vec::new();
+        for entry in dir {
+            if let Some(key) = entry?.file_name().to_str().and_then(hex_decode) {
+                keys.push(key);
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn hex_encode(s: &str) -> String {
+    s.bytes().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn hex_decode(s: &str) -> Option<String> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    let bytes = (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Defines the `component:plugin/storage` functions, scoped to the [Namespace] returned by
+/// [Inner::storage].
+pub(crate) fn add_to_linker<T: Inner + 'static>(
+    linker: &mut Linker,
+    store: &mut Store<T, runtime_layer::Engine>,
+    meter: &Meter,
+) -> anyhow::Result<()> {
+    let storage_interface = linker.define_instance(INTERFACE.try_into()?)?;
+
+    fn namespace<T: Inner>(data: &T) -> anyhow::Result<Namespace> {
+        data.storage()
+            .ok_or_else(|| anyhow::anyhow!("Storage is not available to this plugin"))
+    }
+
+    fn string_param(params: &[Value], ix: usize) -> anyhow::Result<&str> {
+        match params.get(ix) {
+            Some(Value::String(s)) => Ok(&**s),
+            other => bail!("Incorrect input type, found {:?}", other),
+        }
+    }
+
+    // get: func(key: string) -> option<string>;
+    let option_string = OptionType::new(ValueType::String);
+    let meter_clone = meter.clone();
+    storage_interface.define_func(
+        "get",
+        Func::new(
+            &mut *store,
+            FuncType::new(
+                [ValueType::String],
+                [ValueType::Option(option_string.clone())],
+            ),
+            move |store, params, results| {
+                meter_clone.consume()?;
+                let key = string_param(params, 0)?;
+                let value = namespace(store.data())?.get(key)?;
+                results[0] = Value::Option(OptionValue::new(
+                    option_string.clone(),
+                    value.map(|v| Value::String(v.into())),
+                )?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    // set: func(key: string, value: string);
+    let meter_clone = meter.clone();
+    storage_interface.define_func(
+        "set",
+        Func::new(
+            &mut *store,
+            FuncType::new([ValueType::String, ValueType::String], []),
+            move |store, params, _results| {
+                meter_clone.consume()?;
+                let key = string_param(params, 0)?;
+                let value = string_param(params, 1)?;
+                namespace(store.data())?.set(key, value)?;
+                Ok(())
+            },
+        ),
+    )?;
+
+    // delete: func(key: string);
+    let meter_clone = meter.clone();
+    storage_interface.define_func(
+        "delete",
+        Func::new(
+            &mut *store,
+            FuncType::new([ValueType::String], []),
+            move |store, params, _results| {
+                meter_clone.consume()?;
+                let key = string_param(params, 0)?;
+                namespace(store.data())?.delete(key)?;
+                Ok(())
+            },
+        ),
+    )?;
+
+    // list-keys: func() -> list<string>;
+    let meter_clone = meter.clone();
+    storage_interface.define_func(
+        "list-keys",
+        Func::new(
+            &mut *store,
+            FuncType::new([], [ValueType::List(ListType::new(ValueType::String))]),
+            move |store, _params, results| {
+                meter_clone.consume()?;
+                let keys = namespace(store.data())?.keys()?;
+                results[0] = Value::List(List::new(
+                    ListType::new(ValueType::String),
+                    keys.into_iter().map(|k| Value::String(k.into())),
+                )?);
+                Ok(())
+            },
+        ),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_namespaces_are_isolated() {
+        let backend: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let todo = Namespace::new("todo.wasm", backend.clone());
+        let login = Namespace::new("login.wasm", backend);
+
+        todo.set("items", "milk").unwrap();
+        login.set("items", "nope").unwrap();
+
+        assert_eq!(todo.get("items").unwrap(), Some("milk".to_string()));
+        assert_eq!(login.keys().unwrap(), vec!["items".to_string()]);
+
+        todo.delete("items").unwrap();
+        assert_eq!(todo.get("items").unwrap(), None);
+        assert_eq!(login.get("items").unwrap(), Some("nope".to_string()));
+    }
+
+    // the todo example keeps its list in storage, so a new instance picks it up
+    #[test]
+    fn test_guest_storage() {
+        use crate::layer::capability::{Capabilities, Capability};
+        use crate::layer::{Instantiator as _, LayerPlugin};
+        use crate::rdx::PluginPolicy;
+        use crate::State;
+
+        const WASM: &[u8] = include_bytes!("../../target/wasm32-unknown-unknown/release/todo.wasm");
+        let backend: Arc<dyn StorageBackend> = Arc::new(MemoryStorage::new());
+        let todo = || {
+            let policy = PluginPolicy {
+                capabilities: Capabilities::none()
+                    .grant(Capability::Emit)
+                    .grant(Capability::Storage),
+                ..Default::default()
+            };
+            let state = State::new(None)
+                .with_policy(policy)
+                .with_storage(Namespace::new("todo.wasm", backend.clone()));
+            LayerPlugin::new(WASM, state).unwrap()
+        };
+
+        let mut plugin = todo();
+        for item in ["milk", "eggs"] {
+            plugin
+                .call("add-todo", &[Value::String(item.into())])
+                .unwrap();
+        }
+        assert_eq!(
+            backend.get("todo.wasm", "todos").unwrap().as_deref(),
+            Some("milk\neggs")
+        );
+
+        let Some(Value::List(todos)) = todo().call("todos", &[]).unwrap() else {
+            panic!("todos should return a list");
+        };
+        let todos = todos
+            .iter()
+            .map(|todo| match todo {
+                Value::String(todo) => todo.to_string(),
+                other => panic!("expected a string, found {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(todos, ["milk", "eggs"]);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_file_storage_roundtrip() {
+        let root = std::env::temp_dir().join(format!("rdx-storage-{}", std::process::id()));
+        let storage = Namespace::new("todo.wasm", Arc::new(FileStorage::new(&root)));
+
+        storage.set("../escape", "value").unwrap();
+        assert_eq!(storage.get("../escape").unwrap(), Some("value".to_string()));
+        assert_eq!(storage.keys().unwrap(), vec!["../escape".to_string()]);
+
+        // survives a new backend on the same directory, like an app restart
+        let reopened = Namespace::new("todo.wasm", Arc::new(FileStorage::new(&root)));
+        assert_eq!(
+            reopened.get("../escape").unwrap(),
+            Some("value".to_string())
+        );
+
+        reopened.delete("../escape").unwrap();
+        assert!(reopened.keys().unwrap().is_empty());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
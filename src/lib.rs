@@ -11,7 +11,7 @@ pub use app::TemplateApp;
 
 mod rdx;
 use rdx::RdxApp;
-pub use rdx::{PluginDeets, State};
+pub use rdx::{PendingCall, PluginDeets, State, Worker};
 
 pub use wasm_component_layer;
 
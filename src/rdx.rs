@@ -1,13 +1,26 @@
 #![allow(clippy::arc_with_non_send_sync)]
 
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+#[cfg(target_arch = "wasm32")]
 use std::ops::Deref as _;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
 use crate::hteg::HtmlToEgui;
-use crate::layer::{Inner, Instantiator, LayerPlugin, ScopeRef, ScopeRefMut};
+use crate::layer::bus::{Bus, Endpoint, Subscription, TopicPermissions};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::layer::cache::ComponentCache;
+use crate::layer::capability::Capabilities;
+use crate::layer::guest_resource::ResourceHandle;
+use crate::layer::http::AllowedOrigins;
+use crate::layer::limits::{ExecutionLimits, Quotas};
+use crate::layer::manifest::Manifest;
+use crate::layer::signature::{Publisher, SignaturePolicy, TrustedKeys};
+use crate::layer::storage::{MemoryStorage, Namespace, StorageBackend};
+use crate::layer::{Duration, Instant};
+use crate::layer::{Inner, Instantiator, LayerPlugin, ScopeRef, ScopeRefMut, RUN_INTERFACE};
+use crate::Error;
 
 use rhai::{Dynamic, Scope};
 use wasm_component_layer::Value;
@@ -15,120 +28,778 @@ use wasm_component_layer::Value;
 #[cfg(target_arch = "wasm32")]
 use send_wrapper::SendWrapper;
 
+#[cfg(not(target_arch = "wasm32"))]
+mod plugin_dir;
+#[cfg(not(target_arch = "wasm32"))]
+use plugin_dir::PluginDir;
+
+pub(crate) mod convert;
+use convert::arguments;
+mod timers;
+use timers::Timers;
+
+mod worker;
+pub use worker::{PendingCall, Worker};
+
 pub struct RdxApp {
     pub(crate) plugins: HashMap<String, PluginDeets<State>>,
+    /// Plugins that failed to load, and why
+    failed: HashMap<String, Error>,
+    /// Plugins switched off, with the scope to restore when they're switched back on
+    disabled: HashMap<String, Scope<'static>>,
+    /// The component of every known plugin, to restart it from
+    sources: HashMap<String, Arc<[u8]>>,
+    /// The manifest of every plugin that was loaded and has one, kept while it is disabled
+    manifests: HashMap<String, Manifest>,
+    /// Plugins waiting to be instantiated by [RdxApp::load_pending], in the order they came
+    pending: VecDeque<String>,
+    /// How the plugins are hosted
+    config: RdxConfig,
+    /// Handed to every plugin, so emitting an event repaints the UI
+    ctx: Option<egui::Context>,
+    /// Carries messages between the plugins
+    bus: Bus,
+    /// The directory plugins are hot reloaded from
+    #[cfg(not(target_arch = "wasm32"))]
+    plugin_dir: Option<PluginDir>,
+    /// Where components are kept pre-processed between runs
+    #[cfg(not(target_arch = "wasm32"))]
+    cache: Option<ComponentCache>,
+}
+
+/// Where a plugin is in its lifecycle.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PluginStatus {
+    /// Loaded and running
+    Enabled,
+    /// Waiting to be loaded, see [RdxApp::load_pending]
+    Pending,
+    /// Switched off, see [RdxApp::disable]
+    Disabled,
+    /// Failed to load, with the reason
+    Failed(String),
+}
+
+/// How [RdxApp] hosts its plugins.
+#[derive(Clone)]
+pub struct RdxConfig {
+    /// Where the plugins' key/value pairs are kept, one [Namespace] per plugin
+    pub storage: Arc<dyn StorageBackend>,
+    /// The [PluginPolicy] of plugins without an entry in `policies`
+    pub default_policy: PluginPolicy,
+    /// The [PluginPolicy] of each plugin, by plugin name
+    pub policies: HashMap<String, PluginPolicy>,
+    /// The publishers whose signed plugins the host trusts
+    pub trusted_keys: TrustedKeys,
+    /// What to do with plugins none of the `trusted_keys` signed
+    pub signatures: SignaturePolicy,
+    /// A directory of `.wasm` components to load on top of the builtin plugins and to watch
+    /// for changes. A component with the same file name as a builtin plugin replaces it.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub plugin_dir: Option<std::path::PathBuf>,
+    /// A directory to keep components pre-processed in between runs, see [ComponentCache]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub cache_dir: Option<std::path::PathBuf>,
+}
+
+impl Default for RdxConfig {
+    fn default() -> Self {
+        Self {
+            storage: Arc::new(MemoryStorage::new()),
+            default_policy: PluginPolicy::default(),
+            policies: HashMap::new(),
+            trusted_keys: TrustedKeys::none(),
+            signatures: SignaturePolicy::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            plugin_dir: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            cache_dir: None,
+        }
+    }
+}
+
+impl RdxConfig {
+    /// The [PluginPolicy] of the named plugin
+    pub fn policy(&self, plugin: &str) -> PluginPolicy {
+        self.policies
+            .get(plugin)
+            .unwrap_or(&self.default_policy)
+            .clone()
+    }
+}
+
+/// What a plugin is allowed to do, and how much of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginPolicy {
+    /// The host imports the plugin may use. None unless granted, so a plugin only gets what
+    /// it was explicitly given.
+    pub capabilities: Capabilities,
+    /// Bounds on each call into the plugin, so it can't freeze the UI
+    pub limits: ExecutionLimits,
+    /// Bounds on the plugin's memory, resources and scope
+    pub quotas: Quotas,
+    /// Call the plugin's button and input handlers in the background, see [Worker]. On by
+    /// default, so a slow handler doesn't stall the UI
+    pub background: bool,
+    /// The origins the plugin may send HTTP requests to
+    pub http_origins: AllowedOrigins,
+    /// The topics the plugin may publish and subscribe to on the message bus
+    pub topics: TopicPermissions,
+}
+
+impl Default for PluginPolicy {
+    fn default() -> Self {
+        Self {
+            capabilities: Capabilities::none(),
+            limits: ExecutionLimits::unlimited()
+                .with_fuel(100_000_000)
+                .with_deadline(Duration::from_secs(2)),
+            quotas: Quotas::unlimited()
+                .with_memory(256 * 1024 * 1024)
+                .with_resources(1024)
+                .with_scope_entries(500),
+            background: true,
+            http_origins: AllowedOrigins::none(),
+            topics: TopicPermissions::none(),
+        }
+    }
 }
 
 impl Default for RdxApp {
+    /// An app without any plugins loaded.
     fn default() -> Self {
-        Self::new(None)
+        Self {
+            plugins: HashMap::new(),
+            failed: HashMap::new(),
+            disabled: HashMap::new(),
+            sources: HashMap::new(),
+            manifests: HashMap::new(),
+            pending: VecDeque::new(),
+            config: RdxConfig::default(),
+            ctx: None,
+            bus: Bus::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            plugin_dir: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            cache: None,
+        }
     }
 }
 
-// a closure that enables us to register a function by name with zero arguments
-fn register(deets: &mut PluginDeets<State>, fn_name: String, arguments: Vec<Value>) {
-    let plugin_clone = deets.plugin.clone();
-    deets
-        .engine
-        .borrow_mut()
-        .register_fn(fn_name.clone(), move || {
-            let res = {
-                let mut lock = plugin_clone.lock().unwrap();
-                lock.call(&fn_name, &arguments).unwrap()
-            };
+/// Whether `wasm_bytes` are the builtin plugin called `name`, as compiled into the host.
+fn is_builtin(name: &str, wasm_bytes: &[u8]) -> bool {
+    crate::BUILTIN_PLUGINS
+        .iter()
+        .any(|(builtin, bytes)| *builtin == name && *bytes == wasm_bytes)
+}
 
-            // a recurive function that converts List type into Dynamic type
-            fn value_to_dynamic(v: Value) -> Dynamic {
-                match v {
-                    Value::Bool(b) => Dynamic::from(b),
-                    Value::Option(ov) => match ov.deref().clone() {
-                        Some(v) => value_to_dynamic(v),
-                        None => false.into(),
-                    },
-                    Value::String(s) => Dynamic::from(s.to_string()),
-                    Value::U8(u) => Dynamic::from(u),
-                    Value::List(list) => {
-                        let list = list.into_iter().map(value_to_dynamic).collect::<Vec<_>>();
-                        Dynamic::from(list)
-                    }
-                    Value::Tuple(t) => {
-                        let t = t.into_iter().map(value_to_dynamic).collect::<Vec<_>>();
-                        Dynamic::from(t)
-                    }
-                    Value::F32(f) => Dynamic::from(f),
-                    Value::F64(f) => Dynamic::from(f),
-                    Value::U32(u) => Dynamic::from(u),
-                    Value::U64(u) => Dynamic::from(u),
-                    _ => false.into(),
-                }
-            }
+/// Registers every function the plugin exports from [RUN_INTERFACE] in the Rhai engine, with
+/// its arity, so the script can call it. Kebab-case names become snake_case, as Rhai
+/// identifiers can't have dashes: `add-todo` is called as `add_todo(todo)`.
+///
+/// The calls go through the plugin's `worker` and return a [PendingCall] right away, see
+/// [Worker::call_pending].
+fn register_exports<T: Inner + Clone + Send + Sync + 'static>(
+    engine: &mut rhai::Engine,
+    worker: &Arc<Worker<T>>,
+) {
+    let plugin = worker.plugin();
+    let exports = match plugin.lock().unwrap().exports() {
+        Ok(exports) => exports,
+        Err(e) => {
+            tracing::warn!("Failed to list the plugin's exports: {:?}", e);
+            return;
+        }
+    };
 
-            // convert the returned result into Dynamic type
-            res.map(value_to_dynamic).unwrap_or(false.into())
+    // the guest's resources are opaque to scripts, they can only be passed back to exports
+    engine
+        .register_type_with_name::<ResourceHandle>("Resource")
+        .register_fn("to_string", |handle: &mut ResourceHandle| {
+            format!("{handle:?}")
+        })
+        .register_fn("to_debug", |handle: &mut ResourceHandle| {
+            format!("{handle:?}")
         });
+
+    // an `err` result becomes a script error once the value is read
+    engine
+        .register_type_with_name::<PendingCall>("Pending")
+        .register_get("ready", |call: &mut PendingCall| call.is_ready())
+        .register_get(
+            "value",
+            |call: &mut PendingCall| -> Result<Dynamic, Box<rhai::EvalAltResult>> {
+                call.value().map_err(|e| {
+                    Box::new(rhai::EvalAltResult::ErrorRuntime(e, rhai::Position::NONE))
+                })
+            },
+        );
+
+    for (export, func_ty) in exports {
+        // the host calls `load` itself
+        if export == "load" {
+            continue;
+        }
+
+        let rhai_name = export.replace('-', "_");
+        let params = func_ty.params().to_vec();
+        tracing::info!("Registering function: {}/{}", rhai_name, params.len());
+
+        let worker = worker.clone();
+        // Dynamic parameters accept any type, the arguments are converted below
+        let arg_types = vec![std::any::TypeId::of::<Dynamic>(); params.len()];
+        // `register_raw_fn` is only marked deprecated as its API may still change
+        #[allow(deprecated)]
+        engine.register_raw_fn(
+            rhai_name,
+            arg_types,
+            move |_context, args| -> Result<Dynamic, Box<rhai::EvalAltResult>> {
+                // arguments are named by position, the component doesn't tell us their names
+                let args = args
+                    .iter_mut()
+                    .enumerate()
+                    .map(|(i, arg)| (format!("#{}", i + 1), std::mem::take(&mut **arg)))
+                    .collect();
+                let values = arguments(&export, &params, args).map_err(|e| e.to_string())?;
+
+                // the UI doesn't wait for the export, the worker fills the value in
+                Ok(Dynamic::from(worker.call_pending(&export, values)))
+            },
+        );
+    }
 }
+
+/// Lets the Rhai script schedule calls to the plugin's exports, see [timers].
+fn register_timers(engine: &mut rhai::Engine, timers: &Arc<Mutex<Timers>>) {
+    fn millis(ms: i64) -> Duration {
+        Duration::from_millis(ms.max(0) as u64)
+    }
+
+    let timers_clone = timers.clone();
+    engine.register_fn("every", move |export: &str, ms: i64| {
+        timers_clone
+            .lock()
+            .unwrap()
+            .every(export, millis(ms), Instant::now());
+    });
+
+    let timers_clone = timers.clone();
+    engine.register_fn("after", move |export: &str, ms: i64| {
+        timers_clone
+            .lock()
+            .unwrap()
+            .after(export, millis(ms), Instant::now());
+    });
+
+    let timers_clone = timers.clone();
+    engine.register_fn("cancel", move |export: &str| {
+        timers_clone.lock().unwrap().cancel(export)
+    });
+}
+
+/// Lets the Rhai script publish messages, and have those on a topic delivered to an export.
+fn register_bus(
+    engine: &mut rhai::Engine,
+    endpoint: Endpoint,
+    handlers: &Arc<Mutex<HashMap<String, Handler>>>,
+) {
+    let endpoint_clone = endpoint.clone();
+    engine.register_fn(
+        "publish",
+        move |topic: &str, payload: &str| -> Result<i64, Box<rhai::EvalAltResult>> {
+            match endpoint_clone.publish(topic, payload) {
+                Ok(delivered) => Ok(delivered as i64),
+                Err(e) => Err(e.to_string().into()),
+            }
+        },
+    );
+
+    let handlers_clone = handlers.clone();
+    engine.register_fn(
+        "subscribe",
+        move |topic: &str, export: &str| -> Result<(), Box<rhai::EvalAltResult>> {
+            let mut handlers = handlers_clone.lock().unwrap();
+            // Scripts run every frame, so subscribing again keeps the queued messages
+            if let Some(handler) = handlers.get_mut(topic) {
+                handler.export = export.to_string();
+                return Ok(());
+            }
+            let subscription = endpoint.subscribe(topic).map_err(|e| e.to_string())?;
+            handlers.insert(
+                topic.to_string(),
+                Handler {
+                    export: export.to_string(),
+                    subscription,
+                },
+            );
+            Ok(())
+        },
+    );
+
+    let handlers_clone = handlers.clone();
+    engine.register_fn("unsubscribe", move |topic: &str| {
+        handlers_clone.lock().unwrap().remove(topic).is_some()
+    });
+}
+
 impl RdxApp {
-    pub fn new(ctx: Option<egui::Context>) -> Self {
-        let mut plugins = HashMap::new();
+    /// Queues the builtin plugins, hosted as described by the [RdxConfig], followed by those
+    /// in its `plugin_dir`.
+    ///
+    /// None of them is instantiated yet, so the app starts just as fast however many plugins
+    /// there are. [RdxApp::load_pending] loads them over the first frames. A plugin that fails
+    /// to load is then skipped and kept in `failed`, so one broken `.wasm` doesn't take the
+    /// others down with it. Only a plugin directory that can't be read or watched fails the
+    /// whole app.
+    pub fn with_config(ctx: Option<egui::Context>, config: RdxConfig) -> Result<Self, Error> {
+        // deliver the messages on the next frame
+        let bus_ctx = ctx.clone();
+        let bus = Bus::new().on_publish(move || {
+            if let Some(ctx) = &bus_ctx {
+                ctx.request_repaint();
+            }
+        });
+
+        let mut app = Self {
+            config,
+            ctx,
+            bus,
+            ..Default::default()
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(dir) = &app.config.cache_dir {
+            // only slower without one
+            match ComponentCache::new(dir) {
+                Ok(cache) => app.cache = Some(cache),
+                Err(e) => tracing::warn!("Failed to open the component cache: {}", e),
+            }
+        }
+
         for (name, wasm_bytes) in crate::BUILTIN_PLUGINS.iter() {
-            // TODO: init from wasm logic somehow!
-            // scope.set_or_push("count", 0);
-            tracing::info!("Loading plugin: {}", name);
-
-            let mut plugin = LayerPlugin::new(wasm_bytes, State::new(ctx.clone()));
-            let rdx_source = plugin.call("load", &[]).unwrap();
-            let Some(Value::String(rdx_source)) = rdx_source else {
-                panic!("RDX Source should be a string");
-            };
+            app.queue_plugin(name, wasm_bytes);
+        }
 
-            let arc_plugin = Arc::new(Mutex::new(plugin));
-            let mut plugin_deets =
-                PluginDeets::new(name.to_string(), arc_plugin.clone(), rdx_source.to_string());
-
-            // call("register", &[])
-            match arc_plugin.lock().unwrap().call("register", &[]) {
-                Ok(Some(Value::List(list))) => {
-                    for fn_name in &list {
-                        if let Value::String(fn_name) = fn_name {
-                            tracing::info!(
-                                "Registering function: {:?} from plugin: {:?}",
-                                fn_name,
-                                name
-                            );
-                            register(&mut plugin_deets, fn_name.to_string(), vec![]);
-                        }
-                    }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(dir) = app.config.plugin_dir.clone() {
+            let ctx = app.ctx.clone();
+            let plugin_dir = PluginDir::watch(dir, move || {
+                if let Some(ctx) = &ctx {
+                    ctx.request_repaint();
                 }
-                Ok(_) => {}
+            })?;
+
+            for (name, wasm_bytes) in plugin_dir.plugins()? {
+                app.queue_plugin(&name, &wasm_bytes);
+            }
+
+            app.plugin_dir = Some(plugin_dir);
+        }
+
+        Ok(app)
+    }
+
+    /// Instantiates the plugin, runs its `load` export and registers its functions.
+    ///
+    /// A plugin already loaded under the same name is replaced.
+    #[cfg(test)]
+    pub fn load_plugin(&mut self, name: &str, wasm_bytes: &[u8]) -> Result<(), Error> {
+        self.sources.insert(name.to_string(), wasm_bytes.into());
+        let plugin_deets = self.instantiate(name, wasm_bytes, Scope::new())?;
+        self.replace(name, plugin_deets);
+        Ok(())
+    }
+
+    /// Queues the plugin to be instantiated by [RdxApp::load_pending].
+    ///
+    /// A plugin already loaded under the same name keeps running until then.
+    pub fn queue_plugin(&mut self, name: &str, wasm_bytes: &[u8]) {
+        self.sources.insert(name.to_string(), wasm_bytes.into());
+        if !self.pending.iter().any(|pending| pending == name) {
+            self.pending.push_back(name.to_string());
+        }
+    }
+
+    /// Instantiates the queued plugins, one after another until `budget` is spent, so a long
+    /// queue is spread over several frames instead of freezing one. At least one is loaded
+    /// on every call. Cheap when the queue is empty, so it can be called every frame.
+    ///
+    /// Returns whether any are still waiting, in which case egui is asked for another frame.
+    pub fn load_pending(&mut self, budget: Duration) -> bool {
+        let started = Instant::now();
+        while let Some(name) = self.pending.pop_front() {
+            let Ok(wasm_bytes) = self.source(&name) else {
+                continue;
+            };
+            match self.instantiate(&name, &wasm_bytes, Scope::new()) {
+                Ok(plugin_deets) => self.replace(&name, plugin_deets),
                 Err(e) => {
-                    tracing::warn!("Failed to call register on plugin: {:?}", e);
+                    tracing::error!("Failed to load plugin {}: {}", name, e);
+                    self.failed.insert(name, e);
                 }
             }
-            plugins.insert(name.to_string(), plugin_deets);
+            if started.elapsed() >= budget {
+                break;
+            }
+        }
+
+        let waiting = !self.pending.is_empty();
+        if let (true, Some(ctx)) = (waiting, &self.ctx) {
+            ctx.request_repaint();
+        }
+        waiting
+    }
+
+    /// Replaces the plugin with a new instance of `wasm_bytes`, carrying its Rhai scope over.
+    ///
+    /// The guest's own memory starts afresh, only the variables in the scope are kept. If the
+    /// new instance fails to load, the old one keeps running. A disabled plugin stays disabled,
+    /// and is enabled from the new component. One still waiting to be loaded is loaded from
+    /// the new component.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload_plugin(&mut self, name: &str, wasm_bytes: &[u8]) -> Result<(), Error> {
+        if self.disabled.contains_key(name) || self.is_pending(name) {
+            self.sources.insert(name.to_string(), wasm_bytes.into());
+            return Ok(());
+        }
+
+        let scope = self
+            .plugins
+            .get(name)
+            .map(PluginDeets::scope)
+            .unwrap_or_default();
+        let plugin_deets = self.instantiate(name, wasm_bytes, scope)?;
+        self.sources.insert(name.to_string(), wasm_bytes.into());
+        self.replace(name, plugin_deets);
+        Ok(())
+    }
+
+    /// Unloads the plugin, dropping its store, engine and resource table.
+    ///
+    /// Returns whether there was a plugin by that name.
+    pub fn unload(&mut self, name: &str) -> bool {
+        self.failed.remove(name);
+        self.disabled.remove(name);
+        self.pending.retain(|pending| pending != name);
+        self.manifests.remove(name);
+        if let Some(plugin_deets) = self.plugins.remove(name) {
+            plugin_deets.unload();
+        }
+        self.sources.remove(name).is_some()
+    }
+
+    /// Switches the plugin off. Its instance is unloaded, but its scope is kept until it is
+    /// enabled again.
+    pub fn disable(&mut self, name: &str) -> Result<(), Error> {
+        if self.is_pending(name) {
+            self.pending.retain(|pending| pending != name);
+            self.disabled.insert(name.to_string(), Scope::new());
+            return Ok(());
+        }
+        let plugin_deets = self
+            .plugins
+            .remove(name)
+            .ok_or_else(|| Error::PluginNotFound(name.to_string()))?;
+        self.disabled.insert(name.to_string(), plugin_deets.scope());
+        plugin_deets.unload();
+        Ok(())
+    }
+
+    /// Switches a disabled plugin back on, with the scope it had when it was disabled. One
+    /// waiting to be loaded is loaded straight away.
+    pub fn enable(&mut self, name: &str) -> Result<(), Error> {
+        if self.is_pending(name) {
+            return self.restart(name);
+        }
+        let Some(scope) = self.disabled.get(name).cloned() else {
+            // already enabled, or not known at all
+            if self.plugins.contains_key(name) {
+                return Ok(());
+            }
+            return Err(Error::PluginNotFound(name.to_string()));
+        };
+        let wasm_bytes = self.source(name)?;
+        let plugin_deets = self.instantiate(name, &wasm_bytes, scope)?;
+        self.replace(name, plugin_deets);
+        Ok(())
+    }
+
+    /// Starts the plugin over from its component with an empty scope. This also retries a
+    /// plugin that failed to load, and enables a disabled one.
+    ///
+    /// If the new instance fails to load, a running one keeps running.
+    pub fn restart(&mut self, name: &str) -> Result<(), Error> {
+        let wasm_bytes = self.source(name)?;
+        let plugin_deets = self.instantiate(name, &wasm_bytes, Scope::new())?;
+        self.replace(name, plugin_deets);
+        Ok(())
+    }
+
+    /// The trusted publisher who signed the running plugin, if one did.
+    pub fn publisher(&self, name: &str) -> Option<&Publisher> {
+        self.plugins.get(name)?.publisher.as_ref()
+    }
+
+    /// Whether the plugin is one of the builtin plugins, which run without a signature.
+    pub fn is_builtin(&self, name: &str) -> bool {
+        self.sources
+            .get(name)
+            .is_some_and(|wasm_bytes| is_builtin(name, wasm_bytes))
+    }
+
+    /// What the plugin says about itself in its [Manifest], if it has one. Only known once the
+    /// plugin has been loaded.
+    pub fn manifest(&self, name: &str) -> Option<&Manifest> {
+        self.manifests.get(name)
+    }
+
+    /// Every known plugin and its [PluginStatus], sorted by name.
+    pub fn statuses(&self) -> Vec<(String, PluginStatus)> {
+        let mut statuses = self
+            .sources
+            .keys()
+            .map(|name| {
+                let status = if self.plugins.contains_key(name) {
+                    PluginStatus::Enabled
+                } else if self.is_pending(name) {
+                    PluginStatus::Pending
+                } else if let Some(e) = self.failed.get(name) {
+                    PluginStatus::Failed(e.to_string())
+                } else {
+                    PluginStatus::Disabled
+                };
+                (name.clone(), status)
+            })
+            .collect::<Vec<_>>();
+        statuses.sort_by(|(a, _), (b, _)| a.cmp(b));
+        statuses
+    }
+
+    fn source(&self, name: &str) -> Result<Arc<[u8]>, Error> {
+        self.sources
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::PluginNotFound(name.to_string()))
+    }
+
+    fn is_pending(&self, name: &str) -> bool {
+        self.pending.iter().any(|pending| pending == name)
+    }
+
+    /// Puts the new instance of the plugin in place, unloading the one it replaces.
+    fn replace(&mut self, name: &str, plugin_deets: PluginDeets<State>) {
+        self.failed.remove(name);
+        self.disabled.remove(name);
+        self.pending.retain(|pending| pending != name);
+        match &plugin_deets.manifest {
+            Some(manifest) => self.manifests.insert(name.to_string(), manifest.clone()),
+            None => self.manifests.remove(name),
+        };
+        if let Some(old) = self.plugins.insert(name.to_string(), plugin_deets) {
+            old.unload();
         }
+    }
 
-        Self { plugins }
+    /// Reloads the plugins whose files changed in the plugin directory since the last call.
+    /// Cheap when nothing changed, so it can be called every frame.
+    pub fn reload_changed(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let Some(plugin_dir) = &self.plugin_dir else {
+                return;
+            };
+            for (name, wasm_bytes) in plugin_dir.changed() {
+                tracing::info!("Reloading plugin: {}", name);
+                if let Err(e) = self.reload_plugin(&name, &wasm_bytes) {
+                    tracing::error!("Failed to reload plugin {}: {}", name, e);
+                    if !self.plugins.contains_key(&name) {
+                        self.sources.insert(name.clone(), wasm_bytes.into());
+                        self.failed.insert(name, e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn instantiate(
+        &self,
+        name: &str,
+        wasm_bytes: &[u8],
+        scope: Scope<'static>,
+    ) -> Result<PluginDeets<State>, Error> {
+        // TODO: init from wasm logic somehow!
+        // scope.set_or_push("count", 0);
+        tracing::info!("Loading plugin: {}", name);
+
+        let policy = self.config.policy(name);
+        let background = policy.background;
+        // the builtin plugins are compiled into the host, so they are trusted as much as it is.
+        // One replaced from the plugin directory has other bytes, and is checked like the rest
+        let signatures = if is_builtin(name, wasm_bytes) {
+            SignaturePolicy::Allow
+        } else {
+            self.config.signatures
+        };
+        let state = State::new(self.ctx.clone())
+            .with_name(name)
+            .with_scope(scope)
+            .with_storage(Namespace::new(
+                name.to_string(),
+                self.config.storage.clone(),
+            ))
+            .with_bus(self.bus.endpoint(name, policy.topics.clone()))
+            .with_policy(policy)
+            .with_trust(self.config.trusted_keys.clone(), signatures);
+        #[cfg(not(target_arch = "wasm32"))]
+        let state = match &self.cache {
+            Some(cache) => state.with_cache(cache.clone()),
+            None => state,
+        };
+        let mut plugin = LayerPlugin::new(wasm_bytes, state)?;
+
+        let rdx_source = match plugin.call("load", &[]) {
+            Ok(Some(Value::String(rdx_source))) => rdx_source,
+            Ok(other) => {
+                return Err(Error::WrongReturnType(format!(
+                    "`load` should return the RDX source as a string, got {other:?}"
+                )))
+            }
+            Err(Error::FuncNotFound(func)) => {
+                return Err(Error::MissingExport(format!("{RUN_INTERFACE}#{func}")))
+            }
+            Err(e) => return Err(e),
+        };
+
+        // its exports are registered in the Rhai engine as it's created
+        let publisher = plugin.publisher().cloned();
+        let manifest = plugin.manifest().cloned();
+        let arc_plugin = Arc::new(Mutex::new(plugin));
+        let mut plugin_deets =
+            PluginDeets::new(name.to_string(), arc_plugin, rdx_source.to_string());
+        plugin_deets.publisher = publisher;
+        plugin_deets.manifest = manifest;
+        if background {
+            plugin_deets = plugin_deets.in_background();
+        }
+
+        Ok(plugin_deets)
+    }
+
+    /// Calls the plugin exports whose timers are due, and asks egui to repaint in time for the
+    /// next one. Call it every frame.
+    pub fn run_timers(&mut self) {
+        let now = Instant::now();
+        let next = self
+            .plugins
+            .values()
+            .filter_map(|plugin_deets| plugin_deets.run_timers(now))
+            .min();
+
+        if let (Some(next), Some(ctx)) = (next, &self.ctx) {
+            ctx.request_repaint_after(next.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    /// Delivers the messages published since the last frame to the exports the plugins'
+    /// scripts subscribed to them. Call it every frame.
+    pub fn deliver_messages(&mut self) {
+        for plugin_deets in self.plugins.values() {
+            plugin_deets.deliver_messages();
+        }
+    }
+
+    /// Persists any buffered plugin storage writes.
+    pub fn save(&self) {
+        if let Err(e) = self.config.storage.flush() {
+            tracing::error!("Failed to save plugin storage: {:?}", e);
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct State {
+    name: String,
     scope: Arc<Mutex<Scope<'static>>>,
     egui_ctx: Option<egui::Context>,
+    storage: Option<Namespace>,
+    bus: Option<Endpoint>,
+    policy: PluginPolicy,
+    trusted_keys: TrustedKeys,
+    signatures: SignaturePolicy,
+    #[cfg(not(target_arch = "wasm32"))]
+    cache: Option<ComponentCache>,
 }
 
 impl State {
     pub fn new(ctx: Option<egui::Context>) -> Self {
         Self {
+            name: "plugin".to_string(),
             scope: Arc::new(Mutex::new(Scope::new())),
             egui_ctx: ctx,
+            storage: None,
+            bus: None,
+            policy: PluginPolicy::default(),
+            trusted_keys: TrustedKeys::none(),
+            signatures: SignaturePolicy::Allow,
+            #[cfg(not(target_arch = "wasm32"))]
+            cache: None,
         }
     }
+
+    /// Attributes the plugin's logs to `name`
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Starts the plugin off with the variables in `scope`, such as those of the instance it
+    /// replaces
+    pub fn with_scope(mut self, scope: Scope<'static>) -> Self {
+        self.scope = Arc::new(Mutex::new(scope));
+        self
+    }
+
+    /// Gives the plugin persistent key/value storage in the given [Namespace]
+    pub fn with_storage(mut self, storage: Namespace) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Connects the plugin to the message bus through `endpoint`
+    pub fn with_bus(mut self, endpoint: Endpoint) -> Self {
+        self.bus = Some(endpoint);
+        self
+    }
+
+    /// Holds the plugin to the given [PluginPolicy]
+    pub fn with_policy(mut self, policy: PluginPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Only runs the plugin if one of the `trusted_keys` signed it, or the `signatures` policy
+    /// lets it run unsigned
+    pub fn with_trust(mut self, trusted_keys: TrustedKeys, signatures: SignaturePolicy) -> Self {
+        self.trusted_keys = trusted_keys;
+        self.signatures = signatures;
+        self
+    }
+
+    /// Keeps the plugin's component pre-processed in `cache` between runs
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_cache(mut self, cache: ComponentCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
 }
 
 impl Inner for State {
     fn save(&self) {
-        // Save state to disk if you like
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.flush() {
+                tracing::error!("Failed to save storage {}: {:?}", storage.name(), e);
+            }
+        }
     }
 
     /// Updates the scope variable to the given value
@@ -155,6 +826,53 @@ impl Inner for State {
     fn into_scope(self) -> rhai::Scope<'static> {
         self.scope.lock().unwrap().clone()
     }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn storage(&self) -> Option<Namespace> {
+        self.storage.clone()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.policy.capabilities.clone()
+    }
+
+    fn execution_limits(&self) -> ExecutionLimits {
+        self.policy.limits
+    }
+
+    fn quotas(&self) -> Quotas {
+        self.policy.quotas
+    }
+
+    fn bus(&self) -> Option<Endpoint> {
+        self.bus.clone()
+    }
+
+    fn http_origins(&self) -> AllowedOrigins {
+        self.policy.http_origins.clone()
+    }
+
+    fn request_repaint(&self) {
+        if let Some(egui_ctx) = &self.egui_ctx {
+            egui_ctx.request_repaint();
+        }
+    }
+
+    fn trusted_keys(&self) -> TrustedKeys {
+        self.trusted_keys.clone()
+    }
+
+    fn signature_policy(&self) -> SignaturePolicy {
+        self.signatures
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn cache(&self) -> Option<ComponentCache> {
+        self.cache.clone()
+    }
 }
 
 /// The plugin and all the details required to run it,
@@ -163,14 +881,30 @@ impl Inner for State {
 pub struct PluginDeets<T: Inner + Send> {
     /// The name of the plugin
     name: String,
+    /// Who signed the plugin, if a trusted publisher did
+    pub publisher: Option<Publisher>,
+    /// What the plugin says about itself, if it has a manifest
+    pub manifest: Option<Manifest>,
     /// Reference counted impl [Instantiator] so we can pass it into the rhai engine closure
     pub plugin: Arc<Mutex<dyn Instantiator<T>>>,
+    /// Calls the plugin's button and input handlers
+    worker: Arc<Worker<T>>,
     /// The rhai engine
     pub engine: Rc<RefCell<rhai::Engine>>,
     /// The AST of the RDX source
     ast: Option<rhai::AST>,
     /// The egui context, so we can `.show()` an [egui::Window]
     ctx: Option<egui::Context>,
+    /// Exports the Rhai script asked to have called later
+    timers: Arc<Mutex<Timers>>,
+    /// The export to deliver each subscribed topic's messages to, by topic
+    handlers: Arc<Mutex<HashMap<String, Handler>>>,
+}
+
+/// Where the messages of a topic the Rhai script subscribed to go.
+struct Handler {
+    export: String,
+    subscription: Subscription,
 }
 
 impl<T: Inner + Clone + Send + Sync + 'static> PluginDeets<T> {
@@ -180,6 +914,18 @@ impl<T: Inner + Clone + Send + Sync + 'static> PluginDeets<T> {
 
         engine.set_max_map_size(500); // allow object maps with only up to 500 properties
 
+        let timers = Arc::new(Mutex::new(Timers::new()));
+        register_timers(&mut engine, &timers);
+
+        let worker = Arc::new(Worker::immediate(plugin.clone()));
+        register_exports(&mut engine, &worker);
+
+        let handlers = Arc::new(Mutex::new(HashMap::new()));
+        let endpoint = plugin.lock().unwrap().store().data().bus();
+        if let Some(endpoint) = endpoint {
+            register_bus(&mut engine, endpoint, &handlers);
+        }
+
         // Compile the RDX source once ahead of time
         let ast = match engine.compile(&rdx_source) {
             Ok(ast) => Some(ast),
@@ -191,19 +937,96 @@ impl<T: Inner + Clone + Send + Sync + 'static> PluginDeets<T> {
 
         Self {
             name,
+            publisher: None,
+            manifest: None,
+            worker,
             plugin,
             engine: Rc::new(RefCell::new(engine)),
             ast,
             ctx: None,
+            timers,
+            handlers,
         }
     }
 
+    /// Calls the subscribed exports with the messages published on their topics, as
+    /// `func(topic: string, payload: string)`.
+    pub fn deliver_messages(&self) {
+        let messages = self
+            .handlers
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|handler| {
+                let export = handler.export.clone();
+                handler
+                    .subscription
+                    .drain()
+                    .into_iter()
+                    .map(move |message| (export.clone(), message))
+            })
+            .collect::<Vec<_>>();
+
+        for (export, message) in messages {
+            self.worker.call(
+                &export,
+                vec![
+                    Value::String(message.topic.into()),
+                    Value::String(message.payload.into()),
+                ],
+            );
+        }
+    }
+
+    /// Calls the exports whose timers are due at `now`, and returns when the next one is due.
+    ///
+    /// The exports are called through the plugin's [Worker], which logs their failures, and
+    /// the timer tries again next time. An export that doesn't exist has its timer cancelled.
+    pub fn run_timers(&self, now: Instant) -> Option<Instant> {
+        let due = self.timers.lock().unwrap().due(now);
+        for export in due {
+            match self.worker.arguments(&export, vec![]) {
+                Ok(arguments) => self.worker.call(&export, arguments),
+                Err(Error::FuncNotFound(_)) => {
+                    tracing::error!("Plugin {} has no export {} to time", self.name, export);
+                    self.timers.lock().unwrap().cancel(&export);
+                }
+                Err(e) => {
+                    tracing::error!("Timer of {} failed to call {}: {}", self.name, export, e);
+                }
+            }
+        }
+        self.timers.lock().unwrap().next_deadline()
+    }
+
+    /// Calls the plugin's button and input handlers in the background, so a slow handler
+    /// doesn't stall rendering.
+    pub fn in_background(mut self) -> Self {
+        self.worker = Arc::new(Worker::background(&self.name, self.plugin.clone()));
+        // registered again, so the script's calls go through the new worker too
+        register_exports(&mut self.engine.borrow_mut(), &self.worker);
+        self
+    }
+
+    /// Unloads the plugin.
+    ///
+    /// The functions registered in the [rhai::Engine] hold on to the plugin, and `render` holds
+    /// on to the engine itself, so the engine is emptied first to let the plugin's store drop.
+    pub fn unload(self) {
+        *self.engine.borrow_mut() = rhai::Engine::new();
+    }
+
+    /// A copy of the plugin's [rhai::Scope]
+    pub fn scope(&self) -> rhai::Scope<'static> {
+        self.worker.state().into_scope()
+    }
+
     /// Registers functions in the rhai Engine
     pub fn register_fn(&mut self) {
         #[cfg(target_arch = "wasm32")]
-        let plugin_clone = SendWrapper::new(self.plugin.clone());
+        let worker = SendWrapper::new(self.worker.clone());
         #[cfg(not(target_arch = "wasm32"))]
-        let plugin_clone = self.plugin.clone();
+        let worker = self.worker.clone();
 
         let name = self.name.clone();
         let Some(ctx) = self.ctx.clone() else {
@@ -211,9 +1034,14 @@ impl<T: Inner + Clone + Send + Sync + 'static> PluginDeets<T> {
             return;
         };
 
+        let Some(ast) = self.ast.clone() else {
+            tracing::warn!("RDX source of {} did not compile", self.name);
+            return;
+        };
+
         let html_to_egui = Arc::new(Mutex::new(send_wrapper::SendWrapper::new(HtmlToEgui::new(
             self.engine.clone(),
-            self.ast.clone().unwrap(),
+            ast,
         ))));
 
         tracing::info!("CREATED HTML TO EGUI Struct");
@@ -225,15 +1053,15 @@ impl<T: Inner + Clone + Send + Sync + 'static> PluginDeets<T> {
                 .max_size(ctx.available_rect().size())
                 .max_width(ctx.available_rect().width())
                 .show(&ctx, |ui| {
-                    // [browser]: unwrap the sendwrapper to get the plugin
+                    // [browser]: unwrap the sendwrapper to get the worker
                     #[cfg(target_arch = "wasm32")]
-                    let plugin_clone = plugin_clone.deref();
+                    let worker = worker.deref();
 
                     if let Err(e) =
                         html_to_egui
                             .lock()
                             .unwrap()
-                            .parse_and_render(ctx.clone(), ui, html, plugin_clone.clone())
+                            .parse_and_render(ctx.clone(), ui, html, &worker)
                     {
                         tracing::error!(
                             "Failed to parse RDX source for the plugin: {}; with error: {:?}, source {}",
@@ -293,11 +1121,9 @@ impl<T: Inner + Clone + Send + Sync + 'static> PluginDeets<T> {
 
         if let Some(ast) = &self.ast {
             // Get the scope from the plugin and clone it
-            let mut scope = {
-                let plugin = self.plugin.lock().unwrap();
-                // TODO: scope is just a copy, because otherwise the app locks up...
-                plugin.store().data().clone().into_scope()
-            };
+            // TODO: scope is just a copy, because otherwise the app locks up...
+            // Taken from the worker, so a handler running in the background doesn't block
+            let mut scope = self.worker.state().into_scope();
 
             // We have to run the script with only a copy of the scope,
             // because inside that script we use locks on the plugin to change its state.
@@ -347,6 +1173,21 @@ impl<T: Inner + Clone + Send + Sync + 'static> PluginDeets<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::layer::capability::Capability;
+
+    /// An app granting every plugin the `emit` the counter needs
+    fn app() -> RdxApp {
+        RdxApp {
+            config: RdxConfig {
+                default_policy: PluginPolicy {
+                    capabilities: Capabilities::none().grant(Capability::Emit),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
 
     // test calling a tick() function in the rhai script
     #[test]
@@ -387,4 +1228,284 @@ mod tests {
             tracing::error!("Failed to call tick function: {:?}", e);
         }
     }
+
+    #[test]
+    fn test_exports_are_registered() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+        let policy = PluginPolicy {
+            capabilities: Capabilities::none().grant(Capability::Emit),
+            ..Default::default()
+        };
+        let plugin = LayerPlugin::new(WASM, State::new(None).with_policy(policy)).unwrap();
+        let plugin_deets = PluginDeets::new(
+            "counter".to_string(),
+            Arc::new(Mutex::new(plugin)),
+            String::new(),
+        );
+
+        let engine = plugin_deets.engine.borrow();
+        // `increment-count` is callable as `increment_count`
+        assert_eq!(
+            engine
+                .eval::<rhai::INT>("increment_count(); current().value")
+                .unwrap(),
+            1
+        );
+        // with its own arity only
+        assert!(engine.eval::<Dynamic>("current(1)").is_err());
+        // `load` is the host's
+        assert!(engine.eval::<Dynamic>("load()").is_err());
+    }
+
+    #[test]
+    fn test_nothing_granted_by_default() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+        let mut app = RdxApp::default();
+        assert!(matches!(
+            app.load_plugin("counter.wasm", WASM),
+            Err(Error::CapabilityNotGranted {
+                capability: Capability::Emit,
+                ..
+            })
+        ));
+
+        // until the host grants it
+        app.config.policies.insert(
+            "counter.wasm".to_string(),
+            PluginPolicy {
+                capabilities: Capabilities::none().grant(Capability::Emit),
+                ..Default::default()
+            },
+        );
+        app.load_plugin("counter.wasm", WASM).unwrap();
+    }
+
+    #[test]
+    fn test_plugins_load_lazily() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+        let mut app = app();
+        app.queue_plugin("a", WASM);
+        app.queue_plugin("b", WASM);
+        app.queue_plugin("c", WASM);
+        assert!(app.plugins.is_empty());
+        assert!(app
+            .statuses()
+            .iter()
+            .all(|(_, status)| *status == PluginStatus::Pending));
+
+        // one at a time when there's no time to spare
+        assert!(app.load_pending(Duration::ZERO));
+        assert!(app.plugins.contains_key("a"));
+        assert_eq!(app.plugins.len(), 1);
+
+        // switching a pending plugin on loads it now, switching it off takes it off the queue
+        app.enable("c").unwrap();
+        app.disable("b").unwrap();
+        assert!(!app.load_pending(Duration::ZERO));
+        assert_eq!(
+            app.statuses(),
+            vec![
+                ("a".to_string(), PluginStatus::Enabled),
+                ("b".to_string(), PluginStatus::Disabled),
+                ("c".to_string(), PluginStatus::Enabled),
+            ]
+        );
+        app.enable("b").unwrap();
+        assert_eq!(app.plugins.len(), 3);
+    }
+
+    /// Evaluates `script` in the plugin's Rhai engine.
+    /// Runs `script` in the plugin's engine. If it ends in a call to an export, waits for the
+    /// value the export returns.
+    fn eval<R: Clone + Send + Sync + 'static>(
+        app: &RdxApp,
+        name: &str,
+        script: &str,
+    ) -> Result<R, String> {
+        let engine = app.plugins[name].engine.borrow();
+        let mut result = engine.eval::<Dynamic>(script).map_err(|e| e.to_string())?;
+        if result.is::<PendingCall>() {
+            let call = result.cast::<PendingCall>();
+            let started = Instant::now();
+            while !call.is_ready() && started.elapsed() < Duration::from_secs(5) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            result = call.value().map_err(|e| e.to_string())?;
+        }
+        let type_name = result.type_name();
+        result
+            .try_cast::<R>()
+            .ok_or_else(|| format!("Output type incorrect: {type_name}"))
+    }
+
+    fn count(app: &RdxApp, name: &str) -> Option<String> {
+        app.plugins[name].scope().get_value::<String>("count")
+    }
+
+    /// Waits for the calls of `func` the plugin's worker is running in the background
+    fn settle(app: &RdxApp, name: &str, func: &str) {
+        let worker = &app.plugins[name].worker;
+        let started = Instant::now();
+        while worker.is_pending(func) && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!worker.is_pending(func));
+    }
+
+    #[test]
+    fn test_unload_and_enable() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+        let mut app = app();
+        app.load_plugin("counter.wasm", WASM).unwrap();
+        eval::<rhai::INT>(&app, "counter.wasm", "increment_count()").unwrap();
+
+        // a disabled plugin comes back with its scope, but a fresh instance
+        app.disable("counter.wasm").unwrap();
+        assert!(!app.plugins.contains_key("counter.wasm"));
+        app.enable("counter.wasm").unwrap();
+        assert_eq!(count(&app, "counter.wasm").as_deref(), Some("1"));
+        assert_eq!(
+            eval::<rhai::INT>(&app, "counter.wasm", "current()").unwrap(),
+            0
+        );
+
+        // an unloaded one is gone for good
+        assert!(app.unload("counter.wasm"));
+        assert!(matches!(
+            app.enable("counter.wasm"),
+            Err(Error::PluginNotFound(_))
+        ));
+        assert!(app.statuses().is_empty());
+
+        // until it is loaded again
+        app.load_plugin("counter.wasm", WASM).unwrap();
+        assert_eq!(
+            eval::<rhai::INT>(&app, "counter.wasm", "increment_count()").unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_restart_after_trap() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+        let mut app = app();
+        // `emit` traps the guest, as the scope can't take `count`
+        app.config.policies.insert(
+            "counter.wasm".to_string(),
+            PluginPolicy {
+                capabilities: Capabilities::none().grant(Capability::Emit),
+                quotas: Quotas::unlimited().with_scope_entries(0),
+                ..Default::default()
+            },
+        );
+        app.load_plugin("counter.wasm", WASM).unwrap();
+        assert!(eval::<rhai::INT>(&app, "counter.wasm", "increment_count()").is_err());
+
+        app.config.policies.remove("counter.wasm");
+        app.restart("counter.wasm").unwrap();
+        assert_eq!(
+            eval::<rhai::INT>(&app, "counter.wasm", "increment_count()").unwrap(),
+            1
+        );
+        assert_eq!(count(&app, "counter.wasm").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_disabled_plugin_not_called() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+        let mut app = app();
+        app.load_plugin("counter.wasm", WASM).unwrap();
+        eval::<()>(&app, "counter.wasm", r#"after("increment-count", 0)"#).unwrap();
+        app.run_timers();
+        settle(&app, "counter.wasm", "increment-count");
+        assert_eq!(count(&app, "counter.wasm").as_deref(), Some("1"));
+
+        // due again, but not for a plugin that is switched off
+        eval::<()>(
+            &app,
+            "counter.wasm",
+            r#"cancel("increment-count"); after("increment-count", 0)"#,
+        )
+        .unwrap();
+        app.disable("counter.wasm").unwrap();
+        app.run_timers();
+        app.deliver_messages();
+        app.enable("counter.wasm").unwrap();
+        // the timers went with the old instance
+        app.run_timers();
+        assert_eq!(count(&app, "counter.wasm").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_todos_in_scope() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/todo.wasm");
+        let mut app = app();
+        app.config.policies.insert(
+            "todo.wasm".to_string(),
+            PluginPolicy {
+                capabilities: Capabilities::none()
+                    .grant(Capability::Emit)
+                    .grant(Capability::Storage),
+                ..Default::default()
+            },
+        );
+        app.load_plugin("todo.wasm", WASM).unwrap();
+        let todos = |app: &RdxApp| {
+            app.plugins["todo.wasm"]
+                .scope()
+                .get_value::<rhai::Array>("todos")
+                .unwrap()
+                .into_iter()
+                .map(|todo| todo.into_string().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // once the worker ran the call, the list it emitted is in the scope
+        eval::<()>(&app, "todo.wasm", r#"add_todo("milk")"#).unwrap();
+        assert_eq!(todos(&app), ["milk"]);
+
+        // and it's back after a restart, from the plugin's storage
+        app.restart("todo.wasm").unwrap();
+        assert_eq!(todos(&app), ["milk"]);
+    }
+
+    #[test]
+    fn test_builtins_need_no_signature() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+        let mut app = app();
+        app.config.signatures = SignaturePolicy::Require;
+
+        let (name, builtin) = crate::BUILTIN_PLUGINS
+            .iter()
+            .find(|(name, _)| *name == "counter.wasm")
+            .unwrap();
+        app.load_plugin(name, builtin).unwrap();
+        assert!(app.is_builtin(name));
+
+        // the same bytes under another name are just another unsigned plugin
+        assert!(matches!(
+            app.load_plugin("copy.wasm", WASM),
+            Err(Error::Untrusted(_))
+        ));
+        assert!(!app.is_builtin("copy.wasm"));
+    }
+
+    #[test]
+    fn test_manifest_outlives_disable() {
+        const WASM: &[u8] = include_bytes!("../target/wasm32-unknown-unknown/release/counter.wasm");
+        let mut app = app();
+        app.queue_plugin("counter.wasm", WASM);
+        // not known until loaded
+        assert!(app.manifest("counter.wasm").is_none());
+
+        app.load_pending(Duration::ZERO);
+        assert_eq!(app.manifest("counter.wasm").unwrap().name, "Counter");
+
+        // still shown in the catalogue while switched off
+        app.disable("counter.wasm").unwrap();
+        assert!(app.manifest("counter.wasm").is_some());
+
+        app.unload("counter.wasm");
+        assert!(app.manifest("counter.wasm").is_none());
+    }
 }
@@ -0,0 +1,875 @@
+//! Conversion between component model [Value]s and [rhai::Dynamic]s, so plugins can return
+//! any type to scripts and scripts can pass typed arguments back.
+//!
+//! | component model            | Rhai                                           |
+//! |----------------------------|------------------------------------------------|
+//! | `bool`                     | `bool`                                         |
+//! | integers                   | `INT`                                          |
+//! | `f32`, `f64`               | `FLOAT`                                        |
+//! | `char`                     | `char`                                         |
+//! | `string`                   | string                                         |
+//! | `list`, `tuple`            | array                                          |
+//! | `record`                   | object map, by field name                      |
+//! | `variant`                  | `#{ tag: "case", value: payload }`             |
+//! | `enum`                     | the case name                                  |
+//! | `flags`                    | array of the names of the flags that are set   |
+//! | `option`                   | the value, or `()` for `none`                  |
+//! | `result`                   | `#{ ok: value }` or `#{ err: error }`          |
+//! | `own`, `borrow`            | an opaque [ResourceHandle]                     |
+//!
+//! A `result` returned straight from an export is unwrapped instead, see [return_to_dynamic],
+//! so an `err` becomes a script error.
+//!
+//! A resource an export returns is tracked by the plugin's [GuestResources] for as long as the
+//! script holds its handle, see [guest_resource](crate::layer::guest_resource).
+//!
+//! Template handlers take their arguments from scope variables, which inputs set as strings,
+//! so [arguments] also [coerce]s strings into the parameter's type.
+use std::ops::Deref as _;
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use rhai::{Array, Dynamic, Map};
+use wasm_component_layer::{
+    Enum, Flags, List, OptionValue, Record, ResultValue, Tuple, Value, ValueType, Variant,
+};
+
+use crate::layer::guest_resource::{GuestResources, ResourceHandle};
+
+/// The map key holding a variant's case name
+const TAG: &str = "tag";
+/// The map key holding a variant's payload
+const VALUE: &str = "value";
+/// The map key holding an `ok` result
+const OK: &str = "ok";
+/// The map key holding an `err` result
+const ERR: &str = "err";
+
+/// Converts a [Value] into the matching [Dynamic].
+pub fn value_to_dynamic(value: Value, resources: &GuestResources) -> Dynamic {
+    match value {
+        Value::Bool(b) => Dynamic::from_bool(b),
+        Value::S8(n) => Dynamic::from_int(n as rhai::INT),
+        Value::S16(n) => Dynamic::from_int(n as rhai::INT),
+        Value::S32(n) => Dynamic::from_int(n as rhai::INT),
+        Value::S64(n) => Dynamic::from_int(n),
+        Value::U8(n) => Dynamic::from_int(n as rhai::INT),
+        Value::U16(n) => Dynamic::from_int(n as rhai::INT),
+        Value::U32(n) => Dynamic::from_int(n as rhai::INT),
+        // too large for an INT, so kept as it is
+        Value::U64(n) => rhai::INT::try_from(n)
+            .map(Dynamic::from_int)
+            .unwrap_or_else(|_| Dynamic::from(n)),
+        Value::F32(f) => Dynamic::from_float(f as rhai::FLOAT),
+        Value::F64(f) => Dynamic::from_float(f),
+        Value::Char(c) => Dynamic::from_char(c),
+        Value::String(s) => Dynamic::from(s.to_string()),
+        Value::List(list) => Dynamic::from_array(
+            list.into_iter()
+                .map(|value| value_to_dynamic(value, resources))
+                .collect(),
+        ),
+        Value::Tuple(tuple) => Dynamic::from_array(
+            tuple
+                .into_iter()
+                .map(|value| value_to_dynamic(value, resources))
+                .collect(),
+        ),
+        Value::Record(record) => Dynamic::from_map(
+            record
+                .fields()
+                .map(|(name, value)| (name.into(), value_to_dynamic(value, resources)))
+                .collect(),
+        ),
+        Value::Variant(variant) => {
+            let ty = variant.ty();
+            let tag = ty
+                .cases()
+                .get(variant.discriminant())
+                .map(|case| case.name().to_string())
+                .unwrap_or_default();
+            let mut map = Map::new();
+            map.insert(TAG.into(), tag.into());
+            map.insert(
+                VALUE.into(),
+                variant
+                    .value()
+                    .map(|value| value_to_dynamic(value, resources))
+                    .unwrap_or(Dynamic::UNIT),
+            );
+            Dynamic::from_map(map)
+        }
+        Value::Enum(value) => value
+            .ty()
+            .cases()
+            .nth(value.discriminant())
+            .map(|case| Dynamic::from(case.to_string()))
+            .unwrap_or(Dynamic::UNIT),
+        Value::Flags(flags) => Dynamic::from_array(
+            flags
+                .ty()
+                .names()
+                .filter(|name| is_set(&flags, name))
+                .map(|name| Dynamic::from(name.to_string()))
+                .collect(),
+        ),
+        Value::Option(option) => option
+            .deref()
+            .clone()
+            .map(|value| value_to_dynamic(value, resources))
+            .unwrap_or(Dynamic::UNIT),
+        Value::Result(result) => {
+            let (key, payload) = match result.deref().clone() {
+                Ok(payload) => (OK, payload),
+                Err(payload) => (ERR, payload),
+            };
+            let mut map = Map::new();
+            map.insert(
+                key.into(),
+                payload
+                    .map(|value| value_to_dynamic(value, resources))
+                    .unwrap_or(Dynamic::UNIT),
+            );
+            Dynamic::from_map(map)
+        }
+        Value::Own(own) => match resources.track(own) {
+            Ok(handle) => Dynamic::from(handle),
+            Err(e) => {
+                tracing::error!("Can't hand a resource to the script: {}", e);
+                Dynamic::UNIT
+            }
+        },
+        other => {
+            tracing::warn!("Can't pass {:?} to a script", other);
+            Dynamic::UNIT
+        }
+    }
+}
+
+/// Converts what an export returned. A `result` is unwrapped, its `err` payload becoming the
+/// error, and nothing at all becomes `()`.
+pub fn return_to_dynamic(
+    value: Option<Value>,
+    resources: &GuestResources,
+) -> Result<Dynamic, Dynamic> {
+    match value {
+        Some(Value::Result(result)) => match result.deref().clone() {
+            Ok(payload) => Ok(payload
+                .map(|value| value_to_dynamic(value, resources))
+                .unwrap_or(Dynamic::UNIT)),
+            Err(payload) => Err(payload
+                .map(|value| value_to_dynamic(value, resources))
+                .unwrap_or(Dynamic::UNIT)),
+        },
+        Some(value) => Ok(value_to_dynamic(value, resources)),
+        None => Ok(Dynamic::UNIT),
+    }
+}
+
+/// Whether the flag called `name` is set. [Flags::get] only reads it right when no higher
+/// flag is set too, so this clears it in a copy and looks for a difference instead.
+fn is_set(flags: &Flags, name: &str) -> bool {
+    let mut cleared = flags.clone();
+    cleared.set(name, false);
+    cleared != *flags
+}
+
+/// Converts a [Dynamic] into a [Value] of type `ty`, the reverse of [value_to_dynamic].
+///
+/// A resource passed as `own` is lent like for a `borrow`, and its handle added to `moves`. It
+/// is only moved out of the handle by [ResourceHandle::take_all] once everything converted, so
+/// the handle stays usable if the conversion fails.
+pub fn dynamic_to_value(
+    value: Dynamic,
+    ty: &ValueType,
+    moves: &mut Vec<ResourceHandle>,
+) -> Result<Value> {
+    Ok(match ty {
+        ValueType::Bool => Value::Bool(
+            value
+                .as_bool()
+                .map_err(|found| anyhow!("expected a bool, found {found}"))?,
+        ),
+        ValueType::S8 => Value::S8(int(&value)?),
+        ValueType::S16 => Value::S16(int(&value)?),
+        ValueType::S32 => Value::S32(int(&value)?),
+        ValueType::S64 => Value::S64(int(&value)?),
+        ValueType::U8 => Value::U8(int(&value)?),
+        ValueType::U16 => Value::U16(int(&value)?),
+        ValueType::U32 => Value::U32(int(&value)?),
+        ValueType::U64 => match value.clone().try_cast::<u64>() {
+            Some(n) => Value::U64(n),
+            None => Value::U64(int(&value)?),
+        },
+        ValueType::F32 => Value::F32(float(&value)? as f32),
+        ValueType::F64 => Value::F64(float(&value)?),
+        ValueType::Char => Value::Char(
+            value
+                .as_char()
+                .map_err(|found| anyhow!("expected a char, found {found}"))?,
+        ),
+        ValueType::String => Value::String(
+            value
+                .into_immutable_string()
+                .map_err(|found| anyhow!("expected a string, found {found}"))?
+                .as_str()
+                .into(),
+        ),
+        ValueType::List(list_ty) => {
+            let element_ty = list_ty.element_ty();
+            let elements = array(value)?
+                .into_iter()
+                .enumerate()
+                .map(|(i, element)| {
+                    dynamic_to_value(element, &element_ty, moves).with_context(|| format!("[{i}]"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Value::List(List::new(list_ty.clone(), elements)?)
+        }
+        ValueType::Tuple(tuple_ty) => {
+            let array = array(value)?;
+            let fields = tuple_ty.fields();
+            if array.len() != fields.len() {
+                bail!(
+                    "expected a tuple of {} values, found {}",
+                    fields.len(),
+                    array.len()
+                );
+            }
+            let values = array
+                .into_iter()
+                .zip(fields)
+                .enumerate()
+                .map(|(i, (element, ty))| {
+                    dynamic_to_value(element, ty, moves).with_context(|| format!("[{i}]"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Value::Tuple(Tuple::new(tuple_ty.clone(), values)?)
+        }
+        ValueType::Record(record_ty) => {
+            let mut map = map(value)?;
+            let fields = record_ty
+                .fields()
+                .map(|(name, ty)| {
+                    let value = match map.remove(name) {
+                        Some(value) => dynamic_to_value(value, &ty, moves),
+                        // an option can be left out
+                        None if matches!(ty, ValueType::Option(_)) => {
+                            dynamic_to_value(Dynamic::UNIT, &ty, moves)
+                        }
+                        None => Err(anyhow!("missing field")),
+                    };
+                    Ok((name, value.with_context(|| format!(".{name}"))?))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            if let Some(name) = map.keys().next() {
+                bail!("unknown field {name}");
+            }
+            Value::Record(Record::new(record_ty.clone(), fields)?)
+        }
+        ValueType::Variant(variant_ty) => {
+            // a case without a payload can be given by name alone
+            let (tag, payload) = match value.clone().try_cast::<Map>() {
+                Some(mut map) => {
+                    let tag = map
+                        .remove(TAG)
+                        .ok_or_else(|| anyhow!("expected a `{TAG}` naming the case"))?;
+                    (tag.to_string(), map.remove(VALUE))
+                }
+                None => (value.to_string(), None),
+            };
+            let (discriminant, case) = variant_ty
+                .cases()
+                .iter()
+                .enumerate()
+                .find(|(_, case)| case.name() == tag)
+                .ok_or_else(|| anyhow!("no case named {tag}"))?;
+            let payload = match (case.ty(), payload) {
+                (Some(ty), Some(payload)) => Some(dynamic_to_value(payload, &ty, moves)?),
+                (Some(_), None) => bail!("case {tag} needs a `{VALUE}`"),
+                (None, _) => None,
+            };
+            Value::Variant(Variant::new(variant_ty.clone(), discriminant, payload)?)
+        }
+        ValueType::Enum(enum_ty) => {
+            let name = value.to_string();
+            let discriminant = enum_ty
+                .cases()
+                .position(|case| case == name)
+                .ok_or_else(|| anyhow!("no case named {name}"))?;
+            Value::Enum(Enum::new(enum_ty.clone(), discriminant)?)
+        }
+        ValueType::Flags(flags_ty) => {
+            let mut flags = Flags::new(flags_ty.clone());
+            for name in array(value)? {
+                let name = name.to_string();
+                if !flags_ty.names().any(|flag| flag == name) {
+                    bail!("no flag named {name}");
+                }
+                flags.set(&name, true);
+            }
+            Value::Flags(flags)
+        }
+        ValueType::Option(option_ty) => {
+            let value = if value.is_unit() {
+                None
+            } else {
+                Some(dynamic_to_value(value, &option_ty.some_ty(), moves)?)
+            };
+            Value::Option(OptionValue::new(option_ty.clone(), value)?)
+        }
+        ValueType::Result(result_ty) => {
+            let map = value.clone().try_cast::<Map>();
+            let (ok, payload) = match map {
+                Some(mut map) if map.len() == 1 && map.contains_key(ERR) => {
+                    (false, map.remove(ERR))
+                }
+                Some(mut map) if map.len() == 1 && map.contains_key(OK) => (true, map.remove(OK)),
+                // anything else is the ok value
+                _ => (true, Some(value)),
+            };
+            let payload_ty = if ok {
+                result_ty.ok_ty()
+            } else {
+                result_ty.err_ty()
+            };
+            let payload = match (payload_ty, payload) {
+                (Some(ty), Some(payload)) => Some(dynamic_to_value(payload, &ty, moves)?),
+                (Some(_), None) => bail!("expected a value in the result"),
+                (None, _) => None,
+            };
+            let result = if ok { Ok(payload) } else { Err(payload) };
+            Value::Result(ResultValue::new(result_ty.clone(), result)?)
+        }
+        // moved into the guest, once everything else converted
+        ValueType::Own(resource_ty) => {
+            let handle = resource(value, resource_ty)?;
+            let own = handle.own()?;
+            moves.push(handle);
+            Value::Own(own)
+        }
+        // lent by the plugin when it's called, see `LayerPlugin::call`
+        ValueType::Borrow(resource_ty) => {
+            let handle = resource(value, resource_ty)?;
+            Value::Own(handle.own()?)
+        }
+    })
+}
+
+/// Converts a [Dynamic] into a [Value] of type `ty` like [dynamic_to_value], but first parses a
+/// string into the type it stands for: `"42"` for a number, `"true"` for a bool, and JSON for
+/// lists, tuples, records, flags and results. An empty string is `none` for an option.
+pub fn coerce(value: Dynamic, ty: &ValueType, moves: &mut Vec<ResourceHandle>) -> Result<Value> {
+    if !value.is_string() {
+        return dynamic_to_value(value, ty, moves);
+    }
+    let string = value.clone().into_string().unwrap_or_default();
+    let text = string.trim();
+    let parsed = match ty {
+        ValueType::Bool => Dynamic::from_bool(
+            text.parse()
+                .map_err(|_| anyhow!("expected a bool, found {string:?}"))?,
+        ),
+        ValueType::S8
+        | ValueType::S16
+        | ValueType::S32
+        | ValueType::S64
+        | ValueType::U8
+        | ValueType::U16
+        | ValueType::U32 => Dynamic::from_int(
+            text.parse()
+                .map_err(|_| anyhow!("expected an integer, found {string:?}"))?,
+        ),
+        ValueType::U64 => Dynamic::from(
+            text.parse::<u64>()
+                .map_err(|_| anyhow!("expected an integer, found {string:?}"))?,
+        ),
+        ValueType::F32 | ValueType::F64 => Dynamic::from_float(
+            text.parse()
+                .map_err(|_| anyhow!("expected a number, found {string:?}"))?,
+        ),
+        ValueType::Char => {
+            let mut chars = string.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Dynamic::from_char(c),
+                _ => bail!("expected a single char, found {string:?}"),
+            }
+        }
+        ValueType::Option(option_ty) => {
+            let value = if text.is_empty() {
+                None
+            } else {
+                Some(coerce(value, &option_ty.some_ty(), moves)?)
+            };
+            return Ok(Value::Option(OptionValue::new(option_ty.clone(), value)?));
+        }
+        ValueType::List(_)
+        | ValueType::Tuple(_)
+        | ValueType::Record(_)
+        | ValueType::Flags(_)
+        | ValueType::Result(_) => serde_json::from_str(text)
+            .map_err(|e| anyhow!("expected JSON, found {string:?}: {e}"))?,
+        // strings, and enum or variant cases by name
+        _ => value,
+    };
+    dynamic_to_value(parsed, ty, moves)
+}
+
+/// The arguments for a call to `handler`, one per parameter in `params`, each `(name, value)`
+/// [coerce]d into its parameter's type. No resource is moved unless every argument converts.
+pub fn arguments(
+    handler: &str,
+    params: &[ValueType],
+    args: Vec<(String, Dynamic)>,
+) -> Result<Vec<Value>, crate::Error> {
+    if args.len() != params.len() {
+        return Err(crate::Error::WrongArity {
+            handler: handler.to_string(),
+            expected: params.len(),
+            found: args.len(),
+        });
+    }
+    let mut moves = Vec::new();
+    let values = args
+        .into_iter()
+        .zip(params)
+        .map(|((name, value), ty)| {
+            coerce(value, ty, &mut moves).map_err(|reason| crate::Error::BadArgument {
+                handler: handler.to_string(),
+                param: name,
+                reason: format!("{reason:#}"),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    ResourceHandle::take_all(&moves)?;
+    Ok(values)
+}
+
+fn int<I: TryFrom<rhai::INT>>(value: &Dynamic) -> Result<I> {
+    let int = value
+        .as_int()
+        .map_err(|found| anyhow!("expected an integer, found {found}"))?;
+    I::try_from(int).map_err(|_| anyhow!("{int} is out of range"))
+}
+
+fn float(value: &Dynamic) -> Result<rhai::FLOAT> {
+    value
+        .as_float()
+        .or_else(|_| value.as_int().map(|int| int as rhai::FLOAT))
+        .map_err(|found| anyhow!("expected a number, found {found}"))
+}
+
+fn resource(value: Dynamic, ty: &wasm_component_layer::ResourceType) -> Result<ResourceHandle> {
+    let found = value.type_name();
+    let handle = value
+        .try_cast::<ResourceHandle>()
+        .ok_or_else(|| anyhow!("expected a resource, found {found}"))?;
+    if handle.ty().is_some_and(|handle_ty| handle_ty != *ty) {
+        bail!("expected a {ty:?} resource, found {handle:?}");
+    }
+    Ok(handle)
+}
+
+fn array(value: Dynamic) -> Result<Array> {
+    let found = value.type_name();
+    value
+        .try_cast::<Array>()
+        .ok_or_else(|| anyhow!("expected an array, found {found}"))
+}
+
+fn map(value: Dynamic) -> Result<Map> {
+    let found = value.type_name();
+    value
+        .try_cast::<Map>()
+        .ok_or_else(|| anyhow!("expected an object map, found {found}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{runtime_layer, Engine, Store};
+    use wasm_component_layer::{
+        EnumType, FlagsType, ListType, OptionType, RecordType, ResourceOwn, ResourceType,
+        ResultType, TupleType, VariantCase, VariantType,
+    };
+
+    /// Converts `value`, moving the resources it passes as `own`
+    fn to_value(value: Dynamic, ty: &ValueType) -> Result<Value> {
+        let mut moves = Vec::new();
+        let value = dynamic_to_value(value, ty, &mut moves)?;
+        ResourceHandle::take_all(&moves)?;
+        Ok(value)
+    }
+
+    /// Coerces `value`, moving the resources it passes as `own`
+    fn coerced(value: Dynamic, ty: &ValueType) -> Result<Value> {
+        let mut moves = Vec::new();
+        let value = coerce(value, ty, &mut moves)?;
+        ResourceHandle::take_all(&moves)?;
+        Ok(value)
+    }
+
+    fn to_dynamic(value: Value) -> Dynamic {
+        value_to_dynamic(value, &GuestResources::default())
+    }
+
+    /// Converts to a script value and back again.
+    fn roundtrip(value: Value, ty: &ValueType) -> Value {
+        to_value(to_dynamic(value), ty).unwrap()
+    }
+
+    fn todo_type() -> RecordType {
+        RecordType::new(
+            None,
+            [
+                ("title", ValueType::String),
+                ("done", ValueType::Bool),
+                ("due", ValueType::Option(OptionType::new(ValueType::U32))),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn shape_type() -> VariantType {
+        VariantType::new(
+            None,
+            [
+                VariantCase::new("circle", Some(ValueType::F64)),
+                VariantCase::new("point", None),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_primitives() {
+        for (value, ty) in [
+            (Value::Bool(true), ValueType::Bool),
+            (Value::S8(-8), ValueType::S8),
+            (Value::S16(-16), ValueType::S16),
+            (Value::S32(-32), ValueType::S32),
+            (Value::S64(-64), ValueType::S64),
+            (Value::U8(8), ValueType::U8),
+            (Value::U16(16), ValueType::U16),
+            (Value::U32(32), ValueType::U32),
+            (Value::U64(u64::MAX), ValueType::U64),
+            (Value::F64(0.5), ValueType::F64),
+            (Value::Char('x'), ValueType::Char),
+            (Value::String("hello".into()), ValueType::String),
+        ] {
+            assert_eq!(roundtrip(value.clone(), &ty), value);
+        }
+
+        assert_eq!(to_dynamic(Value::S32(-3)).as_int(), Ok(-3));
+        assert!(to_value(Dynamic::from_int(256), &ValueType::U8).is_err());
+        assert!(to_value(Dynamic::from("1"), &ValueType::S32).is_err());
+    }
+
+    #[test]
+    fn test_record() {
+        let record = Value::Record(
+            Record::new(
+                todo_type(),
+                [
+                    ("title", Value::String("write tests".into())),
+                    ("done", Value::Bool(false)),
+                    (
+                        "due",
+                        Value::Option(
+                            OptionValue::new(OptionType::new(ValueType::U32), None).unwrap(),
+                        ),
+                    ),
+                ],
+            )
+            .unwrap(),
+        );
+
+        let map = to_dynamic(record.clone()).cast::<Map>();
+        assert_eq!(map["title"].clone().into_string().unwrap(), "write tests");
+        assert!(map["due"].is_unit());
+
+        let ty = ValueType::Record(todo_type());
+        assert_eq!(roundtrip(record.clone(), &ty), record);
+
+        // the option can be left out
+        let mut map = map;
+        map.remove("due");
+        assert_eq!(to_value(map.clone().into(), &ty).unwrap(), record);
+
+        // but nothing else
+        map.remove("done");
+        let error = to_value(map.into(), &ty).unwrap_err();
+        assert!(format!("{error:#}").contains(".done"), "{error:#}");
+    }
+
+    #[test]
+    fn test_variant_and_enum() {
+        let ty = ValueType::Variant(shape_type());
+        let circle = Value::Variant(Variant::new(shape_type(), 0, Some(Value::F64(2.0))).unwrap());
+        let map = to_dynamic(circle.clone()).cast::<Map>();
+        assert_eq!(map[TAG].clone().into_string().unwrap(), "circle");
+        assert_eq!(roundtrip(circle.clone(), &ty), circle);
+
+        // a case without a payload by name alone
+        let point = Value::Variant(Variant::new(shape_type(), 1, None).unwrap());
+        assert_eq!(to_value(Dynamic::from("point"), &ty).unwrap(), point);
+        assert!(to_value(Dynamic::from("square"), &ty).is_err());
+
+        let level_ty = EnumType::new(None, ["low", "high"]).unwrap();
+        let high = Value::Enum(Enum::new(level_ty.clone(), 1).unwrap());
+        assert_eq!(to_dynamic(high.clone()).into_string().unwrap(), "high");
+        assert_eq!(roundtrip(high.clone(), &ValueType::Enum(level_ty)), high);
+    }
+
+    #[test]
+    fn test_flags() {
+        let ty = FlagsType::new(None, ["read", "write", "exec"]).unwrap();
+        let mut flags = Flags::new(ty.clone());
+        flags.set("read", true);
+        flags.set("exec", true);
+        let flags = Value::Flags(flags);
+
+        let names = to_dynamic(flags.clone()).cast::<Array>();
+        assert_eq!(names.len(), 2);
+        assert_eq!(roundtrip(flags.clone(), &ValueType::Flags(ty)), flags);
+    }
+
+    #[test]
+    fn test_list_tuple_option() {
+        let list_ty = ListType::new(ValueType::S32);
+        let list = Value::List(List::new(list_ty.clone(), [Value::S32(1), Value::S32(2)]).unwrap());
+        assert_eq!(roundtrip(list.clone(), &ValueType::List(list_ty)), list);
+
+        let tuple_ty = TupleType::new(None, [ValueType::String, ValueType::U8]);
+        let tuple = Value::Tuple(
+            Tuple::new(tuple_ty.clone(), [Value::String("a".into()), Value::U8(1)]).unwrap(),
+        );
+        assert_eq!(roundtrip(tuple.clone(), &ValueType::Tuple(tuple_ty)), tuple);
+
+        let option_ty = OptionType::new(ValueType::String);
+        let some = Value::Option(
+            OptionValue::new(option_ty.clone(), Some(Value::String("x".into()))).unwrap(),
+        );
+        assert_eq!(to_dynamic(some.clone()).into_string().unwrap(), "x");
+        assert_eq!(
+            roundtrip(some.clone(), &ValueType::Option(option_ty.clone())),
+            some
+        );
+        assert!(to_dynamic(Value::Option(OptionValue::new(option_ty, None).unwrap())).is_unit());
+    }
+
+    #[test]
+    fn test_result() {
+        let ty = ResultType::new(Some(ValueType::U32), Some(ValueType::String));
+        let ok = Value::Result(ResultValue::new(ty.clone(), Ok(Some(Value::U32(1)))).unwrap());
+        let err = Value::Result(
+            ResultValue::new(ty.clone(), Err(Some(Value::String("nope".into())))).unwrap(),
+        );
+
+        assert!(to_dynamic(err.clone()).cast::<Map>().contains_key(ERR));
+        assert_eq!(roundtrip(ok.clone(), &ValueType::Result(ty.clone())), ok);
+        assert_eq!(roundtrip(err.clone(), &ValueType::Result(ty.clone())), err);
+        // a bare value is ok
+        assert_eq!(
+            to_value(Dynamic::from_int(1), &ValueType::Result(ty)).unwrap(),
+            ok
+        );
+
+        // returned from an export, the result is unwrapped
+        assert_eq!(
+            return_to_dynamic(Some(ok), &GuestResources::default())
+                .unwrap()
+                .as_int(),
+            Ok(1)
+        );
+        assert_eq!(
+            return_to_dynamic(Some(err), &GuestResources::default())
+                .unwrap_err()
+                .into_string()
+                .unwrap(),
+            "nope"
+        );
+        assert!(return_to_dynamic(None, &GuestResources::default())
+            .unwrap()
+            .is_unit());
+    }
+
+    #[test]
+    fn test_resource() {
+        let mut store = Store::new(&Engine::new(runtime_layer::Engine::default()), ());
+        let ty = ResourceType::new::<u32>(None);
+        let resources = GuestResources::default();
+        let own = ResourceOwn::new(&mut store, 7u32, ty.clone()).unwrap();
+
+        let handle = value_to_dynamic(Value::Own(own), &resources);
+        assert!(handle.is::<ResourceHandle>());
+
+        // lent for a borrow, and still held by the script afterwards
+        let lent = to_value(handle.clone(), &ValueType::Borrow(ty.clone())).unwrap();
+        assert!(matches!(lent, Value::Own(_)));
+
+        // moved into the guest for an own
+        let moved = to_value(handle.clone(), &ValueType::Own(ty.clone())).unwrap();
+        assert!(matches!(moved, Value::Own(_)));
+        assert!(to_value(handle, &ValueType::Borrow(ty.clone())).is_err());
+
+        // only handles of the same type are accepted
+        let other = ResourceOwn::new(&mut store, 1u64, ResourceType::new::<u64>(None)).unwrap();
+        let other = value_to_dynamic(Value::Own(other), &resources);
+        assert!(to_value(other, &ValueType::Own(ty.clone())).is_err());
+        assert!(to_value(Dynamic::from_int(1), &ValueType::Own(ty)).is_err());
+    }
+
+    #[test]
+    fn test_resource_kept_on_failure() {
+        let mut store = Store::new(&Engine::new(runtime_layer::Engine::default()), ());
+        let ty = ResourceType::new::<u32>(None);
+        let resources = GuestResources::default();
+        let own = ResourceOwn::new(&mut store, 7u32, ty.clone()).unwrap();
+        let handle = value_to_dynamic(Value::Own(own), &resources);
+
+        // a later field fails, so the resource isn't moved
+        let pair = ValueType::Tuple(TupleType::new(
+            None,
+            [ValueType::Own(ty.clone()), ValueType::U8],
+        ));
+        let value = Dynamic::from_array(vec![handle.clone(), Dynamic::from("x")]);
+        assert!(to_value(value, &pair).is_err());
+
+        // nor when a later argument fails
+        let params = [ValueType::Own(ty.clone()), ValueType::U8];
+        let args = vec![
+            ("#1".to_string(), handle.clone()),
+            ("#2".to_string(), "x".into()),
+        ];
+        assert!(arguments("f", &params, args).is_err());
+
+        // and moving it twice in one call doesn't either
+        let list = ValueType::List(ListType::new(ValueType::Own(ty.clone())));
+        let twice = Dynamic::from_array(vec![handle.clone(), handle.clone()]);
+        assert!(to_value(twice, &list).is_err());
+
+        assert!(!handle.clone().cast::<ResourceHandle>().is_moved());
+        assert!(to_value(handle, &ValueType::Own(ty)).is_ok());
+    }
+
+    #[test]
+    fn test_coerce_strings() {
+        assert_eq!(
+            coerced(Dynamic::from("42"), &ValueType::U32).unwrap(),
+            Value::U32(42)
+        );
+        assert_eq!(
+            coerced(Dynamic::from(" -7 "), &ValueType::S8).unwrap(),
+            Value::S8(-7)
+        );
+        assert_eq!(
+            coerced(Dynamic::from("18446744073709551615"), &ValueType::U64).unwrap(),
+            Value::U64(u64::MAX)
+        );
+        assert_eq!(
+            coerced(Dynamic::from("0.5"), &ValueType::F64).unwrap(),
+            Value::F64(0.5)
+        );
+        assert_eq!(
+            coerced(Dynamic::from("true"), &ValueType::Bool).unwrap(),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            coerced(Dynamic::from("x"), &ValueType::Char).unwrap(),
+            Value::Char('x')
+        );
+        // strings stay strings, even when they look like numbers
+        assert_eq!(
+            coerced(Dynamic::from("42"), &ValueType::String).unwrap(),
+            Value::String("42".into())
+        );
+        // and values that aren't strings are converted as they are
+        assert_eq!(
+            coerced(Dynamic::from_int(3), &ValueType::U8).unwrap(),
+            Value::U8(3)
+        );
+
+        assert!(coerced(Dynamic::from("forty-two"), &ValueType::U32).is_err());
+        assert!(coerced(Dynamic::from("300"), &ValueType::U8).is_err());
+        assert!(coerced(Dynamic::from("yes"), &ValueType::Bool).is_err());
+        assert!(coerced(Dynamic::from("xy"), &ValueType::Char).is_err());
+    }
+
+    #[test]
+    fn test_coerce_json() {
+        let list_ty = ListType::new(ValueType::U32);
+        assert_eq!(
+            coerced(Dynamic::from("[1, 2]"), &ValueType::List(list_ty.clone())).unwrap(),
+            Value::List(List::new(list_ty.clone(), [Value::U32(1), Value::U32(2)]).unwrap())
+        );
+        let error = coerced(Dynamic::from("[1, -2]"), &ValueType::List(list_ty)).unwrap_err();
+        assert!(format!("{error:#}").contains("[1]"), "{error:#}");
+
+        let record = coerced(
+            Dynamic::from(r#"{"title": "write tests", "done": true}"#),
+            &ValueType::Record(todo_type()),
+        )
+        .unwrap();
+        let Value::Record(record) = record else {
+            panic!("expected a record, found {record:?}");
+        };
+        assert_eq!(record.field("done"), Some(Value::Bool(true)));
+
+        assert!(coerced(Dynamic::from("{"), &ValueType::Record(todo_type())).is_err());
+    }
+
+    #[test]
+    fn test_coerce_option() {
+        let option_ty = OptionType::new(ValueType::U32);
+        let ty = ValueType::Option(option_ty.clone());
+        assert_eq!(
+            coerced(Dynamic::from(""), &ty).unwrap(),
+            Value::Option(OptionValue::new(option_ty.clone(), None).unwrap())
+        );
+        assert_eq!(
+            coerced(Dynamic::from("5"), &ty).unwrap(),
+            Value::Option(OptionValue::new(option_ty, Some(Value::U32(5))).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_arguments() {
+        let params = [ValueType::String, ValueType::U32];
+
+        let values = arguments(
+            "set-limit",
+            &params,
+            vec![
+                ("name".to_string(), Dynamic::from("cpu")),
+                ("limit".to_string(), Dynamic::from("80")),
+            ],
+        )
+        .unwrap();
+        assert_eq!(values, [Value::String("cpu".into()), Value::U32(80)]);
+
+        let error = arguments(
+            "set-limit",
+            &params,
+            vec![("name".to_string(), Dynamic::from("cpu"))],
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "set-limit takes 2 arguments, but was given 1"
+        );
+
+        let error = arguments(
+            "set-limit",
+            &params,
+            vec![
+                ("name".to_string(), Dynamic::from("cpu")),
+                ("limit".to_string(), Dynamic::from("lots")),
+            ],
+        )
+        .unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("set-limit"), "{message}");
+        assert!(message.contains("limit"), "{message}");
+        assert!(message.contains("\"lots\""), "{message}");
+    }
+}
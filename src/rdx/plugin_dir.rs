@@ -0,0 +1,202 @@
+//! Plugins loaded from a directory at runtime.
+//!
+//! [PluginDir] reads every `.wasm` component in the directory and then watches it, so a plugin
+//! author can drop in a freshly built component and see it reloaded without rebuilding the host.
+//!
+//! A component signed in a sidecar file, `counter.wasm.sig` next to `counter.wasm`, is read
+//! with the signature attached, see [crate::layer::signature].
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+
+use notify::event::ModifyKind;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+
+use crate::layer::signature::{self, SIDECAR_EXTENSION};
+use crate::Error;
+
+/// A watched directory of `.wasm` plugin components.
+pub struct PluginDir {
+    dir: PathBuf,
+    /// Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+}
+
+impl PluginDir {
+    /// Starts watching `dir`, creating it if needed.
+    ///
+    /// `on_change` is called from the watcher's thread after every change, so the UI can be
+    /// woken up to pick it up with [PluginDir::changed].
+    pub fn watch(
+        dir: impl Into<PathBuf>,
+        on_change: impl Fn() + Send + 'static,
+    ) -> Result<Self, Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let (tx, events) = channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            if tx.send(event).is_ok() {
+                on_change();
+            }
+        })
+        .map_err(watch_error)?;
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .map_err(watch_error)?;
+
+        Ok(Self {
+            dir,
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Reads every plugin in the directory, as `(name, bytes)` sorted by name.
+    pub fn plugins(&self) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let mut plugins = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if let Some(name) = plugin_name(&path) {
+                plugins.push((name, read(&path)?));
+            }
+        }
+        plugins.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(plugins)
+    }
+
+    /// The plugins created or written to since the last call, as `(name, bytes)`.
+    pub fn changed(&self) -> Vec<(String, Vec<u8>)> {
+        let mut paths = BTreeSet::new();
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if is_write(&event.kind) => {
+                    paths.extend(event.paths.into_iter().map(signed_component))
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Error watching {}: {}", self.dir.display(), e),
+            }
+        }
+
+        paths
+            .into_iter()
+            .filter_map(|path| {
+                let name = plugin_name(&path)?;
+                match read(&path) {
+                    Ok(bytes) => Some((name, bytes)),
+                    Err(e) => {
+                        // removed again before we got to it
+                        tracing::warn!("Failed to read {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// The plugin name of a `.wasm` file, which is its file name, like the builtin plugins.
+fn plugin_name(path: &Path) -> Option<String> {
+    if path.extension()? != "wasm" || !path.is_file() {
+        return None;
+    }
+    Some(path.file_name()?.to_string_lossy().into_owned())
+}
+
+/// Reads the component at `path`, attaching its sidecar signature if it has one.
+fn read(path: &Path) -> std::io::Result<Vec<u8>> {
+    let bytes = std::fs::read(path)?;
+    match std::fs::read(sidecar_path(path)) {
+        Ok(sidecar) => Ok(signature::attach(&bytes, &sidecar)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(bytes),
+        Err(e) => Err(e),
+    }
+}
+
+/// `counter.wasm.sig` for `counter.wasm`
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(SIDECAR_EXTENSION);
+    sidecar.into()
+}
+
+/// The component a changed sidecar signs, as a new signature reloads it. Other paths are
+/// returned as they are.
+fn signed_component(path: PathBuf) -> PathBuf {
+    match path.extension() {
+        Some(extension) if extension == SIDECAR_EXTENSION => path.with_extension(""),
+        _ => path,
+    }
+}
+
+/// Whether the event may have changed a file's contents.
+fn is_write(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        _ => false,
+    }
+}
+
+fn watch_error(e: notify::Error) -> Error {
+    Error::Io(std::io::Error::other(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_loads_and_watches() {
+        let dir = std::env::temp_dir().join(format!("rdx-plugin-dir-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.wasm"), b"first").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"not a plugin").unwrap();
+
+        let plugin_dir = PluginDir::watch(&dir, || {}).unwrap();
+        assert_eq!(
+            plugin_dir.plugins().unwrap(),
+            vec![("a.wasm".to_string(), b"first".to_vec())]
+        );
+
+        std::fs::write(dir.join("a.wasm"), b"second").unwrap();
+
+        let started = Instant::now();
+        let changed = loop {
+            let changed = plugin_dir.changed();
+            if !changed.is_empty() || started.elapsed() > Duration::from_secs(5) {
+                break changed;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        assert_eq!(changed, vec![("a.wasm".to_string(), b"second".to_vec())]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sidecar_signature() {
+        let dir = std::env::temp_dir().join(format!("rdx-plugin-sig-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.wasm"), b"component").unwrap();
+        std::fs::write(dir.join("a.wasm.sig"), b"signature").unwrap();
+
+        let plugin_dir = PluginDir::watch(&dir, || {}).unwrap();
+        assert_eq!(
+            plugin_dir.plugins().unwrap(),
+            vec![(
+                "a.wasm".to_string(),
+                signature::attach(b"component", b"signature")
+            )]
+        );
+        assert_eq!(signed_component(dir.join("a.wasm.sig")), dir.join("a.wasm"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
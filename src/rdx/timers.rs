@@ -0,0 +1,184 @@
+//! Host-driven timers that call plugin exports.
+//!
+//! Instead of blocking inside the guest until it's time to do something, a plugin's Rhai
+//! script asks for one of its exports to be called later:
+//!
+//! ```rhai
+//! every("ticker", 1000);  // call `ticker` every second
+//! after("ring", 5000);    // call `ring` once, five seconds from now
+//! cancel("ticker");
+//! ```
+//!
+//! The host calls the exports that are due from its own frame loop and asks egui to repaint in
+//! time for the next one, so this works the same on every target.
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::layer::{Duration, Instant};
+
+/// When an export is to be called next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timer {
+    next: Instant,
+    /// Repeats with this period, or fires once if `None`
+    period: Option<Duration>,
+}
+
+/// The timers of one plugin, by the export they call.
+#[derive(Debug, Default)]
+pub struct Timers {
+    timers: BTreeMap<String, Timer>,
+    /// One-off exports already called, so the script asking again doesn't call them again
+    fired: BTreeSet<String>,
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `export` every `period`, starting one `period` from `now`.
+    ///
+    /// Scripts run every frame, so asking again for the same period keeps the timer as it is.
+    pub fn every(&mut self, export: &str, period: Duration, now: Instant) {
+        // a zero period would be due on every frame, and keep the UI spinning
+        let period = period.max(Duration::from_millis(1));
+        if let Some(timer) = self.timers.get(export) {
+            if timer.period == Some(period) {
+                return;
+            }
+        }
+        self.timers.insert(
+            export.to_string(),
+            Timer {
+                next: now + period,
+                period: Some(period),
+            },
+        );
+    }
+
+    /// Calls `export` once, `delay` after `now`.
+    ///
+    /// Asking again while it is still pending keeps the original deadline, and asking again
+    /// after it was called does nothing until it is [cancel](Timers::cancel)led.
+    pub fn after(&mut self, export: &str, delay: Duration, now: Instant) {
+        if self.fired.contains(export) {
+            return;
+        }
+        self.timers.entry(export.to_string()).or_insert(Timer {
+            next: now + delay,
+            period: None,
+        });
+    }
+
+    /// Stops calling `export`. Returns whether it had a timer.
+    pub fn cancel(&mut self, export: &str) -> bool {
+        self.fired.remove(export);
+        self.timers.remove(export).is_some()
+    }
+
+    /// Whether no timers are set.
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Takes the exports due at `now`, in order of name.
+    ///
+    /// Repeating timers move on to their next period. One that fell behind by several periods,
+    /// say while the window was hidden, is called once rather than once per missed period.
+    pub fn due(&mut self, now: Instant) -> Vec<String> {
+        let due = self
+            .timers
+            .iter()
+            .filter(|(_, timer)| timer.next <= now)
+            .map(|(export, _)| export.clone())
+            .collect::<Vec<_>>();
+
+        for export in &due {
+            let Some(timer) = self.timers.get_mut(export) else {
+                continue;
+            };
+            match timer.period {
+                Some(period) => {
+                    timer.next += period;
+                    if timer.next <= now {
+                        timer.next = now + period;
+                    }
+                }
+                None => {
+                    self.timers.remove(export);
+                    self.fired.insert(export.clone());
+                }
+            }
+        }
+
+        due
+    }
+
+    /// When the next timer is due, if any.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.values().map(|timer| timer.next).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn test_every() {
+        let start = Instant::now();
+        let mut timers = Timers::new();
+        timers.every("ticker", SECOND, start);
+
+        assert!(timers.due(start).is_empty());
+        assert_eq!(timers.next_deadline(), Some(start + SECOND));
+
+        assert_eq!(timers.due(start + SECOND), vec!["ticker".to_string()]);
+        assert_eq!(timers.next_deadline(), Some(start + 2 * SECOND));
+
+        // asking again, as a script does every frame, keeps the deadline
+        timers.every("ticker", SECOND, start + SECOND + SECOND / 2);
+        assert_eq!(timers.next_deadline(), Some(start + 2 * SECOND));
+
+        // after falling far behind, it is called once and catches up
+        assert_eq!(timers.due(start + 10 * SECOND).len(), 1);
+        assert_eq!(timers.next_deadline(), Some(start + 11 * SECOND));
+    }
+
+    #[test]
+    fn test_after() {
+        let start = Instant::now();
+        let mut timers = Timers::new();
+        timers.after("ring", SECOND, start);
+        timers.after("ring", SECOND, start + SECOND / 2);
+
+        assert!(timers.due(start).is_empty());
+        assert_eq!(timers.due(start + SECOND), vec!["ring".to_string()]);
+        assert!(timers.is_empty());
+        assert_eq!(timers.next_deadline(), None);
+
+        // the script asking again on the next frame doesn't ring again
+        timers.after("ring", SECOND, start + SECOND);
+        assert!(timers.is_empty());
+
+        // until it is cancelled
+        timers.cancel("ring");
+        timers.after("ring", SECOND, start + SECOND);
+        assert_eq!(timers.next_deadline(), Some(start + 2 * SECOND));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let start = Instant::now();
+        let mut timers = Timers::new();
+        timers.every("ticker", SECOND, start);
+        timers.after("ring", SECOND, start);
+
+        assert!(timers.cancel("ticker"));
+        assert!(!timers.cancel("ticker"));
+        assert_eq!(timers.due(start + SECOND), vec!["ring".to_string()]);
+    }
+}
@@ -0,0 +1,440 @@
+//! Runs a plugin's event handlers, in the background or on the UI thread.
+//!
+//! By default a button or input handler is queued to the plugin's own worker, and the UI keeps
+//! drawing while it runs. Natively the worker is a thread, in the browser the calls are
+//! [spawn](crate::futures::spawn)ed to run after the frame. A plugin whose
+//! [PluginPolicy](super::PluginPolicy) has `background` unset instead has its handlers called
+//! right away, while egui builds the frame, so a slow export stalls rendering.
+//!
+//! A template gives a handler's arguments as the names of scope variables. The worker
+//! [coerce](super::convert::coerce)s their values into the types of the export's parameters
+//! before calling it, see [Worker::arguments].
+//!
+//! While a handler is queued or running it is [pending](Worker::is_pending), so the UI can show
+//! it. When it finishes, the value it returned, if any, is set in the scope under the handler's
+//! name and the UI is repainted.
+//!
+//! Every call into the plugin goes through its worker, so a timer or a script's call to an
+//! export waits its turn behind the queued handlers instead of racing them for the plugin.
+//! The script itself doesn't wait for its call: it gets a [PendingCall] back right away, which
+//! the worker fills in once the export returns, see [Worker::call_pending].
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use rhai::Dynamic;
+use wasm_component_layer::{Value, ValueType};
+
+use super::convert::{self, return_to_dynamic, value_to_dynamic};
+use crate::layer::guest_resource::GuestResources;
+use crate::layer::{Inner, Instantiator};
+use crate::Error;
+
+/// How many scripts' calls with different arguments are remembered before the ones that
+/// returned are forgotten
+const MAX_CALLS: usize = 256;
+
+/// Calls the handlers of one plugin.
+pub struct Worker<T: Inner + Send> {
+    plugin: Arc<Mutex<dyn Instantiator<T>>>,
+    /// Shares its scope with the plugin's store, so the scope can be used without locking
+    /// the plugin while a handler runs
+    state: T,
+    /// The parameter types of each export, so they can be looked up without locking the
+    /// plugin
+    params: HashMap<String, Vec<ValueType>>,
+    /// How many calls of each handler are queued or running
+    pending: Arc<Mutex<HashMap<String, usize>>>,
+    /// The scripts' calls, by export and arguments
+    calls: Mutex<HashMap<String, PendingCall>>,
+    mode: Mode,
+}
+
+enum Mode {
+    /// Call handlers right away
+    Immediate,
+    /// Queue the calls to the worker thread
+    #[cfg(not(target_arch = "wasm32"))]
+    Thread(std::sync::mpsc::Sender<Job>),
+    /// Spawn the calls onto the browser's event loop
+    #[cfg(target_arch = "wasm32")]
+    Spawn,
+}
+
+/// A handler to call, with its arguments.
+struct Job {
+    func: String,
+    arguments: Vec<Value>,
+    /// Where to put the result instead of the scope, for a script's call
+    call: Option<PendingCall>,
+}
+
+/// What a script's call to an export returned, once it has. The worker fills it in and
+/// repaints, so a script rendering every frame picks the value up in the next one.
+///
+/// In Rhai, `ready` is whether the export returned yet, and `value` is what it returned, `()`
+/// until then. An `err` result, or a call that failed, is a script error when `value` is read.
+#[derive(Clone, Default)]
+pub struct PendingCall(Arc<Mutex<CallState>>);
+
+#[derive(Default)]
+struct CallState {
+    /// Whether a call is queued or running
+    running: bool,
+    /// What the latest call returned, converted for the script
+    result: Option<Result<Dynamic, Dynamic>>,
+}
+
+impl PendingCall {
+    /// Whether the export returned yet.
+    pub fn is_ready(&self) -> bool {
+        self.0.lock().unwrap().result.is_some()
+    }
+
+    /// What the export returned, `()` until it has. Its `err` payload, or why the call failed,
+    /// is the error.
+    pub fn value(&self) -> Result<Dynamic, Dynamic> {
+        self.0
+            .lock()
+            .unwrap()
+            .result
+            .clone()
+            .unwrap_or(Ok(Dynamic::UNIT))
+    }
+
+    fn is_running(&self) -> bool {
+        self.0.lock().unwrap().running
+    }
+
+    /// Marks a call as started, unless one already is.
+    fn start(&self) -> bool {
+        !std::mem::replace(&mut self.0.lock().unwrap().running, true)
+    }
+
+    fn fill(&self, func: &str, result: Result<Option<Value>, Error>, resources: &GuestResources) {
+        let result = match result {
+            Ok(value) => return_to_dynamic(value, resources),
+            Err(e) => Err(format!("Calling {func} failed: {e}").into()),
+        };
+        let mut state = self.0.lock().unwrap();
+        state.running = false;
+        state.result = Some(result);
+    }
+}
+
+impl<T: Inner + Clone + Send + Sync + 'static> Worker<T> {
+    /// Calls the plugin's handlers right away.
+    pub fn immediate(plugin: Arc<Mutex<dyn Instantiator<T>>>) -> Self {
+        let (state, exports) = {
+            let plugin = plugin.lock().unwrap();
+            (plugin.store().data().clone(), plugin.exports())
+        };
+        let params = match exports {
+            Ok(exports) => exports
+                .into_iter()
+                .map(|(name, ty)| (name, ty.params().to_vec()))
+                .collect(),
+            Err(e) => {
+                tracing::warn!("Failed to list the plugin's exports: {:?}", e);
+                HashMap::new()
+            }
+        };
+        Self {
+            plugin,
+            state,
+            params,
+            pending: Default::default(),
+            calls: Default::default(),
+            mode: Mode::Immediate,
+        }
+    }
+
+    /// Calls the plugin's handlers in the background.
+    ///
+    /// Falls back to calling them right away if no worker thread can be started.
+    pub fn background(name: &str, plugin: Arc<Mutex<dyn Instantiator<T>>>) -> Self {
+        let mut worker = Self::immediate(plugin);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (jobs, queue) = std::sync::mpsc::channel::<Job>();
+            let plugin = worker.plugin.clone();
+            let mut state = worker.state.clone();
+            let pending = worker.pending.clone();
+            // the thread ends once the worker, and with it the sender, is dropped
+            let spawned = std::thread::Builder::new()
+                .name(format!("plugin {name}"))
+                .spawn(move || {
+                    for job in queue {
+                        job.run(&plugin, &mut state, &pending);
+                    }
+                });
+            match spawned {
+                Ok(_) => worker.mode = Mode::Thread(jobs),
+                Err(e) => tracing::error!("Failed to start a worker for {}: {}", name, e),
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            tracing::debug!("Calling the handlers of {} in the background", name);
+            worker.mode = Mode::Spawn;
+        }
+
+        worker
+    }
+
+    /// The plugin this worker calls.
+    pub fn plugin(&self) -> &Arc<Mutex<dyn Instantiator<T>>> {
+        &self.plugin
+    }
+
+    /// The plugin's state, whose scope is shared with the plugin.
+    pub fn state(&self) -> T {
+        self.state.clone()
+    }
+
+    /// Whether a call to `func` is queued or running.
+    pub fn is_pending(&self, func: &str) -> bool {
+        self.pending.lock().unwrap().contains_key(func)
+    }
+
+    /// Converts the `(name, value)` arguments for the `func` handler into the types of its
+    /// export's parameters.
+    pub fn arguments(&self, func: &str, args: Vec<(String, Dynamic)>) -> Result<Vec<Value>, Error> {
+        let params = self
+            .params
+            .get(func)
+            .ok_or_else(|| Error::FuncNotFound(func.to_string()))?;
+        convert::arguments(func, params, args)
+    }
+
+    /// Calls the plugin's `func` export, right away or in the background.
+    pub fn call(&self, func: &str, arguments: Vec<Value>) {
+        let job = Job {
+            func: func.to_string(),
+            arguments,
+            call: None,
+        };
+        match &self.mode {
+            Mode::Immediate => match self.plugin.lock().unwrap().call(&job.func, &job.arguments) {
+                Ok(res) => tracing::info!("{} response {:?}", job.func, res),
+                Err(e) => tracing::error!("{} Error {:?}", job.func, e),
+            },
+            _ => self.send(job),
+        }
+    }
+
+    /// Calls the plugin's `func` export for a script, without waiting for it to return.
+    ///
+    /// Calls with the same arguments share one [PendingCall], holding what the latest of them
+    /// returned. The export is only called again once the previous call returned, so a script
+    /// calling it every frame keeps one call queued instead of one per frame. Right away, the
+    /// call has returned by the time this does.
+    pub fn call_pending(&self, func: &str, arguments: Vec<Value>) -> PendingCall {
+        let key = format!("{func}{arguments:?}");
+        let call = {
+            let mut calls = self.calls.lock().unwrap();
+            if calls.len() >= MAX_CALLS && !calls.contains_key(&key) {
+                calls.retain(|_, call| call.is_running());
+            }
+            calls.entry(key).or_default().clone()
+        };
+        if call.start() {
+            self.send(Job {
+                func: func.to_string(),
+                arguments,
+                call: Some(call.clone()),
+            });
+        }
+        call
+    }
+
+    /// Runs the job right away, or hands it to the background.
+    fn send(&self, job: Job) {
+        match &self.mode {
+            Mode::Immediate => job.run(&self.plugin, &mut self.state(), &self.pending),
+            #[cfg(not(target_arch = "wasm32"))]
+            Mode::Thread(jobs) => {
+                job.queue(&self.pending);
+                if let Err(std::sync::mpsc::SendError(job)) = jobs.send(job) {
+                    tracing::error!("The worker is gone, dropping the call to {}", job.func);
+                    job.finish(&self.pending);
+                    if let Some(call) = &job.call {
+                        let gone = Err(anyhow::anyhow!("The worker is gone").into());
+                        call.fill(&job.func, gone, &GuestResources::default());
+                    }
+                }
+            }
+            #[cfg(target_arch = "wasm32")]
+            Mode::Spawn => {
+                job.queue(&self.pending);
+                let plugin = self.plugin.clone();
+                let mut state = self.state.clone();
+                let pending = self.pending.clone();
+                crate::futures::spawn(async move {
+                    job.run(&plugin, &mut state, &pending);
+                });
+            }
+        }
+    }
+}
+
+impl Job {
+    /// Counts a handler as pending. A script's call isn't a handler, so it isn't counted.
+    fn queue(&self, pending: &Mutex<HashMap<String, usize>>) {
+        if self.call.is_some() {
+            return;
+        }
+        *pending
+            .lock()
+            .unwrap()
+            .entry(self.func.clone())
+            .or_default() += 1;
+    }
+
+    fn finish(&self, pending: &Mutex<HashMap<String, usize>>) {
+        if self.call.is_some() {
+            return;
+        }
+        let mut pending = pending.lock().unwrap();
+        if let Some(count) = pending.get_mut(&self.func) {
+            *count -= 1;
+            if *count == 0 {
+                pending.remove(&self.func);
+            }
+        }
+    }
+
+    /// Calls the handler, sets its result in the scope and repaints. A script's call has its
+    /// [PendingCall] filled in instead.
+    fn run<T: Inner + Send + Sync>(
+        self,
+        plugin: &Mutex<dyn Instantiator<T>>,
+        state: &mut T,
+        pending: &Mutex<HashMap<String, usize>>,
+    ) {
+        let (result, resources) = {
+            let mut plugin = plugin.lock().unwrap();
+            (plugin.call(&self.func, &self.arguments), plugin.resources())
+        };
+        self.finish(pending);
+        if let Some(call) = &self.call {
+            call.fill(&self.func, result, &resources);
+            state.request_repaint();
+            return;
+        }
+        match result {
+            Ok(Some(value)) => {
+                tracing::info!("{} response {:?}", self.func, value);
+                state.update(&self.func, value_to_dynamic(value, &resources));
+            }
+            Ok(None) => {}
+            Err(e) => tracing::error!("{} Error {:?}", self.func, e),
+        }
+        // the handler is no longer pending
+        state.request_repaint();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::capability::{Capabilities, Capability};
+    use crate::layer::{Duration, Instant, LayerPlugin};
+    use crate::rdx::PluginPolicy;
+    use crate::State;
+
+    /// The counter, granted the `emit` it needs
+    fn counter() -> LayerPlugin<State> {
+        const WASM: &[u8] =
+            include_bytes!("../../target/wasm32-unknown-unknown/release/counter.wasm");
+        let policy = PluginPolicy {
+            capabilities: Capabilities::none().grant(Capability::Emit),
+            ..Default::default()
+        };
+        LayerPlugin::new(WASM, State::new(None).with_policy(policy)).unwrap()
+    }
+
+    #[test]
+    fn test_background_call() {
+        let plugin = counter();
+        let worker = Worker::background("counter", Arc::new(Mutex::new(plugin)));
+
+        worker.call("increment-count", vec![]);
+
+        let started = Instant::now();
+        while worker.is_pending("increment-count") && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!worker.is_pending("increment-count"));
+
+        // the event it emitted and the value it returned are both in the scope
+        let scope = worker.state().into_scope();
+        assert_eq!(scope.get_value::<String>("count"), Some("1".to_string()));
+        assert!(scope.contains("increment-count"));
+    }
+
+    /// Waits for the worker to fill `call` in
+    fn settle(call: &PendingCall) {
+        let started = Instant::now();
+        while !call.is_ready() && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_pending_call() {
+        let plugin = counter();
+        let worker = Worker::background("counter", Arc::new(Mutex::new(plugin)));
+
+        // held so nothing runs until both calls are queued
+        let held = worker.plugin().lock().unwrap();
+        // queued behind the handler, so it sees its increment
+        worker.call("increment-count", vec![]);
+        let call = worker.call_pending("increment-count", vec![]);
+        assert!(!call.is_ready());
+        // shares the call that is still queued rather than making another
+        let again = worker.call_pending("increment-count", vec![]);
+        drop(held);
+        settle(&call);
+        assert_eq!(call.value().unwrap().as_int().unwrap(), 2);
+        assert_eq!(again.value().unwrap().as_int().unwrap(), 2);
+        assert!(!worker.is_pending("increment-count"));
+
+        let current = worker.call_pending("current", vec![]);
+        settle(&current);
+        assert_eq!(current.value().unwrap().as_int().unwrap(), 2);
+
+        let missing = worker.call_pending("no-such-export", vec![]);
+        settle(&missing);
+        assert!(missing.value().is_err());
+    }
+
+    #[test]
+    fn test_arguments() {
+        let plugin = counter();
+        let worker = Worker::immediate(Arc::new(Mutex::new(plugin)));
+
+        assert!(worker
+            .arguments("increment-count", vec![])
+            .unwrap()
+            .is_empty());
+
+        let error = worker
+            .arguments("increment-count", vec![("count".to_string(), "1".into())])
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::WrongArity {
+                expected: 0,
+                found: 1,
+                ..
+            }
+        ));
+
+        assert!(matches!(
+            worker.arguments("no-such-export", vec![]),
+            Err(Error::FuncNotFound(_))
+        ));
+    }
+}